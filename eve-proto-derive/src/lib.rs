@@ -0,0 +1,368 @@
+//! `#[derive(ToEve, FromEve)]` for types sent over the EVE marshal format.
+//!
+//! Structs go over the wire as a tuple of their fields by default.
+//! `#[eve(layout = "dict")]` sends a dict keyed by field name instead and
+//! `#[eve(class = "util.KeyVal")]` an object of that class whose args are
+//! that dict. Fields take `#[eve(rename = "name")]` and, outside of tuples,
+//! `#[eve(default)]` for fields that may be left out.
+//!
+//! Enum variants without fields are sent as their name, others as a dict
+//! from their name to their fields laid out like a struct's. Variants can
+//! be renamed as well.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr, Member, Result};
+
+#[proc_macro_derive(ToEve, attributes(eve))]
+pub fn derive_to_eve(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_to_eve(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(FromEve, attributes(eve))]
+pub fn derive_from_eve(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_eve(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+enum Layout {
+    Tuple,
+    Dict,
+    Object(String)
+}
+
+/// What an `#[eve(...)]` attribute is written on, which decides the keys
+/// it can take
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    Container,
+    Variant,
+    Field
+}
+
+#[derive(Default)]
+struct Attrs {
+    class: Option<String>,
+    layout: Option<(String, Span)>,
+    rename: Option<String>,
+    default: bool
+}
+
+impl Attrs {
+    fn parse(attrs: &[syn::Attribute], level: Level) -> Result<Self> {
+        let mut parsed = Attrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("eve")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("class") {
+                    if level != Level::Container {
+                        return Err(meta.error("class only applies to a struct"));
+                    }
+                    parsed.class = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("layout") {
+                    if level != Level::Container {
+                        return Err(meta.error("layout only applies to a struct or enum"));
+                    }
+                    let layout: LitStr = meta.value()?.parse()?;
+                    parsed.layout = Some((layout.value(), layout.span()));
+                } else if meta.path.is_ident("rename") {
+                    if level == Level::Container {
+                        return Err(meta.error("rename only applies to fields and variants"));
+                    }
+                    parsed.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("default") {
+                    if level != Level::Field {
+                        return Err(meta.error("default only applies to fields"));
+                    }
+                    parsed.default = true;
+                } else {
+                    return Err(meta.error("unknown eve attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+
+    fn layout(&self) -> Result<Layout> {
+        match (&self.layout, &self.class) {
+            (None, None) => Ok(Layout::Tuple),
+            (None, Some(class)) => Ok(Layout::Object(class.clone())),
+            (Some((layout, span)), class) => match (layout.as_str(), class) {
+                ("tuple", None) => Ok(Layout::Tuple),
+                ("dict", None) => Ok(Layout::Dict),
+                ("object", Some(class)) => Ok(Layout::Object(class.clone())),
+                ("object", None) => Err(Error::new(*span, "object layout needs a class")),
+                ("tuple" | "dict", Some(_)) => Err(Error::new(*span, "class only applies to the object layout")),
+                _ => Err(Error::new(*span, "layout must be \"tuple\", \"dict\" or \"object\""))
+            }
+        }
+    }
+}
+
+struct Field {
+    member: Member,
+    // Name in error messages and dict keys
+    name: String,
+    default: bool,
+    // What the field is bound to when matching an enum variant
+    binding: Ident
+}
+
+fn fields(fields: &Fields, layout: &Layout) -> Result<Vec<Field>> {
+    fields.iter().enumerate().map(|(i, field)| {
+        let attrs = Attrs::parse(&field.attrs, Level::Field)?;
+        if attrs.default && matches!(layout, Layout::Tuple) {
+            return Err(Error::new_spanned(field, "fields sent as a tuple can't be left out"));
+        }
+
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(i.into())
+        };
+        let name = match (attrs.rename, &field.ident) {
+            (Some(rename), _) => rename,
+            (None, Some(ident)) => ident.to_string(),
+            (None, None) => i.to_string()
+        };
+        Ok(Field { member, name, default: attrs.default, binding: format_ident!("__field{}", i) })
+    }).collect()
+}
+
+/// How a variant's fields are laid out, only named ones follow the enum's layout
+fn variant_layout<'l>(fields: &Fields, layout: &'l Layout) -> &'l Layout {
+    match fields {
+        Fields::Named(_) => layout,
+        _ => &Layout::Tuple
+    }
+}
+
+/// Builds the value for a set of fields from expressions referencing them
+fn fields_to_eve(layout: &Layout, fields: &[Field], access: &[TokenStream]) -> TokenStream {
+    let values = access.iter().map(|access| quote!(::eve_proto::convert::ToEve::to_eve(#access)));
+    match layout {
+        Layout::Tuple => quote!(::eve_proto::value::EVEValue::Tuple(::std::vec![#(#values),*])),
+        Layout::Dict => {
+            let names = fields.iter().map(|field| &field.name);
+            quote!(::eve_proto::convert::__private::dict(::std::vec![#((#names, #values)),*]))
+        },
+        Layout::Object(class) => {
+            let dict = fields_to_eve(&Layout::Dict, fields, access);
+            quote!(::eve_proto::convert::__private::object(#class, #dict))
+        }
+    }
+}
+
+/// Reads a set of fields out of `value`, then evaluates `build` with each
+/// field's binding set
+fn fields_from_eve(layout: &Layout, fields: &[Field], value: TokenStream, ty: &str, build: TokenStream) -> TokenStream {
+    let bindings = fields.iter().map(|field| &field.binding);
+    match layout {
+        Layout::Tuple => {
+            let len = fields.len();
+            let reads = fields.iter().enumerate().map(|(i, field)| {
+                let name = &field.name;
+                quote!(::eve_proto::convert::__private::field(&__items[#i], #ty, #name)?)
+            });
+            quote!({
+                let __items = ::eve_proto::convert::__private::items(#value, #len)?;
+                #(let #bindings = #reads;)*
+                #build
+            })
+        },
+        Layout::Dict | Layout::Object(_) => {
+            let reads = fields.iter().map(|field| {
+                let name = &field.name;
+                if field.default {
+                    quote!(::eve_proto::convert::__private::dict_field_or_default(__fields, #ty, #name)?)
+                } else {
+                    quote!(::eve_proto::convert::__private::dict_field(__fields, #ty, #name)?)
+                }
+            });
+            let value = match layout {
+                Layout::Object(class) => quote!(::eve_proto::convert::__private::object_args(#value, #class)?),
+                _ => value
+            };
+            quote!({
+                let __fields = ::eve_proto::convert::__private::fields(#value)?;
+                #(let #bindings = #reads;)*
+                #build
+            })
+        }
+    }
+}
+
+/// `path { a: __field0, .. }` or `path(__field0, ..)` for the fields
+fn construct(path: TokenStream, fields: &[Field], style: &Fields) -> TokenStream {
+    let members = fields.iter().map(|field| &field.member);
+    let bindings = fields.iter().map(|field| &field.binding);
+    match style {
+        Fields::Named(_) => quote!(#path { #(#members: #bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path
+    }
+}
+
+fn with_bounds(input: &DeriveInput, bound: TokenStream) -> syn::Generics {
+    let mut generics = input.generics.clone();
+    let params: Vec<_> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(syn::parse_quote!(#param: #bound));
+    }
+    generics
+}
+
+fn expand_to_eve(input: &DeriveInput) -> Result<TokenStream> {
+    let attrs = Attrs::parse(&input.attrs, Level::Container)?;
+    let layout = attrs.layout()?;
+
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unit => quote!(::eve_proto::value::EVEValue::None),
+            // Newtypes are sent as what they wrap unless asked otherwise
+            Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 && attrs.layout.is_none() && attrs.class.is_none() => {
+                fields(&data.fields, &Layout::Tuple)?;
+                quote!(::eve_proto::convert::ToEve::to_eve(&self.0))
+            },
+            style => {
+                let fields = fields(style, &layout)?;
+                let access: Vec<_> = fields.iter().map(|field| {
+                    let member = &field.member;
+                    quote!(&self.#member)
+                }).collect();
+                fields_to_eve(&layout, &fields, &access)
+            }
+        },
+        Data::Enum(data) => {
+            if attrs.class.is_some() {
+                return Err(Error::new_spanned(input, "enums can't be sent as objects"));
+            }
+
+            let arms = data.variants.iter().map(|variant| {
+                let variant_attrs = Attrs::parse(&variant.attrs, Level::Variant)?;
+                let ident = &variant.ident;
+                let name = variant_attrs.rename.unwrap_or_else(|| ident.to_string());
+                let fields = fields(&variant.fields, variant_layout(&variant.fields, &layout))?;
+                let pattern = construct(quote!(Self::#ident), &fields, &variant.fields);
+                let access: Vec<_> = fields.iter().map(|field| {
+                    let binding = &field.binding;
+                    quote!(#binding)
+                }).collect();
+
+                Ok(match &variant.fields {
+                    Fields::Unit => quote! {
+                        #pattern => ::eve_proto::value::EVEValue::Unicode(::eve_proto::convert::__private::Cow::Borrowed(#name))
+                    },
+                    Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => quote! {
+                        #pattern => ::eve_proto::convert::__private::variant(#name, ::eve_proto::convert::ToEve::to_eve(__field0))
+                    },
+                    Fields::Unnamed(_) => {
+                        let contents = fields_to_eve(&Layout::Tuple, &fields, &access);
+                        quote!(#pattern => ::eve_proto::convert::__private::variant(#name, #contents))
+                    },
+                    Fields::Named(_) => {
+                        let contents = fields_to_eve(&layout, &fields, &access);
+                        quote!(#pattern => ::eve_proto::convert::__private::variant(#name, #contents))
+                    }
+                })
+            }).collect::<Result<Vec<_>>>()?;
+
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        },
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions can't be sent over the wire"))
+    };
+
+    let ident = &input.ident;
+    let generics = with_bounds(input, quote!(::eve_proto::convert::ToEve));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::eve_proto::convert::ToEve for #ident #ty_generics #where_clause {
+            fn to_eve(&self) -> ::eve_proto::value::EVEValue<'static> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_from_eve(input: &DeriveInput) -> Result<TokenStream> {
+    let attrs = Attrs::parse(&input.attrs, Level::Container)?;
+    let layout = attrs.layout()?;
+    let ty = input.ident.to_string();
+
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unit => quote! {
+                <() as ::eve_proto::convert::FromEve>::from_eve(value)?;
+                ::std::result::Result::Ok(Self)
+            },
+            Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 && attrs.layout.is_none() && attrs.class.is_none() => {
+                fields(&data.fields, &Layout::Tuple)?;
+                quote!(::eve_proto::convert::FromEve::from_eve(value).map(Self))
+            },
+            style => {
+                let fields = fields(style, &layout)?;
+                let build = construct(quote!(Self), &fields, style);
+                fields_from_eve(&layout, &fields, quote!(value), &ty, quote!(::std::result::Result::Ok(#build)))
+            }
+        },
+        Data::Enum(data) => {
+            if attrs.class.is_some() {
+                return Err(Error::new_spanned(input, "enums can't be sent as objects"));
+            }
+
+            let arms = data.variants.iter().map(|variant| {
+                let variant_attrs = Attrs::parse(&variant.attrs, Level::Variant)?;
+                let ident = &variant.ident;
+                let name = variant_attrs.rename.unwrap_or_else(|| ident.to_string());
+                let variant_ty = format!("{}::{}", ty, ident);
+                let fields = fields(&variant.fields, variant_layout(&variant.fields, &layout))?;
+                let build = construct(quote!(Self::#ident), &fields, &variant.fields);
+
+                Ok(match &variant.fields {
+                    Fields::Unit => quote!((#name, ::std::option::Option::None) => ::std::result::Result::Ok(#build)),
+                    Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => quote! {
+                        (#name, ::std::option::Option::Some(__value)) => ::std::result::Result::Ok(
+                            Self::#ident(::eve_proto::convert::__private::field(__value, #variant_ty, "0")?)
+                        )
+                    },
+                    Fields::Unnamed(_) => {
+                        let read = fields_from_eve(&Layout::Tuple, &fields, quote!(__value), &variant_ty, quote!(::std::result::Result::Ok(#build)));
+                        quote!((#name, ::std::option::Option::Some(__value)) => #read)
+                    },
+                    Fields::Named(_) => {
+                        let read = fields_from_eve(&layout, &fields, quote!(__value), &variant_ty, quote!(::std::result::Result::Ok(#build)));
+                        quote!((#name, ::std::option::Option::Some(__value)) => #read)
+                    }
+                })
+            }).collect::<Result<Vec<_>>>()?;
+
+            quote! {
+                let (__name, __contents) = ::eve_proto::convert::__private::split_variant(value, #ty)?;
+                match (__name.as_str(), __contents) {
+                    #(#arms,)*
+                    _ => ::std::result::Result::Err(::eve_proto::error::FromEveError::UnknownVariant {
+                        ty: #ty,
+                        variant: __name.clone()
+                    })
+                }
+            }
+        },
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions can't be sent over the wire"))
+    };
+
+    let ident = &input.ident;
+    let generics = with_bounds(input, quote!(::eve_proto::convert::FromEve));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::eve_proto::convert::FromEve for #ident #ty_generics #where_clause {
+            fn from_eve(value: &::eve_proto::value::EVEValue) -> ::std::result::Result<Self, ::eve_proto::error::FromEveError> {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use eve_proto::convert::{FromEve, ToEve};
+use eve_proto::decode::decode_payload;
+use eve_proto::encode::encode_payload;
+use eve_proto::error::FromEveError;
+use eve_proto::types::KeyVal;
+use eve_proto::value::EVEValue;
+
+#[derive(Debug, PartialEq, ToEve, FromEve)]
+struct GetTimeArgs(i64, String);
+
+#[derive(Debug, PartialEq, ToEve, FromEve)]
+struct CharacterId(i32);
+
+#[derive(Debug, PartialEq, ToEve, FromEve)]
+#[eve(class = "util.KeyVal")]
+struct CharacterInfo {
+    #[eve(rename = "characterID")]
+    id: CharacterId,
+    name: String,
+    #[eve(default)]
+    skills: HashMap<i32, u8>,
+    corporation: Option<i32>
+}
+
+#[derive(Debug, PartialEq, Serialize, ToEve, FromEve)]
+#[eve(class = "util.KeyVal")]
+struct Location {
+    x: f64,
+    solar_system: i32,
+    name: String
+}
+
+#[derive(Debug, PartialEq, ToEve, FromEve)]
+#[eve(layout = "dict")]
+struct Settings {
+    volume: f64,
+    muted: bool
+}
+
+#[derive(Debug, PartialEq, ToEve, FromEve)]
+enum Response {
+    Ok,
+    #[eve(rename = "error")]
+    Error(String),
+    Moved(i32, i32),
+    Redirect { node: i64, service: String }
+}
+
+fn round_trip<T: ToEve + FromEve>(value: &T) -> T {
+    let payload = encode_payload(&[value.to_eve()]).unwrap();
+    let decoded = decode_payload(&payload).unwrap();
+    T::from_eve(&decoded[0]).unwrap()
+}
+
+#[test]
+fn test_tuple_layout() {
+    let args = GetTimeArgs(42, "machoNet".to_string());
+    assert_eq!(args.to_eve(), EVEValue::Tuple(vec![EVEValue::Integer(42), "machoNet".into()]));
+    assert_eq!(round_trip(&args), args);
+
+    assert_eq!(CharacterId(7).to_eve(), EVEValue::Integer(7));
+}
+
+#[test]
+fn test_object_layout() {
+    let info = CharacterInfo {
+        id: CharacterId(90000001),
+        name: "Dreae".to_string(),
+        skills: HashMap::from([(3300, 5)]),
+        corporation: None
+    };
+    match info.to_eve() {
+        EVEValue::Object { class, args } => {
+            assert_eq!(class, "util.KeyVal");
+            match *args {
+                EVEValue::Dict(fields) => assert!(fields.contains_key(&b"characterID"[..].into())),
+                other => panic!("args are {:?}", other)
+            }
+        },
+        other => panic!("sent as {:?}", other)
+    }
+    assert_eq!(round_trip(&info), info);
+
+    let settings = Settings { volume: 0.5, muted: false };
+    assert!(matches!(settings.to_eve(), EVEValue::Dict(_)));
+    assert_eq!(round_trip(&settings), settings);
+}
+
+#[test]
+fn test_keyval_matches_serde() {
+    // Both send the field names with the same key type
+    let location = Location { x: 1.5, solar_system: 30000142, name: "Jita".to_string() };
+    let derived = encode_payload(&[location.to_eve()]).unwrap();
+    assert_eq!(derived, eve_proto::to_bytes(&KeyVal(&location)).unwrap());
+    assert_eq!(round_trip(&location), location);
+}
+
+#[test]
+fn test_enums() {
+    for response in [
+        Response::Ok,
+        Response::Error("denied".to_string()),
+        Response::Moved(1, 2),
+        Response::Redirect { node: 1000, service: "charMgr".to_string() }
+    ] {
+        assert_eq!(round_trip(&response), response);
+    }
+    assert_eq!(Response::Ok.to_eve(), "Ok".into());
+
+    let err = Response::from_eve(&"Gone".into()).unwrap_err();
+    assert_eq!(err, FromEveError::UnknownVariant { ty: "Response", variant: "Gone".to_string() });
+}
+
+#[test]
+fn test_errors() {
+    let err = GetTimeArgs::from_eve(&EVEValue::Tuple(vec![EVEValue::Integer(1)])).unwrap_err();
+    assert_eq!(err, FromEveError::WrongLength { expected: 2, found: 1 });
+
+    let err = GetTimeArgs::from_eve(&EVEValue::Tuple(vec![EVEValue::Integer(1), EVEValue::Integer(2)])).unwrap_err();
+    assert_eq!(err.to_string(), "field `1` of GetTimeArgs: expected string, found int");
+
+    let settings = eve_proto::convert::__private::dict(vec![("volume", EVEValue::Float(1.0))]);
+    let err = Settings::from_eve(&settings).unwrap_err();
+    assert_eq!(err, FromEveError::MissingField { ty: "Settings", field: "muted" });
+
+    let other = eve_proto::convert::__private::object("util.Row", EVEValue::None);
+    let err = CharacterInfo::from_eve(&other).unwrap_err();
+    assert_eq!(err.to_string(), "expected a util.KeyVal object, found util.Row");
+}
@@ -0,0 +1,4 @@
+#[test]
+fn test_attribute_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
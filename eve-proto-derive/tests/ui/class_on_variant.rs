@@ -0,0 +1,10 @@
+use eve_proto_derive::ToEve;
+
+#[derive(ToEve)]
+enum Target {
+    #[eve(class = "util.KeyVal")]
+    Item { id: i64 },
+    Nothing
+}
+
+fn main() {}
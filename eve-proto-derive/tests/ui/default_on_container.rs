@@ -0,0 +1,9 @@
+use eve_proto_derive::FromEve;
+
+#[derive(FromEve)]
+#[eve(layout = "dict", default)]
+struct Skills {
+    level: u8
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use eve_proto_derive::FromEve;
+
+#[derive(FromEve)]
+#[eve(layout = "dict")]
+enum Event {
+    Jump(i64, #[eve(default)] Option<i64>),
+    Dock { station: i64 }
+}
+
+fn main() {}
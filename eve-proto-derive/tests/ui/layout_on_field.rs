@@ -0,0 +1,10 @@
+use eve_proto_derive::ToEve;
+
+#[derive(ToEve)]
+struct Position {
+    #[eve(layout = "dict")]
+    x: f64,
+    y: f64
+}
+
+fn main() {}
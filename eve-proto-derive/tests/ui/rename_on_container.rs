@@ -0,0 +1,9 @@
+use eve_proto_derive::FromEve;
+
+#[derive(FromEve)]
+#[eve(rename = "characterInfo")]
+struct CharacterInfo {
+    name: String
+}
+
+fn main() {}
@@ -0,0 +1,150 @@
+//! Decodes, encodes and compares marshal payloads such as packet captures
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::Value as Json;
+
+use eve_proto::decode::decode_payload;
+use eve_proto::encode::encode_payload;
+use eve_proto::json::{from_json, to_json};
+use eve_proto::{diff, disassemble, repr, PrettyPrinter};
+
+#[derive(Parser)]
+#[command(name = "marshal-tool", about = "Decode, encode and compare EVE marshal payloads")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the values in a payload
+    Dump {
+        /// Payload to read, stdin if left out or `-`
+        input: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Leave out containers nested deeper than this
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Wrap lines at this width and cut longer strings short
+        #[arg(long)]
+        width: Option<usize>
+    },
+    /// List which bytes of a payload make up each value
+    Disasm {
+        /// Payload to read, stdin if left out or `-`
+        input: Option<PathBuf>
+    },
+    /// Build a payload from values as printed by `dump`
+    Encode {
+        /// Values to read, stdin if left out or `-`
+        input: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Where to write the payload, stdout if left out
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    },
+    /// Show where two payloads differ, exiting with 1 if they do
+    Diff {
+        left: PathBuf,
+        right: PathBuf
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Python repr, reads back exactly apart from integer widths
+    Text,
+    /// JSON with type tags, reads back exactly, see `eve_proto::json`
+    Json
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(code) => code,
+        // Output piped into something like `head` that stopped reading
+        Err(err) if err.downcast_ref::<io::Error>().is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("marshal-tool: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(command: Command) -> Result<ExitCode, Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+    match command {
+        Command::Dump { input, format, depth, width } => {
+            let payload = read_input(input.as_deref())?;
+            let values = decode_payload(&payload)?;
+            match format {
+                Format::Text => {
+                    let mut printer = PrettyPrinter::new().truncate_strings(width.is_some());
+                    if let Some(depth) = depth {
+                        printer = printer.max_depth(depth);
+                    }
+                    if let Some(width) = width {
+                        printer = printer.max_width(width);
+                    }
+                    for value in &values {
+                        writeln!(stdout, "{}", printer.print(value))?;
+                    }
+                },
+                Format::Json => {
+                    let json = Json::Array(values.iter().map(to_json).collect());
+                    writeln!(stdout, "{}", serde_json::to_string_pretty(&json)?)?;
+                }
+            }
+        },
+        Command::Disasm { input } => {
+            let payload = read_input(input.as_deref())?;
+            write!(stdout, "{}", disassemble(&payload))?;
+        },
+        Command::Encode { input, format, output } => {
+            let text = read_input(input.as_deref())?;
+            let values = match format {
+                Format::Text => repr::parse(std::str::from_utf8(&text)?)?,
+                // An array with an item for each value
+                Format::Json => match serde_json::from_slice(&text)? {
+                    Json::Array(items) => items.iter().map(from_json).collect::<Result<_, _>>()?,
+                    _ => return Err("expected a JSON array of values".into())
+                }
+            };
+
+            let payload = encode_payload(&values)?;
+            match output {
+                Some(path) => fs::write(path, payload)?,
+                None => stdout.write_all(&payload)?
+            }
+        },
+        Command::Diff { left, right } => {
+            let (left, right) = (read_input(Some(&left))?, read_input(Some(&right))?);
+            let differences = diff(&decode_payload(&left)?, &decode_payload(&right)?);
+            for difference in &differences {
+                writeln!(stdout, "{}", difference)?;
+            }
+            if !differences.is_empty() {
+                return Ok(ExitCode::from(1));
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn read_input(path: Option<&Path>) -> io::Result<Vec<u8>> {
+    match path {
+        Some(path) if path != Path::new("-") => fs::read(path),
+        _ => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            Ok(input)
+        }
+    }
+}
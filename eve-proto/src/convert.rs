@@ -0,0 +1,408 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+use crate::error::FromEveError;
+use crate::value::{EVEValue, HashableEVEValue};
+
+pub use eve_proto_derive::{FromEve, ToEve};
+
+/// Types that can be sent as a marshal value, see the `ToEve` derive
+pub trait ToEve {
+    fn to_eve(&self) -> EVEValue<'static>;
+}
+
+/// Types that can be built from a received marshal value, see the `FromEve` derive
+pub trait FromEve: Sized {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError>;
+}
+
+/// Types that can be used as dict keys
+pub trait ToEveKey {
+    fn to_eve_key(&self) -> HashableEVEValue<'static>;
+}
+
+fn wrong_type<T>(expected: &'static str, value: &EVEValue) -> Result<T, FromEveError> {
+    Err(FromEveError::WrongType { expected, found: value.type_name() })
+}
+
+fn as_int(value: &EVEValue) -> Result<i128, FromEveError> {
+    match *value {
+        EVEValue::Byte(i) => Ok(i.into()),
+        EVEValue::Short(i) => Ok(i.into()),
+        EVEValue::Integer(i) => Ok(i.into()),
+        EVEValue::BigInt(i) => Ok(i),
+        _ => wrong_type("int", value)
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ToEve for $ty {
+                fn to_eve(&self) -> EVEValue<'static> {
+                    match i64::try_from(*self) {
+                        Ok(i) => EVEValue::Integer(i),
+                        Err(_) => EVEValue::BigInt(*self as i128)
+                    }
+                }
+            }
+
+            impl ToEveKey for $ty {
+                fn to_eve_key(&self) -> HashableEVEValue<'static> {
+                    match i64::try_from(*self) {
+                        Ok(i) => HashableEVEValue::Integer(i),
+                        Err(_) => HashableEVEValue::BigInt(*self as i128)
+                    }
+                }
+            }
+
+            impl FromEve for $ty {
+                fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+                    <$ty>::try_from(as_int(value)?).map_err(|_| FromEveError::OutOfRange { ty: stringify!($ty) })
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(i8, i16, i32, i64, i128, u8, u16, u32, u64);
+
+impl ToEve for bool {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Bool(*self)
+    }
+}
+
+impl ToEveKey for bool {
+    fn to_eve_key(&self) -> HashableEVEValue<'static> {
+        HashableEVEValue::Bool(*self)
+    }
+}
+
+impl FromEve for bool {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match *value {
+            EVEValue::Bool(b) => Ok(b),
+            _ => wrong_type("bool", value)
+        }
+    }
+}
+
+impl ToEve for f64 {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Float(*self)
+    }
+}
+
+impl ToEve for f32 {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Float((*self).into())
+    }
+}
+
+impl FromEve for f64 {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match *value {
+            EVEValue::Float(f) => Ok(f),
+            _ => as_int(value).map(|i| i as f64).or_else(|_| wrong_type("float", value))
+        }
+    }
+}
+
+impl FromEve for f32 {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        f64::from_eve(value).map(|f| f as f32)
+    }
+}
+
+impl ToEve for str {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Unicode(Cow::Owned(self.to_owned()))
+    }
+}
+
+impl ToEve for String {
+    fn to_eve(&self) -> EVEValue<'static> {
+        self.as_str().to_eve()
+    }
+}
+
+impl ToEveKey for String {
+    fn to_eve_key(&self) -> HashableEVEValue<'static> {
+        HashableEVEValue::Unicode(Cow::Owned(self.clone()))
+    }
+}
+
+impl FromEve for String {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match value {
+            EVEValue::Unicode(s) => Ok(s.to_string()),
+            EVEValue::String(s) => String::from_utf8(s.to_vec()).or_else(|_| wrong_type("UTF-8 string", value)),
+            _ => wrong_type("string", value)
+        }
+    }
+}
+
+impl ToEve for () {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::None
+    }
+}
+
+impl FromEve for () {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match value {
+            EVEValue::None => Ok(()),
+            _ => wrong_type("None", value)
+        }
+    }
+}
+
+impl ToEve for EVEValue<'_> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        self.clone().into_owned()
+    }
+}
+
+impl FromEve for EVEValue<'static> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        Ok(value.clone().into_owned())
+    }
+}
+
+impl<T: ToEve + ?Sized> ToEve for &T {
+    fn to_eve(&self) -> EVEValue<'static> {
+        (**self).to_eve()
+    }
+}
+
+impl<T: ToEve + ?Sized> ToEve for Box<T> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        (**self).to_eve()
+    }
+}
+
+impl<T: FromEve> FromEve for Box<T> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        T::from_eve(value).map(Box::new)
+    }
+}
+
+impl<T: ToEve> ToEve for Option<T> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        match self {
+            Some(value) => value.to_eve(),
+            None => EVEValue::None
+        }
+    }
+}
+
+impl<T: FromEve> FromEve for Option<T> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match value {
+            EVEValue::None => Ok(None),
+            value => T::from_eve(value).map(Some)
+        }
+    }
+}
+
+impl<T: ToEve> ToEve for [T] {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::List(self.iter().map(ToEve::to_eve).collect())
+    }
+}
+
+impl<T: ToEve> ToEve for Vec<T> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        self.as_slice().to_eve()
+    }
+}
+
+impl<T: FromEve> FromEve for Vec<T> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        match value {
+            EVEValue::List(vals) | EVEValue::Tuple(vals) => vals.iter().map(T::from_eve).collect(),
+            _ => wrong_type("list", value)
+        }
+    }
+}
+
+/// Field names go over the wire as str, like Python attribute names. Both
+/// the derive macros and serde send `util.KeyVal` fields with these keys.
+pub(crate) fn field_key(name: &'static str) -> HashableEVEValue<'static> {
+    HashableEVEValue::String(Cow::Borrowed(name.as_bytes()))
+}
+
+fn dict_entries<'v, 'a>(value: &'v EVEValue<'a>) -> Result<&'v BTreeMap<HashableEVEValue<'a>, EVEValue<'a>>, FromEveError> {
+    match value {
+        EVEValue::Dict(map) => Ok(map),
+        _ => wrong_type("dict", value)
+    }
+}
+
+impl<K: ToEveKey, V: ToEve, S> ToEve for HashMap<K, V, S> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Dict(self.iter().map(|(key, value)| (key.to_eve_key(), value.to_eve())).collect())
+    }
+}
+
+impl<K: FromEve + Eq + Hash, V: FromEve, S: BuildHasher + Default> FromEve for HashMap<K, V, S> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        dict_entries(value)?.iter()
+            .map(|(key, value)| Ok((K::from_eve(&key.clone().into())?, V::from_eve(value)?)))
+            .collect()
+    }
+}
+
+impl<K: ToEveKey, V: ToEve> ToEve for BTreeMap<K, V> {
+    fn to_eve(&self) -> EVEValue<'static> {
+        EVEValue::Dict(self.iter().map(|(key, value)| (key.to_eve_key(), value.to_eve())).collect())
+    }
+}
+
+impl<K: FromEve + Ord, V: FromEve> FromEve for BTreeMap<K, V> {
+    fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+        dict_entries(value)?.iter()
+            .map(|(key, value)| Ok((K::from_eve(&key.clone().into())?, V::from_eve(value)?)))
+            .collect()
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr => $($name:ident $index:tt),+) => {
+        impl<$($name: ToEve),+> ToEve for ($($name,)+) {
+            fn to_eve(&self) -> EVEValue<'static> {
+                EVEValue::Tuple(vec![$(self.$index.to_eve()),+])
+            }
+        }
+
+        impl<$($name: ToEveKey),+> ToEveKey for ($($name,)+) {
+            fn to_eve_key(&self) -> HashableEVEValue<'static> {
+                HashableEVEValue::Tuple(vec![$(self.$index.to_eve_key()),+])
+            }
+        }
+
+        impl<$($name: FromEve),+> FromEve for ($($name,)+) {
+            fn from_eve(value: &EVEValue) -> Result<Self, FromEveError> {
+                let items = __private::items(value, $len)?;
+                Ok(($($name::from_eve(&items[$index])?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(1 => A 0);
+impl_tuple!(2 => A 0, B 1);
+impl_tuple!(3 => A 0, B 1, C 2);
+impl_tuple!(4 => A 0, B 1, C 2, D 3);
+impl_tuple!(5 => A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6 => A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Used by the derive macros, not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use std::borrow::Cow;
+    pub use std::vec::Vec;
+
+    use std::collections::BTreeMap;
+
+    use crate::error::FromEveError;
+    use crate::value::{EVEValue, HashableEVEValue};
+    use super::FromEve;
+
+    type Fields<'v, 'a> = &'v BTreeMap<HashableEVEValue<'a>, EVEValue<'a>>;
+
+    pub fn key(name: &'static str) -> HashableEVEValue<'static> {
+        super::field_key(name)
+    }
+
+    pub fn dict(fields: Vec<(&'static str, EVEValue<'static>)>) -> EVEValue<'static> {
+        EVEValue::Dict(fields.into_iter().map(|(name, value)| (key(name), value)).collect())
+    }
+
+    pub fn object(class: &'static str, args: EVEValue<'static>) -> EVEValue<'static> {
+        EVEValue::Object { class: Cow::Borrowed(class), args: Box::new(args) }
+    }
+
+    /// An enum variant with its contents
+    pub fn variant(name: &'static str, contents: EVEValue<'static>) -> EVEValue<'static> {
+        EVEValue::Dict(BTreeMap::from([(HashableEVEValue::Unicode(Cow::Borrowed(name)), contents)]))
+    }
+
+    pub fn items<'v, 'a>(value: &'v EVEValue<'a>, len: usize) -> Result<&'v [EVEValue<'a>], FromEveError> {
+        match value {
+            EVEValue::Tuple(vals) | EVEValue::List(vals) if vals.len() == len => Ok(vals),
+            EVEValue::Tuple(vals) | EVEValue::List(vals) => Err(FromEveError::WrongLength { expected: len, found: vals.len() }),
+            _ => Err(FromEveError::WrongType { expected: "tuple", found: value.type_name() })
+        }
+    }
+
+    pub fn fields<'v, 'a>(value: &'v EVEValue<'a>) -> Result<Fields<'v, 'a>, FromEveError> {
+        super::dict_entries(value)
+    }
+
+    pub fn object_args<'v, 'a>(value: &'v EVEValue<'a>, class: &'static str) -> Result<&'v EVEValue<'a>, FromEveError> {
+        match value {
+            EVEValue::Object { class: found, args } if found == class => Ok(args),
+            EVEValue::Object { class: found, .. } => Err(FromEveError::WrongClass { expected: class, found: found.to_string() }),
+            _ => Err(FromEveError::WrongType { expected: "object", found: value.type_name() })
+        }
+    }
+
+    pub fn field<T: FromEve>(value: &EVEValue, ty: &'static str, field: &'static str) -> Result<T, FromEveError> {
+        T::from_eve(value).map_err(|source| FromEveError::InvalidField { ty, field, source: Box::new(source) })
+    }
+
+    pub fn dict_field<T: FromEve>(fields: Fields, ty: &'static str, name: &'static str) -> Result<T, FromEveError> {
+        match fields.get(&key(name)) {
+            Some(value) => field(value, ty, name),
+            None => Err(FromEveError::MissingField { ty, field: name })
+        }
+    }
+
+    pub fn dict_field_or_default<T: FromEve + Default>(fields: Fields, ty: &'static str, name: &'static str) -> Result<T, FromEveError> {
+        match fields.get(&key(name)) {
+            Some(value) => field(value, ty, name),
+            None => Ok(T::default())
+        }
+    }
+
+    /// Splits a received enum into its variant name and contents, if it has any
+    pub fn split_variant<'v, 'a>(value: &'v EVEValue<'a>, ty: &'static str) -> Result<(String, Option<&'v EVEValue<'a>>), FromEveError> {
+        let name = |name: &EVEValue| match name {
+            EVEValue::Unicode(s) => Ok(s.to_string()),
+            EVEValue::String(s) => Ok(String::from_utf8_lossy(s).into_owned()),
+            _ => Err(FromEveError::UnknownVariant { ty, variant: name.type_name().to_string() })
+        };
+
+        match value {
+            EVEValue::Dict(map) if map.len() == 1 => {
+                let (variant, contents) = map.iter().next().expect("map has one entry");
+                Ok((name(&variant.clone().into())?, Some(contents)))
+            },
+            value => Ok((name(value)?, None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn test_primitives() {
+        assert_eq!(u64::MAX.to_eve(), EVEValue::BigInt(u64::MAX as i128));
+        assert_eq!(u64::from_eve(&EVEValue::BigInt(u64::MAX as i128)), Ok(u64::MAX));
+        assert_eq!(u8::from_eve(&EVEValue::Integer(256)), Err(FromEveError::OutOfRange { ty: "u8" }));
+        assert_eq!(f64::from_eve(&EVEValue::Byte(3)), Ok(3.0));
+        assert_eq!(String::from_eve(&b"abc"[..].into()), Ok("abc".to_string()));
+        assert_eq!(Option::<i32>::from_eve(&EVEValue::None), Ok(None));
+
+        let pair = (1, "a".to_string());
+        assert_eq!(<(i32, String)>::from_eve(&pair.to_eve()), Ok(pair));
+
+        let map = BTreeMap::from([((1, true), vec![2.5])]);
+        assert_eq!(BTreeMap::from_eve(&map.to_eve()), Ok(map));
+    }
+}
@@ -0,0 +1,312 @@
+use std::borrow::Cow;
+
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::forward_to_deserialize_any;
+
+use crate::decode::decode_payload;
+use crate::error::ConvertError;
+use crate::types::{GLOBAL, KEYVAL, KEYVAL_CLASS, OBJECT};
+use crate::value::EVEValue;
+
+/// Builds a `T` from a marshal value, the reverse of `to_value`.
+///
+/// Structs can be read from either a tuple of their fields or a dict keyed
+/// by field name, and objects other than `util.KeyVal` are looked through
+/// to their args.
+pub fn from_value<'de, T: Deserialize<'de>>(value: EVEValue<'de>) -> Result<T, ConvertError> {
+    T::deserialize(value)
+}
+
+/// Decodes a payload holding a single value into a `T`
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, ConvertError> {
+    let mut values = decode_payload(bytes)?;
+    if values.len() != 1 {
+        return Err(ConvertError::ValueCount(values.len()));
+    }
+    from_value(values.remove(0))
+}
+
+impl de::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::Message(msg.to_string())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ConvertError> for EVEValue<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn unexpected<'a>(value: &'a EVEValue) -> Unexpected<'a> {
+    match *value {
+        EVEValue::None => Unexpected::Unit,
+        EVEValue::Bool(b) => Unexpected::Bool(b),
+        EVEValue::Byte(i) => Unexpected::Unsigned(i.into()),
+        EVEValue::Short(i) => Unexpected::Signed(i.into()),
+        EVEValue::Integer(i) => Unexpected::Signed(i),
+        EVEValue::Float(f) => Unexpected::Float(f),
+        EVEValue::String(ref s) | EVEValue::Buffer(ref s) => Unexpected::Bytes(s),
+        EVEValue::Unicode(ref s) => Unexpected::Str(s),
+        EVEValue::Tuple(_) | EVEValue::List(_) | EVEValue::SubStream(_) => Unexpected::Seq,
+        EVEValue::Dict(_) | EVEValue::PackedRow(_) => Unexpected::Map,
+        EVEValue::BigInt(_) => Unexpected::Other("big integer"),
+        EVEValue::Object { .. } => Unexpected::Other("object"),
+        EVEValue::ObjectEx { .. } => Unexpected::Other("ObjectEx"),
+        EVEValue::Global(_) => Unexpected::Other("global"),
+        EVEValue::ChecksummedStream { .. } => Unexpected::Other("checksummed stream"),
+        EVEValue::Pickled(_) => Unexpected::Other("pickle")
+    }
+}
+
+fn visit_seq<'de, V: Visitor<'de>>(vals: Vec<EVEValue<'de>>, visitor: V) -> Result<V::Value, ConvertError> {
+    let mut seq = SeqDeserializer::new(vals.into_iter());
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+fn visit_map<'de, V: Visitor<'de>>(
+    entries: impl Iterator<Item = (EVEValue<'de>, EVEValue<'de>)>,
+    visitor: V
+) -> Result<V::Value, ConvertError> {
+    let mut map = MapDeserializer::new(entries);
+    let value = visitor.visit_map(&mut map)?;
+    map.end()?;
+    Ok(value)
+}
+
+fn visit_bytes<'de, V: Visitor<'de>>(bytes: Cow<'de, [u8]>, visitor: V) -> Result<V::Value, ConvertError> {
+    match bytes {
+        Cow::Borrowed(b) => visitor.visit_borrowed_bytes(b),
+        Cow::Owned(b) => visitor.visit_byte_buf(b)
+    }
+}
+
+fn visit_str<'de, V: Visitor<'de>>(s: Cow<'de, str>, visitor: V) -> Result<V::Value, ConvertError> {
+    match s {
+        Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+        Cow::Owned(s) => visitor.visit_string(s)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for EVEValue<'de> {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            EVEValue::None => visitor.visit_unit(),
+            EVEValue::Bool(b) => visitor.visit_bool(b),
+            EVEValue::Byte(i) => visitor.visit_u8(i),
+            EVEValue::Short(i) => visitor.visit_i16(i),
+            EVEValue::Integer(i) => visitor.visit_i64(i),
+            EVEValue::BigInt(i) => match i64::try_from(i) {
+                Ok(i) => visitor.visit_i64(i),
+                Err(_) => visitor.visit_i128(i)
+            },
+            EVEValue::Float(f) => visitor.visit_f64(f),
+            // Python str is usually text, only fall back to bytes if it isn't
+            EVEValue::String(Cow::Borrowed(s)) => match std::str::from_utf8(s) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => visitor.visit_borrowed_bytes(s)
+            },
+            EVEValue::String(Cow::Owned(s)) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(err) => visitor.visit_byte_buf(err.into_bytes())
+            },
+            EVEValue::Unicode(s) | EVEValue::Global(s) => visit_str(s, visitor),
+            EVEValue::Buffer(b) => visit_bytes(b, visitor),
+            EVEValue::Tuple(vals) | EVEValue::List(vals) | EVEValue::SubStream(vals) => visit_seq(vals, visitor),
+            EVEValue::Dict(map) => visit_map(map.into_iter().map(|(key, value)| (key.into(), value)), visitor),
+            EVEValue::PackedRow(row) => {
+                let entries: Vec<_> = row.descriptor().columns().iter()
+                    .zip(row.values())
+                    .map(|(column, value)| (EVEValue::Unicode(column.name.clone()), value.to_value()))
+                    .collect();
+                visit_map(entries.into_iter(), visitor)
+            },
+            EVEValue::Object { args, .. } => args.deserialize_any(visitor),
+            EVEValue::ChecksummedStream { value, .. } | EVEValue::Pickled(value) => value.deserialize_any(visitor),
+            ref other @ EVEValue::ObjectEx { .. } => Err(de::Error::invalid_type(unexpected(other), &visitor))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            EVEValue::None => visitor.visit_none(),
+            other => visitor.visit_some(other)
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            EVEValue::String(b) | EVEValue::Buffer(b) => visit_bytes(b, visitor),
+            EVEValue::Unicode(Cow::Borrowed(s)) => visitor.visit_borrowed_bytes(s.as_bytes()),
+            EVEValue::Unicode(Cow::Owned(s)) => visitor.visit_byte_buf(s.into_bytes()),
+            other => other.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        match (name, self) {
+            (GLOBAL, EVEValue::Global(name)) => visitor.visit_newtype_struct(EVEValue::Unicode(name)),
+            (KEYVAL, EVEValue::Object { class, args }) if class == KEYVAL_CLASS => visitor.visit_newtype_struct(*args),
+            (GLOBAL | KEYVAL, other) => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+            (_, value) => visitor.visit_newtype_struct(value)
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        match (name, self) {
+            (OBJECT, EVEValue::Object { class, args }) => visit_seq(vec![EVEValue::Unicode(class), *args], visitor),
+            (OBJECT, other) => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+            (_, value) => value.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            variant @ (EVEValue::Unicode(_) | EVEValue::String(_)) => visitor.visit_enum(Enum { variant, value: EVEValue::None }),
+            EVEValue::Dict(map) if map.len() == 1 => {
+                let (variant, value) = map.into_iter().next().expect("map has one entry");
+                visitor.visit_enum(Enum { variant: variant.into(), value })
+            },
+            other => Err(de::Error::invalid_type(unexpected(&other), &visitor))
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple map struct identifier ignored_any
+    }
+}
+
+/// A variant by name, with the value it holds
+struct Enum<'de> {
+    variant: EVEValue<'de>,
+    value: EVEValue<'de>
+}
+
+impl<'de> de::EnumAccess<'de> for Enum<'de> {
+    type Error = ConvertError;
+    type Variant = EVEValue<'de>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self::Variant), Self::Error> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EVEValue<'de> {
+    type Error = ConvertError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Self::Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::encode::encode_payload;
+    use crate::ser::{to_bytes, to_value};
+    use crate::types::{Global, KeyVal, Object};
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Character {
+        id: i64,
+        name: String,
+        corp: Option<i32>,
+        skills: HashMap<i32, u8>,
+        location: KeyVal<Location>
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Location {
+        system: i32,
+        docked: bool
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Command {
+        Stop,
+        Warp(f64, f64),
+        Dock { station: i32 }
+    }
+
+    #[test_log::test]
+    fn test_round_trip() {
+        let character = Character {
+            id: 90000001,
+            name: "Dreae".to_string(),
+            corp: None,
+            skills: HashMap::from([(3300, 5), (3301, 4)]),
+            location: KeyVal(Location { system: 30000142, docked: true })
+        };
+        let bytes = to_bytes(&character).unwrap();
+        assert_eq!(from_bytes::<Character>(&bytes).unwrap(), character);
+
+        for command in [Command::Stop, Command::Warp(1.0, -2.5), Command::Dock { station: 60003760 }] {
+            assert_eq!(from_bytes::<Command>(&to_bytes(&command).unwrap()).unwrap(), command);
+        }
+
+        let object = Object { class: "foo.Bar".to_string(), args: (Global("foo.baz".to_string()), vec![1, 2]) };
+        assert_eq!(from_value::<Object<(Global, Vec<i32>)>>(to_value(&object).unwrap()).unwrap(), object);
+    }
+
+    #[test_log::test]
+    fn test_from_wire_values() {
+        // Structs from dicts, objects looked through, str as text and bytes
+        let mut attrs = BTreeMap::new();
+        attrs.insert(b"system"[..].into(), EVEValue::Byte(7));
+        attrs.insert(b"docked"[..].into(), EVEValue::Bool(false));
+        let value = EVEValue::Object {
+            class: "some.Row".into(),
+            args: Box::new(EVEValue::Dict(attrs))
+        };
+        assert_eq!(from_value::<Location>(value).unwrap(), Location { system: 7, docked: false });
+
+        let payload = encode_payload(&[EVEValue::Tuple(vec![b"abc"[..].into(), EVEValue::BigInt(5)])]).unwrap();
+        let (s, i): (&str, u8) = from_bytes(&payload).unwrap();
+        assert_eq!((s, i), ("abc", 5));
+    }
+
+    #[test_log::test]
+    fn test_errors() {
+        let err = from_value::<KeyVal<Location>>(EVEValue::Integer(1)).unwrap_err();
+        assert!(matches!(err, ConvertError::Message(_)));
+
+        let err = from_value::<(i32,)>(EVEValue::Tuple(vec![EVEValue::Integer(1), EVEValue::Integer(2)])).unwrap_err();
+        assert!(matches!(err, ConvertError::Message(_)));
+
+        let payload = encode_payload(&[EVEValue::None, EVEValue::None]).unwrap();
+        assert_eq!(from_bytes::<()>(&payload).unwrap_err(), ConvertError::ValueCount(2));
+    }
+}
@@ -1,210 +1,794 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::ffi::OsStr;
-use std::os::unix::prelude::OsStrExt;
-
-use nom::{IResult, Err as NomErr};
-use nom::error::{Error, ErrorKind};
-use nom::number::complete::{le_u8, le_u32, le_f64, le_i32, le_i64, le_i16, le_u16, le_i128};
-use nom::combinator::{all_consuming, map};
-use nom::multi::{length_count, fold_many0, length_data, length_value};
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use nom::{IResult, Err as NomErr, Parser};
+use nom::error::Error as NomError;
+use nom::number::complete::{le_u8, le_u32, le_f64, le_i32, le_i64, le_i16, le_u16};
+use nom::combinator::map;
+use nom::multi::count;
 use nom::bytes::complete::{tag, take};
-use nom::sequence::{preceded, tuple};
+use nom::sequence::preceded;
 use nom::branch::alt;
 
-use crate::opcodes::EVEOpCode;
+use crate::error::{Error, Limit, PathSegment, ValuePath};
+use crate::fidelity::{Detail, Fidelity, Hint};
+use crate::packed_row::{self, DBRowDescriptor, DBValue, PackedRow};
+use crate::pickle::Unpickler;
+use crate::opcodes::{self, EVEOpCode, OPCODE_MASK, SHARED_FLAG, UNKNOWN_FLAG};
 use crate::string_table::DEFAULT_STRINGS;
-use crate::value::EVEValue;
+use crate::value::{EVEValue, HashableEVEValue, ObjectExKind};
 
-use crate::value::HashableEVEValue;
+pub(crate) type DecodeResult<'a, T> = IResult<&'a [u8], T, Error>;
 
-pub fn decode_payload<'a>(payload: &'a [u8]) -> IResult<&'a [u8], Vec<EVEValue>> {
-    let (payload, len) = le_u32(payload)?;
-    log::trace!("Len {}", len);
-    assert!(payload.len() == len as usize);
-
-    self::decode_payload_body(payload)
+/// Bounds on what a single payload may make the decoder do, so hostile
+/// length prefixes and nesting can't exhaust memory or the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Deepest nesting of containers
+    pub max_depth: usize,
+    /// Most items in a single tuple, list, dict or object
+    pub max_elements: usize,
+    /// Longest string, buffer, sub stream or inflated stream in bytes
+    pub max_length: usize,
+    /// Most values decoded in total, counting every copy made for
+    /// references to the save table
+    pub max_work: usize
 }
 
-fn decode_payload_body<'a>(payload: &'a [u8]) -> IResult<&'a [u8], Vec<EVEValue>> {
-    let (payload, _tilde) = tag([0x7e])(payload)?;
-    let (payload, _save_count) = le_u32(payload)?;
-    log::trace!("Decoding {} len body", payload.len());
-    log::trace!("Got save_count {}", _save_count);
-    fold_many0(
-        self::decode_value,
-        Vec::new,
-        |mut acc, item| {
-            acc.push(item);
-            acc
-        }
-    )(payload)
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 64,
+            max_elements: 1 << 20,
+            max_length: 16 << 20,
+            // References let a few hundred bytes ask for exponentially many
+            // copies, so this stays near what real payloads need
+            max_work: 1 << 18
+        }
+    }
 }
 
-fn decode_value<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, opcode) = le_u8(payload)?;
-    log::trace!("Got opcode {:#04x}", opcode);
-    match opcode {
-        _ if opcode == EVEOpCode::None.into() => Ok((payload, EVEValue::None)),
-        _ if opcode == EVEOpCode::Long.into() => map(le_i32, |v| v.into())(payload),
-        _ if opcode == EVEOpCode::LongLong.into() => map(le_i64, |v| v.into())(payload),
-        _ if opcode == EVEOpCode::SignedShort.into() => map(le_i16, |v| v.into())(payload),
-        _ if opcode == EVEOpCode::Byte.into() => map(le_u8, |v| v.into())(payload),
-        _ if opcode == EVEOpCode::IntegerNegativeOne.into() => Ok((payload, EVEValue::Integer(-1))),
-        _ if opcode == EVEOpCode::IntegerZero.into() => Ok((payload, EVEValue::Integer(0))),
-        _ if opcode == EVEOpCode::IntegerOne.into() => Ok((payload, EVEValue::Integer(1))),
-        _ if opcode == EVEOpCode::Real.into() => map(le_f64, |v| v.into())(payload),
-        _ if opcode == EVEOpCode::RealZero.into() => Ok((payload, EVEValue::Float(0.0))),
-        _ if opcode == EVEOpCode::ShortString.into() => self::decode_string(payload),
-        _ if opcode == EVEOpCode::StringTableString.into() => self::decode_stringtable_string(payload),
-        _ if opcode == EVEOpCode::WStringUCS2.into() => self::decode_wstring_ucs2(payload),
-        _ if opcode == EVEOpCode::LongString.into() => self::decode_string(payload),
-        _ if opcode == EVEOpCode::Tuple.into() => self::decode_tuple(payload),
-        _ if opcode == EVEOpCode::Dict.into() => self::decode_dict(payload),
-        _ if opcode == EVEOpCode::Object.into() => self::decode_object(payload),
-        _ if opcode == EVEOpCode::EmptyTuple.into() => Ok((payload, EVEValue::Tuple(vec![]))),
-        _ if opcode == EVEOpCode::OneTuple.into() => self::decode_one_tuple(payload),
-        _ if opcode == EVEOpCode::SubStream.into() => {
-            map(length_value(self::decode_size, self::decode_payload_body), |vals| EVEValue::SubStream(vals))(payload)
-        },
-        _ if opcode == EVEOpCode::TwoTuple.into() => self::decode_two_tuple(payload),
-        _ if opcode == EVEOpCode::WStringUTF8.into() => self::decode_wstring_utf8(payload),
-        _ if opcode == EVEOpCode::VarInteger.into() => self::decode_var_int(payload),
-        x => self::invalid_opcode(x, payload)
+impl DecodeLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            max_elements: usize::MAX,
+            max_length: usize::MAX,
+            max_work: usize::MAX
+        }
     }
 }
 
-fn invalid_opcode<'a>(opcode: u8, payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    log::error!("Invalid opcode {:#04x} in net message", opcode);
-    Err(NomErr::Failure(Error::new(payload, ErrorKind::Fail)))
+/// Decodes a payload within the default `DecodeLimits`
+pub fn decode_payload(payload: &[u8]) -> Result<Vec<EVEValue<'_>>, Error> {
+    decode_payload_with_limits(payload, DecodeLimits::default())
 }
 
-fn decode_size<'a>(payload: &'a [u8]) -> IResult<&'a [u8], usize> {
-    let (payload, size ) = alt((
-        map(preceded(tag([0xff]), le_u32),
-            |size| size as usize),
-        map(le_u8, |size| size as usize)
-    ))(payload)?;
-
-    Ok((payload, size as usize))
+/// Decodes a payload into values that own all their data, so they can
+/// outlive it. Only a convenience over `decode_payload` followed by
+/// `EVEValue::into_owned`: the values are decoded borrowing as usual, then
+/// every string and buffer is copied out.
+pub fn decode_payload_owned(payload: &[u8]) -> Result<Vec<EVEValue<'static>>, Error> {
+    let values = decode_payload(payload)?;
+    Ok(values.into_iter().map(EVEValue::into_owned).collect())
 }
 
-fn decode_tuple<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    log::trace!("Decoding tuple");
-    map(length_count(self::decode_size, self::decode_value), |vals| EVEValue::Tuple(vals))(payload)
+pub fn decode_payload_with_limits(payload: &[u8], limits: DecodeLimits) -> Result<Vec<EVEValue<'_>>, Error> {
+    Decoder::new(payload, limits).run(payload)
 }
 
-fn decode_two_tuple<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    log::trace!("Decoding two tuple");
-    let (payload, (item1, item2)) = tuple((self::decode_value, self::decode_value))(payload)?;
-    Ok((payload, EVEValue::Tuple(vec![item1, item2])))
+/// Decodes a payload along with how it was laid out, so that
+/// `Encoder::encode_payload_with_fidelity` can send it again unchanged
+pub fn decode_payload_with_fidelity(payload: &[u8]) -> Result<(Vec<EVEValue<'_>>, Fidelity), Error> {
+    let mut decoder = Decoder::new(payload, DecodeLimits::default());
+    decoder.hints = Some(Vec::new());
+    let values = decoder.run(payload)?;
+    Ok((values, Fidelity { hints: decoder.hints.unwrap_or_default() }))
 }
 
-fn decode_one_tuple<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    log::trace!("Decoding one tuple");
-    map(self::decode_value, |val| EVEValue::Tuple(vec![val]))(payload)
+/// The part of an encoded value covered by a ChecksummedStream's checksum,
+/// the body of a sub stream or the whole value for anything else
+pub(crate) fn checksummed_data(encoded: &[u8]) -> &[u8] {
+    match encoded.split_first() {
+        Some((opcode, rest)) if opcode & OPCODE_MASK == u8::from(EVEOpCode::SubStream) => match rest.split_first() {
+            Some((0xff, rest)) => rest.get(4..).unwrap_or_default(),
+            Some((_, rest)) => rest,
+            None => encoded
+        },
+        _ => encoded
+    }
 }
 
-fn decode_string<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-let (payload, size) = self::decode_size(payload)?;
-    log::trace!("Decoding {} length string", size);
+pub(crate) struct Decoder<'a> {
+    base: &'a [u8],
+    path: Vec<PathSegment>,
+    saved: SaveTable<'a>,
+    limits: DecodeLimits,
+    depth: usize,
+    work: usize,
+    // Only recorded in fidelity mode
+    hints: Option<Vec<Hint>>,
+    // Set by the opcode being decoded once everything inside it is done
+    detail: Option<Detail>
+}
 
-    let (payload, value) = take(size)(payload)?;
-    let string = OsStr::from_bytes(value);
-    log::trace!("Decoded string {:?}", string);
-    Ok((payload, EVEValue::String(string)))
+/// Objects flagged as shared in the stream currently being decoded
+#[derive(Default)]
+struct SaveTable<'a> {
+    // Slot for each shared object, in the order they appear in the stream
+    map: &'a [u8],
+    // Each object with the work it took to decode, charged again for every reference
+    objects: Vec<Option<(EVEValue<'a>, usize)>>
 }
 
-fn decode_wstring_ucs2<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, data) = length_count(self::decode_size, le_u16)(payload)?;
-    log::trace!("Decoding {} length wstring", data.len());
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(base: &'a [u8], limits: DecodeLimits) -> Self {
+        Self {
+            base,
+            path: Vec::new(),
+            saved: SaveTable::default(),
+            limits,
+            depth: 0,
+            work: 0,
+            hints: None,
+            detail: None
+        }
+    }
 
-    let mut buffer = vec![0u8; data.len() * 2];
-    if let Ok(_) = ucs2::decode(&data, &mut buffer) && let Ok(string) = String::from_utf8(buffer) {
-        log::trace!("Decoded string {}", string);
-        Ok((payload, EVEValue::OwnedString(string)))
-    } else {
-        log::warn!("Error decoding wstring in net message");
-        unimplemented!()
+    fn run(&mut self, payload: &'a [u8]) -> Result<Vec<EVEValue<'a>>, Error> {
+        match self.decode_payload(payload) {
+            Ok((_, values)) => Ok(values),
+            Err(NomErr::Error(err)) | Err(NomErr::Failure(err)) => Err(err),
+            Err(NomErr::Incomplete(_)) => Err(Error::Truncated { offset: payload.len(), path: ValuePath::default() })
+        }
+    }
+
+    fn record(&mut self, detail: impl FnOnce() -> Detail) {
+        if self.hints.is_some() {
+            self.detail = Some(detail());
+        }
+    }
+
+    fn offset(&self, at: &'a [u8]) -> usize {
+        at.as_ptr() as usize - self.base.as_ptr() as usize
+    }
+
+    fn fail(&self, at: &'a [u8], error: impl FnOnce(usize, ValuePath) -> Error) -> NomErr<Error> {
+        NomErr::Failure(error(self.offset(at), ValuePath::new(self.path.clone())))
+    }
+
+    fn exceeded(&self, at: &'a [u8], limit: Limit) -> NomErr<Error> {
+        log::error!("Decoding exceeded {:?} limit", limit);
+        self.fail(at, |offset, path| Error::LimitExceeded { limit, offset, path })
+    }
+
+    fn charge(&mut self, at: &'a [u8], work: usize) -> Result<(), NomErr<Error>> {
+        self.work = self.work.saturating_add(work);
+        if self.work > self.limits.max_work {
+            return Err(self.exceeded(at, Limit::Work));
+        }
+        Ok(())
+    }
+
+    /// Reads the size of a string like value
+    fn decode_length(&self, payload: &'a [u8]) -> DecodeResult<'a, usize> {
+        let (rest, len) = self.decode_size(payload)?;
+        if len > self.limits.max_length {
+            return Err(self.exceeded(payload, Limit::Length));
+        }
+        Ok((rest, len))
+    }
+
+    /// Reads the number of items in a container
+    fn decode_count(&self, payload: &'a [u8]) -> DecodeResult<'a, usize> {
+        let (rest, count) = self.decode_size(payload)?;
+        self.check_count(payload, count)?;
+        Ok((rest, count))
+    }
+
+    fn check_count(&self, at: &'a [u8], count: usize) -> Result<(), NomErr<Error>> {
+        if count > self.limits.max_elements {
+            return Err(self.exceeded(at, Limit::Elements));
+        }
+        Ok(())
+    }
+
+    /// Runs a plain nom parser, the only way those fail on complete input is running out of it
+    fn parse<O>(&self, payload: &'a [u8], mut parser: impl Parser<&'a [u8], O, NomError<&'a [u8]>>) -> DecodeResult<'a, O> {
+        parser.parse(payload).map_err(|err| match err {
+            NomErr::Error(err) | NomErr::Failure(err) => self.fail(err.input, |offset, path| Error::Truncated { offset, path }),
+            NomErr::Incomplete(_) => self.fail(payload, |offset, path| Error::Truncated { offset, path })
+        })
+    }
+
+    fn decode_payload(&mut self, payload: &'a [u8]) -> DecodeResult<'a, Vec<EVEValue<'a>>> {
+        let (body, len) = self.parse(payload, le_u32)?;
+        log::trace!("Len {}", len);
+        if body.len() != len as usize {
+            log::error!("Payload length {} does not match header {}", body.len(), len);
+            return Err(self.fail(payload, |offset, path| Error::BadLength { length: len as usize, offset, path }));
+        }
+
+        self.decode_payload_body(body, None)
+    }
+
+    fn decode_payload_body(&mut self, payload: &'a [u8], segment: Option<fn(usize) -> PathSegment>) -> DecodeResult<'a, Vec<EVEValue<'a>>> {
+        // Large streams are sent zlib compressed
+        if payload.first() == Some(&0x78) {
+            return self.decode_compressed_body(payload, segment);
+        }
+
+        let (rest, header) = self.parse(payload, le_u8)?;
+        if header != 0x7e {
+            return Err(self.fail(payload, |offset, path| Error::InvalidHeader { header, offset, path }));
+        }
+        let (body, save_count) = self.parse(rest, le_u32)?;
+        log::trace!("Decoding {} len body", body.len());
+        log::trace!("Got save_count {}", save_count);
+
+        // The save table's slot map trails the values
+        let map_len = match (save_count as usize).checked_mul(4) {
+            Some(len) if len <= body.len() => len,
+            _ => return Err(self.fail(rest, |offset, path| Error::BadLength { length: save_count as usize, offset, path }))
+        };
+        let (mut payload, map) = body.split_at(body.len() - map_len);
+        if let Some(hints) = &mut self.hints {
+            let save_map = map.chunks(4).map(|slot| u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]])).collect();
+            hints.push(Hint::Stream { save_map });
+        }
+        let outer = std::mem::replace(&mut self.saved, SaveTable {
+            map,
+            objects: vec![None; save_count as usize]
+        });
+
+        let mut values = Vec::new();
+        while !payload.is_empty() {
+            self.check_count(payload, values.len() + 1)?;
+            if let Some(segment) = segment {
+                self.path.push(segment(values.len()));
+            }
+            let (rest, value) = self.decode_value(payload)?;
+            if segment.is_some() {
+                self.path.pop();
+            }
+
+            payload = rest;
+            values.push(value);
+        }
+
+        self.saved = outer;
+        Ok((payload, values))
+    }
+
+    fn decode_compressed_body(&mut self, payload: &'a [u8], segment: Option<fn(usize) -> PathSegment>) -> DecodeResult<'a, Vec<EVEValue<'a>>> {
+        // Read one byte past the limit to tell if it was hit
+        let mut inflated = Vec::new();
+        let max_len = self.limits.max_length.saturating_add(1) as u64;
+        if let Err(err) = ZlibDecoder::new(payload).take(max_len).read_to_end(&mut inflated) {
+            log::error!("Error inflating stream: {}", err);
+            return Err(self.fail(payload, |offset, path| Error::InvalidCompression { offset, path }));
+        }
+        if inflated.len() > self.limits.max_length {
+            return Err(self.exceeded(payload, Limit::Length));
+        }
+        log::trace!("Inflated {} bytes to {}", payload.len(), inflated.len());
+
+        // The inflated data only lives as long as this call, so everything
+        // decoded from it has to be copied out
+        let mut decoder = Decoder::new(&inflated, self.limits);
+        decoder.path = self.path.clone();
+        decoder.path.push(PathSegment::Inflated);
+        decoder.depth = self.depth;
+        decoder.work = self.work;
+        decoder.hints = self.hints.as_ref().map(|_| Vec::new());
+        let (_, values) = decoder.decode_payload_body(&inflated, segment)?;
+        self.work = decoder.work;
+
+        if let (Some(hints), Some(inner)) = (&mut self.hints, decoder.hints) {
+            hints.push(Hint::Compressed { inflated: inflated.clone(), deflated: payload.to_vec() });
+            hints.extend(inner);
+        }
+
+        let values = values.into_iter().map(EVEValue::into_owned).collect();
+        Ok((&payload[payload.len()..], values))
+    }
+
+    fn decode_value(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let start = payload;
+        self.charge(start, 1)?;
+        if self.depth >= self.limits.max_depth {
+            return Err(self.exceeded(start, Limit::Depth));
+        }
+
+        let (payload, header) = self.parse(payload, le_u8)?;
+        if header & UNKNOWN_FLAG != 0 {
+            log::warn!("Unknown flag set on opcode {:#04x}", header);
+        }
+
+        // Shared objects claim their slot before any objects nested inside them
+        let slot = if header & SHARED_FLAG != 0 {
+            Some(self.next_save_slot(start)?)
+        } else {
+            None
+        };
+
+        let hint = self.hints.as_mut().map(|hints| {
+            let long_size = opcodes::has_size(header & OPCODE_MASK) && payload.first() == Some(&0xff);
+            hints.push(Hint::Value { header, long_size, detail: Detail::None });
+            hints.len() - 1
+        });
+
+        let work = self.work;
+        self.depth += 1;
+        let (payload, value) = self.decode_opcode(header & OPCODE_MASK, payload, start)?;
+        self.depth -= 1;
+
+        if let (Some(hints), Some(hint), Some(recorded)) = (&mut self.hints, hint, self.detail.take()) {
+            if let Hint::Value { detail, .. } = &mut hints[hint] {
+                *detail = recorded;
+            }
+        }
+
+        if let Some(slot) = slot {
+            log::trace!("Saving object in slot {}", slot + 1);
+            self.saved.objects[slot] = Some((value.clone(), self.work - work));
+        }
+        Ok((payload, value))
+    }
+
+    pub(crate) fn decode_opcode(&mut self, opcode: u8, payload: &'a [u8], start: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        log::trace!("Got opcode {:#04x}", opcode);
+        match opcode {
+            _ if opcode == u8::from(EVEOpCode::None) => Ok((payload, EVEValue::None)),
+            _ if opcode == u8::from(EVEOpCode::Global) => self.decode_global(payload),
+            _ if opcode == u8::from(EVEOpCode::Long) => self.parse(payload, map(le_i32, |v| v.into())),
+            _ if opcode == u8::from(EVEOpCode::LongLong) => self.parse(payload, map(le_i64, |v| v.into())),
+            _ if opcode == u8::from(EVEOpCode::SignedShort) => self.parse(payload, map(le_i16, |v| v.into())),
+            _ if opcode == u8::from(EVEOpCode::Byte) => self.parse(payload, map(le_u8, |v| v.into())),
+            _ if opcode == u8::from(EVEOpCode::IntegerNegativeOne) => Ok((payload, EVEValue::Integer(-1))),
+            _ if opcode == u8::from(EVEOpCode::IntegerZero) => Ok((payload, EVEValue::Integer(0))),
+            _ if opcode == u8::from(EVEOpCode::IntegerOne) => Ok((payload, EVEValue::Integer(1))),
+            _ if opcode == u8::from(EVEOpCode::Real) => self.parse(payload, map(le_f64, |v| v.into())),
+            _ if opcode == u8::from(EVEOpCode::RealZero) => Ok((payload, EVEValue::Float(0.0))),
+            _ if opcode == u8::from(EVEOpCode::Buffer) => self.decode_buffer(payload),
+            _ if opcode == u8::from(EVEOpCode::EmptyString) => Ok((payload, EVEValue::String(Cow::Borrowed(&[])))),
+            _ if opcode == u8::from(EVEOpCode::CharString) => {
+                self.parse(payload, map(take(1usize), |c: &'a [u8]| EVEValue::String(Cow::Borrowed(c))))
+            },
+            _ if opcode == u8::from(EVEOpCode::ShortString) => self.decode_string(payload),
+            _ if opcode == u8::from(EVEOpCode::StringTableString) => self.decode_stringtable_string(payload),
+            _ if opcode == u8::from(EVEOpCode::WStringUCS2) => self.decode_wstring_ucs2(payload),
+            _ if opcode == u8::from(EVEOpCode::LongString) => self.decode_string(payload),
+            _ if opcode == u8::from(EVEOpCode::Tuple) => self.decode_tuple(payload),
+            _ if opcode == u8::from(EVEOpCode::List) => self.decode_list(payload),
+            _ if opcode == u8::from(EVEOpCode::Dict) => self.decode_dict(payload),
+            _ if opcode == u8::from(EVEOpCode::Object) => self.decode_object(payload),
+            _ if opcode == u8::from(EVEOpCode::SavedStreamElement) => self.decode_saved_stream_element(payload),
+            _ if opcode == u8::from(EVEOpCode::ChecksummedStream) => self.decode_checksummed_stream(payload),
+            _ if opcode == u8::from(EVEOpCode::True) => Ok((payload, EVEValue::Bool(true))),
+            _ if opcode == u8::from(EVEOpCode::False) => Ok((payload, EVEValue::Bool(false))),
+            _ if opcode == u8::from(EVEOpCode::Pickle) => self.decode_pickle(payload),
+            _ if opcode == u8::from(EVEOpCode::ObjectEx1) => self.decode_object_ex(payload, ObjectExKind::Ex1),
+            _ if opcode == u8::from(EVEOpCode::ObjectEx2) => self.decode_object_ex(payload, ObjectExKind::Ex2),
+            _ if opcode == u8::from(EVEOpCode::EmptyTuple) => Ok((payload, EVEValue::Tuple(vec![]))),
+            _ if opcode == u8::from(EVEOpCode::OneTuple) => self.decode_items(payload, 1, PathSegment::Tuple, EVEValue::Tuple),
+            _ if opcode == u8::from(EVEOpCode::EmptyList) => Ok((payload, EVEValue::List(vec![]))),
+            _ if opcode == u8::from(EVEOpCode::OneList) => self.decode_items(payload, 1, PathSegment::List, EVEValue::List),
+            _ if opcode == u8::from(EVEOpCode::EmptyUnicode) => Ok((payload, EVEValue::Unicode(Cow::Borrowed("")))),
+            _ if opcode == u8::from(EVEOpCode::UnicodeChar) => self.decode_unicode_char(payload),
+            _ if opcode == u8::from(EVEOpCode::PackedRow) => self.decode_packed_row(payload),
+            _ if opcode == u8::from(EVEOpCode::SubStream) => self.decode_sub_stream(payload),
+            _ if opcode == u8::from(EVEOpCode::TwoTuple) => self.decode_items(payload, 2, PathSegment::Tuple, EVEValue::Tuple),
+            _ if opcode == u8::from(EVEOpCode::WStringUTF8) => self.decode_wstring_utf8(payload),
+            _ if opcode == u8::from(EVEOpCode::VarInteger) => self.decode_var_int(payload),
+            x => self.invalid_opcode(x, start)
+        }
+    }
+
+    fn next_save_slot(&mut self, at: &'a [u8]) -> Result<usize, NomErr<Error>> {
+        let (map, index) = self.parse(self.saved.map, le_u32)?;
+        self.saved.map = map;
+
+        let index = index as usize;
+        if index == 0 || index > self.saved.objects.len() {
+            log::error!("Save table slot {} out of range", index);
+            return Err(self.fail(at, |offset, path| Error::UnknownSavedObject { index, offset, path }));
+        }
+        Ok(index - 1)
+    }
+
+    fn decode_saved_stream_element(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (rest, index) = self.decode_size(payload)?;
+        log::trace!("Loading saved object {}", index);
+        self.record(|| Detail::Reference(index));
+
+        let saved = index.checked_sub(1)
+            .and_then(|slot| self.saved.objects.get(slot))
+            .and_then(|object| object.as_ref());
+        match saved {
+            Some((object, work)) => {
+                // Every reference is a full copy, charge for it before making it
+                self.work = self.work.saturating_add(*work);
+                if self.work > self.limits.max_work {
+                    return Err(self.exceeded(payload, Limit::Work));
+                }
+                Ok((rest, object.clone()))
+            },
+            None => {
+                log::error!("Reference to unknown saved object {}", index);
+                Err(self.fail(payload, |offset, path| Error::UnknownSavedObject { index, offset, path }))
+            }
+        }
+    }
+
+    fn invalid_opcode<T>(&self, opcode: u8, payload: &'a [u8]) -> DecodeResult<'a, T> {
+        log::error!("Invalid opcode {:#04x} in net message", opcode);
+        Err(self.fail(payload, |offset, path| Error::InvalidOpcode { opcode, offset, path }))
+    }
+
+    fn decode_size(&self, payload: &'a [u8]) -> DecodeResult<'a, usize> {
+        self.parse(payload, alt((
+            map(preceded(tag([0xff]), le_u32),
+                |size| size as usize),
+            map(le_u8, |size| size as usize)
+        )))
+    }
+
+    fn decode_items(
+        &mut self,
+        mut payload: &'a [u8],
+        len: usize,
+        segment: fn(usize) -> PathSegment,
+        container: fn(Vec<EVEValue<'a>>) -> EVEValue<'a>
+    ) -> DecodeResult<'a, EVEValue<'a>> {
+        let mut vals = Vec::new();
+        for i in 0..len {
+            self.path.push(segment(i));
+            let (rest, val) = self.decode_value(payload)?;
+            self.path.pop();
+
+            payload = rest;
+            vals.push(val);
+        }
+        Ok((payload, container(vals)))
     }
-}
 
-fn decode_wstring_utf8<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, data) = length_count(self::decode_size, le_u8)(payload)?;
-    log::trace!("Decoding {} length wstring", data.len());
+    fn decode_tuple(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        log::trace!("Decoding tuple");
+        let (payload, len) = self.decode_count(payload)?;
+        self.decode_items(payload, len, PathSegment::Tuple, EVEValue::Tuple)
+    }
+
+    fn decode_list(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        log::trace!("Decoding list");
+        let (payload, len) = self.decode_count(payload)?;
+        self.decode_items(payload, len, PathSegment::List, EVEValue::List)
+    }
+
+    fn decode_string(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (payload, size) = self.decode_length(payload)?;
+        log::trace!("Decoding {} length string", size);
+
+        let (payload, value) = self.parse(payload, take(size))?;
+        log::trace!("Decoded string {:?}", String::from_utf8_lossy(value));
+        Ok((payload, EVEValue::String(Cow::Borrowed(value))))
+    }
+
+    fn decode_buffer(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (payload, size) = self.decode_length(payload)?;
+        log::trace!("Decoding {} length buffer", size);
+
+        let (payload, value) = self.parse(payload, take(size))?;
+        Ok((payload, EVEValue::Buffer(Cow::Borrowed(value))))
+    }
+
+    fn ucs2_to_string(&self, data: &[u16], at: &'a [u8]) -> Result<String, NomErr<Error>> {
+        // Every UCS-2 character takes at most 3 bytes of UTF-8
+        let mut buffer = vec![0u8; data.len() * 3];
+        let string = ucs2::decode(data, &mut buffer).ok()
+            .and_then(|len| {
+                buffer.truncate(len);
+                String::from_utf8(buffer).ok()
+            });
+
+        string.ok_or_else(|| {
+            log::warn!("Error decoding wstring in net message");
+            self.fail(at, |offset, path| Error::InvalidUcs2 { offset, path })
+        })
+    }
 
-    if let Ok(string) = String::from_utf8(data) {
+    fn decode_wstring_ucs2(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (data_start, size) = self.decode_length(payload)?;
+        let (payload, data) = self.parse(data_start, count(le_u16, size))?;
+        log::trace!("Decoding {} length wstring", data.len());
+
+        let string = self.ucs2_to_string(&data, data_start)?;
         log::trace!("Decoded string {}", string);
-        Ok((payload, EVEValue::OwnedString(string)))
-    } else {
-        log::warn!("Error decoding wstring in net message");
-        unimplemented!()
+        Ok((payload, EVEValue::Unicode(Cow::Owned(string))))
     }
-}
 
-fn decode_stringtable_string<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, index) = le_u8(payload)?;
-    if (index as usize) < DEFAULT_STRINGS.len() {
-        Ok((payload, EVEValue::String((*DEFAULT_STRINGS.get(index as usize).unwrap()).as_ref())))
-    } else {
-        unimplemented!()
+    fn decode_unicode_char(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (rest, c) = self.parse(payload, le_u16)?;
+        let string = self.ucs2_to_string(&[c], payload)?;
+        Ok((rest, EVEValue::Unicode(Cow::Owned(string))))
     }
-}
 
-fn decode_dict<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    log::trace!("Decoding dict");
-    let (payload, kvs) = length_count(self::decode_size, tuple((self::decode_value, self::decode_value)))(payload)?;
+    fn decode_wstring_utf8(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (data_start, size) = self.decode_length(payload)?;
+        let (payload, data) = self.parse(data_start, take(size))?;
+        log::trace!("Decoding {} length wstring", data.len());
 
-    let mut map = BTreeMap::new();
-    for (value, key) in kvs {
-        if let Ok(key) = key.try_into() {
-            map.insert(key, value);
+        if let Ok(string) = std::str::from_utf8(data) {
+            log::trace!("Decoded string {}", string);
+            Ok((payload, EVEValue::Unicode(Cow::Borrowed(string))))
         } else {
-            return Err(NomErr::Failure(Error::new(payload, ErrorKind::Fail)));
+            log::warn!("Error decoding wstring in net message");
+            Err(self.fail(data_start, |offset, path| Error::InvalidUtf8 { offset, path }))
         }
     }
-    Ok((payload, EVEValue::Dict(map)))
-}
 
-fn decode_object<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, typ) = self::decode_value(payload)?;
-    let (payload, arguments) = self::decode_value(payload)?;
-    Ok((payload, EVEValue::Object(vec![typ, arguments])))
-}
+    fn decode_stringtable_string(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        // String table indexes start at 1
+        let (rest, index) = self.parse(payload, le_u8)?;
+        match (index as usize).checked_sub(1).and_then(|i| DEFAULT_STRINGS.get(i)) {
+            Some(string) => {
+                self.record(|| Detail::StringTable(index));
+                Ok((rest, EVEValue::String(Cow::Borrowed(string.as_bytes()))))
+            },
+            None => {
+                log::error!("Unknown string table index {} in net message", index);
+                Err(self.fail(payload, |offset, path| Error::UnknownStringTableIndex { index, offset, path }))
+            }
+        }
+    }
 
-fn decode_var_int<'a>(payload: &'a [u8]) -> IResult<&'a [u8], EVEValue> {
-    let (payload, buffer) = length_count(self::decode_size, le_u8)(payload)?;
-    match buffer.len() {
-        1 => {
-            // CCP using 2 bytes to send 1...
-            map(le_u8, |v| EVEValue::BigInt(v as i128))(payload)
-        },
-        4 => {
-            let bytes: [u8; 4] = buffer.try_into().unwrap();
-            Ok((payload, EVEValue::BigInt(i32::from_le_bytes(bytes) as i128)))
-        },
-        8 => {
-            let bytes: [u8; 8] = buffer.try_into().unwrap();
-            Ok((payload, EVEValue::BigInt(i64::from_le_bytes(bytes) as i128)))
-        },
-        16 => {
-            let bytes: [u8; 16] = buffer.try_into().unwrap();
-            Ok((payload, EVEValue::BigInt(i128::from_le_bytes(bytes) as i128)))
-        },
-        _ => {
+    fn decode_dict(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        log::trace!("Decoding dict");
+        let (mut payload, len) = self.decode_count(payload)?;
+
+        let mut map = BTreeMap::new();
+        let mut keys = Vec::new();
+        for i in 0..len {
+            // Values come before their keys on the wire
+            self.path.push(PathSegment::DictValue(i));
+            let (rest, value) = self.decode_value(payload)?;
+            self.path.pop();
+
+            self.path.push(PathSegment::DictKey(i));
+            let key_start = rest;
+            let (rest, key) = self.decode_value(key_start)?;
+            if let Ok(key) = key.try_into() {
+                if self.hints.is_some() {
+                    keys.push(HashableEVEValue::clone(&key));
+                }
+                map.insert(key, value);
+            } else {
+                return Err(self.fail(key_start, |offset, path| Error::UnhashableKey { offset, path }));
+            }
+            self.path.pop();
+
+            payload = rest;
+        }
+
+        self.record(|| {
+            // Where each key sent ended up once sorted
+            let sorted: Vec<_> = map.keys().collect();
+            Detail::Order(keys.iter().filter_map(|key| sorted.binary_search(&key).ok()).collect())
+        });
+        Ok((payload, EVEValue::Dict(map)))
+    }
+
+    fn decode_object(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        self.path.push(PathSegment::ObjectClass);
+        let (rest, class) = self.decode_value(payload)?;
+        let class = self.class_name(class, payload)?;
+        self.path.pop();
+        log::trace!("Decoding {} object", class);
+
+        self.path.push(PathSegment::Object(class.to_string()));
+        let (payload, args) = self.decode_value(rest)?;
+        self.path.pop();
+        Ok((payload, EVEValue::Object { class, args: Box::new(args) }))
+    }
+
+    fn decode_object_ex(&mut self, payload: &'a [u8], kind: ObjectExKind) -> DecodeResult<'a, EVEValue<'a>> {
+        log::trace!("Decoding {:?} object", kind);
+        self.path.push(PathSegment::ObjectExHeader);
+        let (mut payload, header) = self.decode_value(payload)?;
+        self.path.pop();
+
+        let mut list = Vec::new();
+        loop {
+            let (rest, done) = self.take_marker(payload)?;
+            payload = rest;
+            if done {
+                break;
+            }
+
+            self.check_count(payload, list.len() + 1)?;
+            self.path.push(PathSegment::ObjectExList(list.len()));
+            let (rest, item) = self.decode_value(payload)?;
+            self.path.pop();
+
+            payload = rest;
+            list.push(item);
+        }
+
+        let mut dict = Vec::new();
+        loop {
+            let (rest, done) = self.take_marker(payload)?;
+            payload = rest;
+            if done {
+                break;
+            }
+
+            // Unlike dicts, these go key first
+            self.check_count(payload, dict.len() + 1)?;
+            self.path.push(PathSegment::ObjectExDictKey(dict.len()));
+            let (rest, key) = self.decode_value(payload)?;
+            self.path.pop();
+            self.path.push(PathSegment::ObjectExDictValue(dict.len()));
+            let (rest, value) = self.decode_value(rest)?;
+            self.path.pop();
+
+            payload = rest;
+            dict.push((key, value));
+        }
+
+        Ok((payload, EVEValue::ObjectEx {
+            kind,
+            header: Box::new(header),
+            list,
+            dict
+        }))
+    }
+
+    /// Consumes the marker ending an ObjectEx's items if it is next
+    fn take_marker(&self, payload: &'a [u8]) -> DecodeResult<'a, bool> {
+        match payload.first() {
+            Some(opcode) if *opcode == u8::from(EVEOpCode::Marker) => Ok((&payload[1..], true)),
+            Some(_) => Ok((payload, false)),
+            None => Err(self.fail(payload, |offset, path| Error::Truncated { offset, path }))
+        }
+    }
+
+    fn class_name(&self, class: EVEValue<'a>, at: &'a [u8]) -> Result<Cow<'a, str>, NomErr<Error>> {
+        let class = match class {
+            EVEValue::String(Cow::Borrowed(s)) => std::str::from_utf8(s).ok().map(Cow::Borrowed),
+            EVEValue::String(Cow::Owned(s)) => String::from_utf8(s).ok().map(Cow::Owned),
+            EVEValue::Unicode(s) | EVEValue::Global(s) => Some(s),
+            _ => return Err(self.fail(at, |offset, path| Error::InvalidClass { offset, path }))
+        };
+        class.ok_or_else(|| self.fail(at, |offset, path| Error::InvalidUtf8 { offset, path }))
+    }
+
+    fn decode_global(&self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (data_start, size) = self.decode_length(payload)?;
+        let (payload, data) = self.parse(data_start, take(size))?;
+        match std::str::from_utf8(data) {
+            Ok(name) => {
+                log::trace!("Decoded global {}", name);
+                Ok((payload, EVEValue::Global(Cow::Borrowed(name))))
+            },
+            Err(_) => Err(self.fail(data_start, |offset, path| Error::InvalidUtf8 { offset, path }))
+        }
+    }
+
+    fn decode_sub_stream(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (payload, size) = self.decode_length(payload)?;
+        let (payload, body) = self.parse(payload, take(size))?;
+        let (_, values) = self.decode_payload_body(body, Some(PathSegment::SubStream))?;
+        Ok((payload, EVEValue::SubStream(values)))
+    }
+
+    fn decode_packed_row(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        self.path.push(PathSegment::PackedRowHeader);
+        let (rest, header) = self.decode_value(payload)?;
+        let descriptor = match DBRowDescriptor::from_value(&header) {
+            Some(descriptor) => descriptor,
+            None => return Err(self.fail(payload, |offset, path| Error::InvalidRowDescriptor { offset, path }))
+        };
+        self.path.pop();
+        log::trace!("Decoding packed row with {} columns", descriptor.columns().len());
+
+        let long_size = rest.first() == Some(&0xff);
+        let (rest, size) = self.decode_length(rest)?;
+        let (mut payload, packed) = self.parse(rest, take(size))?;
+        let Some(mut data) = packed_row::zero_decompress(packed, descriptor.fixed_len()) else {
+            log::error!("Packed row data expands past {} bytes", descriptor.fixed_len());
+            return Err(self.fail(rest, |offset, path| Error::PackedRowOverflow { offset, path }));
+        };
+        // Trailing zeros are left off
+        data.resize(descriptor.fixed_len(), 0);
+
+        let mut values = descriptor.read_fixed(&data);
+        for i in descriptor.variable_columns().collect::<Vec<_>>() {
+            let column = &descriptor.columns()[i];
+            self.path.push(PathSegment::PackedRowColumn(column.name.to_string()));
+            let (rest, value) = self.decode_value(payload)?;
+            values[i] = match DBValue::from_variable(column.typ, value) {
+                Some(value) => value,
+                None => return Err(self.fail(payload, |offset, path| Error::InvalidColumnValue { offset, path }))
+            };
+            self.path.pop();
+
+            payload = rest;
+        }
+
+        self.record(|| Detail::PackedRow { long_size, packed: packed.to_vec() });
+        Ok((payload, EVEValue::PackedRow(PackedRow::from_parts(descriptor, values))))
+    }
+
+    fn decode_checksummed_stream(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (value_start, expected) = self.parse(payload, le_u32)?;
+        let (rest, value) = self.decode_value(value_start)?;
+
+        let encoded = &value_start[..value_start.len() - rest.len()];
+        let actual = adler2::adler32_slice(checksummed_data(encoded));
+        if actual != expected {
+            log::error!("Checksum {:#010x} does not match {:#010x}", actual, expected);
+            return Err(self.fail(payload, |offset, path| Error::ChecksumMismatch { expected, actual, offset, path }));
+        }
+        Ok((rest, EVEValue::ChecksummedStream { checksum: expected, value: Box::new(value) }))
+    }
+
+    fn decode_pickle(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (data_start, size) = self.decode_length(payload)?;
+        let (payload, data) = self.parse(data_start, take(size))?;
+        log::trace!("Decoding {} length pickle", size);
+
+        let mut path = self.path.clone();
+        path.push(PathSegment::Pickle);
+        let mut unpickler = Unpickler::new(data, self.offset(data_start), path, self.limits, self.work, self.depth);
+        let value = unpickler.load().map_err(NomErr::Failure)?;
+        self.work = unpickler.work();
+        self.record(|| Detail::Pickle(data.to_vec()));
+        Ok((payload, EVEValue::Pickled(Box::new(value))))
+    }
+
+    fn decode_var_int(&mut self, payload: &'a [u8]) -> DecodeResult<'a, EVEValue<'a>> {
+        let (data_start, size) = self.decode_size(payload)?;
+        let (payload, buffer) = self.parse(data_start, take(size))?;
+        if buffer.len() > 16 {
             log::error!("Unexpected VarInt length in packet {} {:?}", buffer.len(), buffer);
-            return Err(NomErr::Failure(Error::new(payload, ErrorKind::Fail)));
+            return Err(self.fail(data_start, |offset, path| Error::BadLength { length: size, offset, path }));
         }
+
+        // Little-endian two's complement, sign extend from the highest byte we got
+        let fill = match buffer.last() {
+            Some(byte) if byte & 0x80 != 0 => 0xff,
+            _ => 0x00
+        };
+        let mut bytes = [fill; 16];
+        bytes[..buffer.len()].copy_from_slice(buffer);
+        self.record(|| Detail::Width(size));
+        Ok((payload, EVEValue::BigInt(i128::from_le_bytes(bytes))))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tests::test_data;
+    use crate::error::{Limit, PathSegment};
+    use crate::packed_row::{DBColumn, DBType};
+    use crate::repr::PrettyPrinter;
+    use crate::value::HashableEVEValue;
+    use crate::tests::test_data::{self, with_header, with_length};
     use super::*;
 
-    fn decode_and_print(payload: &'static [u8]) -> IResult<&'_ [u8], Vec<EVEValue>> {
+    fn decode_and_print(payload: &'static [u8]) -> Result<Vec<EVEValue<'static>>, Error> {
         let res = decode_payload(payload);
-        log::trace!("{:?}", res);
+        match &res {
+            Ok(values) => {
+                let printer = PrettyPrinter::new();
+                for value in values {
+                    log::trace!("{}", printer.print(value));
+                }
+            },
+            Err(err) => log::trace!("{:?}", err)
+        }
         res
     }
 
@@ -222,4 +806,321 @@ mod tests {
     fn test_macho_net_get_time() {
         assert!(decode_and_print(test_data::MACHONET_GETTIME).is_ok());
     }
+
+    #[test_log::test]
+    fn test_string_table_lookup() {
+        let values = decode_and_print(test_data::MACHONET_GETTIME).unwrap();
+        if let [EVEValue::Object { class, .. }] = values.as_slice() {
+            assert_eq!(class, "macho.CallReq");
+        } else {
+            panic!("Expected a single object, got {:?}", values);
+        }
+    }
+
+    #[test_log::test]
+    fn test_bad_length() {
+        let err = decode_payload(&test_data::PACKET1[..20]).unwrap_err();
+        assert_eq!(err, Error::BadLength { length: 0x38, offset: 0, path: ValuePath::default() });
+    }
+
+    #[test_log::test]
+    fn test_truncated() {
+        let mut payload = test_data::PACKET1[..20].to_vec();
+        payload[..4].copy_from_slice(&16u32.to_le_bytes());
+        // Cuts off the Real at offset 20
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::Truncated { offset: 20, .. }), "{}", err);
+        assert_eq!(err.path().segments(), [PathSegment::Tuple(3)]);
+    }
+
+    #[test_log::test]
+    fn test_invalid_opcode_path() {
+        // (None, {0x3e: 'a'})
+        let payload = with_header(&[0x2c, 0x01, 0x16, 0x01, 0x3e, 0x10, 0x01, b'a']);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err, Error::InvalidOpcode {
+            opcode: 0x3e,
+            offset: 13,
+            path: ValuePath::new(vec![PathSegment::Tuple(1), PathSegment::DictValue(0)])
+        });
+        assert_eq!(err.to_string(), "invalid opcode 0x3e at offset 13 (Tuple[1].Dict[0])");
+    }
+
+    #[test_log::test]
+    fn test_unhashable_key() {
+        let payload = with_header(&[0x16, 0x01, 0x01, 0x26]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::UnhashableKey { offset: 12, .. }), "{}", err);
+
+        // A tuple is only hashable if everything in it is
+        let payload = with_header(&[0x16, 0x01, 0x01, 0x2c, 0x09, 0x26]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::UnhashableKey { offset: 12, .. }), "{}", err);
+
+        // (typeID, flag) keys
+        let payload = with_header(&[0x16, 0x01, 0x01, 0x2c, 0x04, 0x22, 0x02, 0x00, 0x00, 0x06, 0x04]);
+        let values = decode_payload(&payload).unwrap();
+        let key = HashableEVEValue::Tuple(vec![HashableEVEValue::Integer(546), HashableEVEValue::Byte(4)]);
+        assert_eq!(values, [EVEValue::Dict([(key, EVEValue::None)].into_iter().collect())]);
+    }
+
+    #[test_log::test]
+    fn test_bad_strings() {
+        let payload = with_header(&[0x11, 0x00]);
+        assert!(matches!(decode_payload(&payload), Err(Error::UnknownStringTableIndex { index: 0, offset: 10, .. })));
+
+        let payload = with_header(&[0x2e, 0x02, 0xc3, 0x28]);
+        assert!(matches!(decode_payload(&payload), Err(Error::InvalidUtf8 { offset: 11, .. })));
+
+        let payload = with_header(&[0x12, 0x01, 0x00, 0xd8]);
+        assert!(matches!(decode_payload(&payload), Err(Error::InvalidUcs2 { offset: 11, .. })));
+    }
+
+    #[test_log::test]
+    fn test_saved_stream_element() {
+        // ('hello', <saved 1>) with 'hello' flagged as shared
+        let payload = [
+            0x13, 0x00, 0x00, 0x00,
+            0x7e, 0x01, 0x00, 0x00, 0x00,
+            0x2c, 0x50, 0x05, b'h', b'e', b'l', b'l', b'o', 0x1b, 0x01,
+            0x01, 0x00, 0x00, 0x00
+        ];
+        let values = decode_payload(&payload).unwrap();
+        let hello: EVEValue = b"hello"[..].into();
+        assert_eq!(values, [EVEValue::Tuple(vec![hello.clone(), hello])]);
+    }
+
+    #[test_log::test]
+    fn test_unknown_saved_object() {
+        let payload = with_header(&[0x2c, 0x01, 0x1b, 0x01]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err, Error::UnknownSavedObject {
+            index: 1,
+            offset: 12,
+            path: ValuePath::new(vec![PathSegment::Tuple(1)])
+        });
+    }
+
+    #[test_log::test]
+    fn test_object_path() {
+        // macho.CallReq object with a truncated argument tuple
+        let payload = with_header(&[0x17, 0x11, 0x2e, 0x14, 0x02, 0x01]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err.to_string(), "truncated input at offset 15 (Object(macho.CallReq).Tuple[1])");
+
+        let payload = with_header(&[0x17, 0x09, 0x01]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::InvalidClass { offset: 10, .. }), "{}", err);
+    }
+
+    #[test_log::test]
+    fn test_object_ex() {
+        // util.Rowset style object, header (util.Rowset, ()) with one list item
+        // and one dict item
+        let payload = with_header(&[
+            0x22, 0x2c, 0x02, 0x0b, b'u', b't', b'i', b'l', b'.', b'R', b'o', b'w', b's', b'e', b't', 0x24,
+            0x09, 0x2d,
+            0x11, 0x2b, 0x26, 0x2d
+        ]);
+        let values = decode_payload(&payload).unwrap();
+        assert_eq!(values, [EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex1,
+            header: Box::new(EVEValue::Tuple(vec![EVEValue::Global("util.Rowset".into()), EVEValue::Tuple(vec![])])),
+            list: vec![EVEValue::Integer(1)],
+            dict: vec![(b"lines"[..].into(), EVEValue::List(vec![]))]
+        }]);
+
+        // Missing the dict value and final marker
+        let payload = with_header(&[0x23, 0x01, 0x2d, 0x11, 0x2a]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err.to_string(), "truncated input at offset 14 (ObjectEx.Dict[0])");
+    }
+
+    #[test_log::test]
+    fn test_packed_row_errors() {
+        let payload = with_header(&[0x2a, 0x01, 0x00]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err.to_string(), "packed row header is not a DBRowDescriptor at offset 10 (PackedRow.header)");
+
+        // Descriptor with a single string column sent as an integer
+        let header = DBRowDescriptor::new(vec![DBColumn::new("name", DBType::String)]).to_value();
+        let mut body = vec![0x2a];
+        body.extend_from_slice(&crate::encode::encode_payload(&[header]).unwrap()[9..]);
+        body.extend_from_slice(&[0x00, 0x09]);
+        let payload = with_header(&body);
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::InvalidColumnValue { .. }), "{}", err);
+        assert_eq!(err.path().to_string(), "PackedRow.name");
+
+        // A single I4 column is 4 bytes, but the data expands to 8 zeros
+        // and then to 8 more for every 0xff
+        let header = DBRowDescriptor::new(vec![DBColumn::new("id", DBType::I4)]).to_value();
+        let mut body = vec![0x2a];
+        body.extend_from_slice(&crate::encode::encode_payload(&[header]).unwrap()[9..]);
+        body.extend_from_slice(&[0x02, 0xff, 0xff]);
+        let payload = with_header(&body);
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::PackedRowOverflow { .. }), "{}", err);
+    }
+
+    #[test_log::test]
+    fn test_compressed_stream() {
+        use std::io::Write;
+        use flate2::{Compression, write::ZlibEncoder};
+
+        let deflate = |data: &[u8]| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        // A compressed stream holding a compressed sub stream
+        let inner = deflate(&[0x7e, 0x00, 0x00, 0x00, 0x00, 0x10, 0x03, b'a', b'b', b'c']);
+        let mut outer = vec![0x7e, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x09, 0x2b, inner.len() as u8];
+        outer.extend_from_slice(&inner);
+        let body = deflate(&outer);
+        let payload = with_length(&body);
+
+        let values = decode_payload(&payload).unwrap();
+        assert_eq!(values, [EVEValue::Tuple(vec![
+            EVEValue::Integer(1),
+            EVEValue::SubStream(vec![b"abc"[..].into()])
+        ])]);
+
+        // Offsets inside compressed data are into the inflated stream
+        let body = deflate(&[0x7e, 0x00, 0x00, 0x00, 0x00, 0x14, 0x02, 0x09, 0x3e]);
+        let payload = with_length(&body);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err.to_string(), "invalid opcode 0x3e at offset 8 (zlib.Tuple[1])");
+
+        let payload = [0x04, 0x00, 0x00, 0x00, 0x78, 0x9c, 0xff, 0xff];
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::InvalidCompression { offset: 4, .. }), "{}", err);
+    }
+
+    #[test_log::test]
+    fn test_checksummed_stream() {
+        // Adler-32 covers the sub stream's body
+        let payload = with_header(&[0x1c, 0x88, 0x00, 0x03, 0x03, 0x2b, 0x06, 0x7e, 0x00, 0x00, 0x00, 0x00, 0x09]);
+        let values = decode_payload(&payload).unwrap();
+        assert_eq!(values, [EVEValue::ChecksummedStream {
+            checksum: 0x03030088,
+            value: Box::new(EVEValue::SubStream(vec![EVEValue::Integer(1)]))
+        }]);
+
+        let payload = with_header(&[0x1c, 0x88, 0x00, 0x03, 0x03, 0x2b, 0x06, 0x7e, 0x00, 0x00, 0x00, 0x00, 0x08]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err, Error::ChecksumMismatch {
+            expected: 0x03030088,
+            actual: 0x03020087,
+            offset: 10,
+            path: ValuePath::default()
+        });
+    }
+
+    #[test_log::test]
+    fn test_pickle() {
+        let payload = with_header(&[0x21, 0x08, 0x80, 0x02, 0x4b, 0x01, 0x4b, 0x02, 0x86, 0x2e]);
+        let values = decode_payload(&payload).unwrap();
+        assert_eq!(values, [EVEValue::Pickled(Box::new(EVEValue::Tuple(vec![1i64.into(), 2i64.into()])))]);
+
+        let payload = with_header(&[0x14, 0x01, 0x21, 0x04, 0x80, 0x02, 0x65, 0x2e]);
+        let err = decode_payload(&payload).unwrap_err();
+        assert_eq!(err.to_string(), "malformed pickle at offset 15 (Tuple[0].Pickle)");
+    }
+
+    #[test_log::test]
+    fn test_limits() {
+        let limit = |payload: &[u8], limits| match decode_payload_with_limits(payload, limits) {
+            Err(Error::LimitExceeded { limit, .. }) => Some(limit),
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => None
+        };
+
+        let mut nested = vec![0x25; 70];
+        nested.push(0x01);
+        let payload = with_header(&nested);
+        assert_eq!(limit(&payload, DecodeLimits::default()), Some(Limit::Depth));
+        assert_eq!(limit(&payload, DecodeLimits { max_depth: 71, ..Default::default() }), None);
+
+        // Pickles count from where they sit in the stream, (pickle((1, 2)),)
+        // nests the ints four deep
+        let payload = with_header(&[0x14, 0x01, 0x21, 0x08, 0x80, 0x02, 0x4b, 0x01, 0x4b, 0x02, 0x86, 0x2e]);
+        assert_eq!(limit(&payload, DecodeLimits { max_depth: 3, ..Default::default() }), Some(Limit::Depth));
+        assert_eq!(limit(&payload, DecodeLimits { max_depth: 4, ..Default::default() }), None);
+
+        let payload = with_header(&[0x14, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(limit(&payload, DecodeLimits::default()), Some(Limit::Elements));
+        let payload = with_header(&[0x13, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(limit(&payload, DecodeLimits::default()), Some(Limit::Length));
+        let payload = with_header(&[0x14, 0x03, 0x01, 0x01, 0x01]);
+        assert_eq!(limit(&payload, DecodeLimits { max_elements: 2, ..Default::default() }), Some(Limit::Elements));
+    }
+
+    #[test_log::test]
+    fn test_work_limit() {
+        // Each list holds ten references to the one before it
+        let mut body = vec![0x7e, 0x03, 0x00, 0x00, 0x00, 0x14, 0x03, 0x55, 0x0a];
+        body.extend_from_slice(&[0x01; 10]);
+        for slot in 1..=2 {
+            body.extend_from_slice(&[0x55, 0x0a]);
+            for _ in 0..10 {
+                body.extend_from_slice(&[0x1b, slot]);
+            }
+        }
+        body.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+        let payload = with_length(&body);
+
+        assert!(decode_payload(&payload).is_ok());
+        let err = decode_payload_with_limits(&payload, DecodeLimits { max_work: 1000, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { limit: Limit::Work, .. }), "{}", err);
+    }
+
+    #[test_log::test]
+    fn test_doubling_references() {
+        // Each saved tuple holds two references to the one before it, so
+        // the last of 40 would be 2^40 values
+        const LEVELS: u8 = 40;
+        let mut body = vec![0x7e, LEVELS, 0x00, 0x00, 0x00, 0x6c, 0x01, 0x01];
+        for slot in 1..LEVELS {
+            body.extend_from_slice(&[0x6c, 0x1b, slot, 0x1b, slot]);
+        }
+        for slot in 1..=LEVELS as u32 {
+            body.extend_from_slice(&slot.to_le_bytes());
+        }
+        let payload = with_length(&body);
+
+        let start = std::time::Instant::now();
+        let err = decode_payload(&payload).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { limit: Limit::Work, .. }), "{}", err);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1), "took {:?}", start.elapsed());
+    }
+
+    #[test_log::test]
+    fn test_inflated_length_limit() {
+        use std::io::Write;
+        use flate2::{Compression, write::ZlibEncoder};
+
+        let mut body = vec![0x7e, 0x00, 0x00, 0x00, 0x00, 0x13, 0xff, 0x00, 0x10, 0x00, 0x00];
+        body.extend_from_slice(&[0x00; 0x1000]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+        let body = encoder.finish().unwrap();
+        let payload = with_length(&body);
+
+        assert!(decode_payload(&payload).is_ok());
+        let err = decode_payload_with_limits(&payload, DecodeLimits { max_length: 0x800, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { limit: Limit::Length, offset: 4, .. }), "{}", err);
+    }
+
+    #[test_log::test]
+    fn test_decode_owned() {
+        let payload = test_data::PACKET2.to_vec();
+        let values = decode_payload_owned(&payload).unwrap();
+        drop(payload);
+
+        let expected = decode_payload(test_data::PACKET2).unwrap();
+        let handle = std::thread::spawn(move || values);
+        assert_eq!(handle.join().unwrap(), expected);
+    }
 }
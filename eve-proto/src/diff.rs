@@ -0,0 +1,196 @@
+use std::fmt;
+
+use crate::value::EVEValue;
+
+/// Longest value shown in a difference
+const VALUE_WIDTH: usize = 80;
+
+/// One place where two sets of values differ, with a Python like path to
+/// it such as `[0][1]['header'].args[2]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Changed { path: String, left: EVEValue<'static>, right: EVEValue<'static> },
+    /// Only on the left
+    Removed { path: String, value: EVEValue<'static> },
+    /// Only on the right
+    Added { path: String, value: EVEValue<'static> }
+}
+
+/// Compares two payloads value by value.
+///
+/// Values are compared by what they mean on the Python side, so an integer
+/// is the same whatever width it was sent with and checksummed streams and
+/// pickles are looked through.
+pub fn diff(left: &[EVEValue], right: &[EVEValue]) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_items(&mut differences, "", left, right, |i| format!("[{}]", i));
+    differences
+}
+
+fn diff_items(
+    differences: &mut Vec<Difference>,
+    path: &str,
+    left: &[EVEValue],
+    right: &[EVEValue],
+    segment: impl Fn(usize) -> String
+) {
+    for i in 0..left.len().max(right.len()) {
+        let path = format!("{}{}", path, segment(i));
+        match (left.get(i), right.get(i)) {
+            (Some(left), Some(right)) => diff_value(differences, path, left, right),
+            (Some(value), None) => differences.push(Difference::Removed { path, value: value.clone().into_owned() }),
+            (None, Some(value)) => differences.push(Difference::Added { path, value: value.clone().into_owned() }),
+            (None, None) => unreachable!("index is within one of them")
+        }
+    }
+}
+
+fn diff_value(differences: &mut Vec<Difference>, path: String, left: &EVEValue, right: &EVEValue) {
+    match (left, right) {
+        (EVEValue::ChecksummedStream { value: left, .. } | EVEValue::Pickled(left), right) => {
+            diff_value(differences, path, left, right)
+        },
+        (left, EVEValue::ChecksummedStream { value: right, .. } | EVEValue::Pickled(right)) => {
+            diff_value(differences, path, left, right)
+        },
+        (EVEValue::Tuple(left), EVEValue::Tuple(right)) |
+        (EVEValue::List(left), EVEValue::List(right)) |
+        (EVEValue::SubStream(left), EVEValue::SubStream(right)) => {
+            diff_items(differences, &path, left, right, |i| format!("[{}]", i))
+        },
+        (EVEValue::Dict(left), EVEValue::Dict(right)) => {
+            for (key, value) in left {
+                let path = format!("{}[{}]", path, key);
+                match right.get(key) {
+                    Some(other) => diff_value(differences, path, value, other),
+                    None => differences.push(Difference::Removed { path, value: value.clone().into_owned() })
+                }
+            }
+            for (key, value) in right.iter().filter(|(key, _)| !left.contains_key(key)) {
+                let path = format!("{}[{}]", path, key);
+                differences.push(Difference::Added { path, value: value.clone().into_owned() });
+            }
+        },
+        (EVEValue::Object { class: left_class, args: left }, EVEValue::Object { class: right_class, args: right })
+            if left_class == right_class => {
+            diff_value(differences, format!("{}.args", path), left, right)
+        },
+        (
+            EVEValue::ObjectEx { kind: left_kind, header: left_header, list: left_list, dict: left_dict },
+            EVEValue::ObjectEx { kind: right_kind, header: right_header, list: right_list, dict: right_dict }
+        ) if left_kind == right_kind => {
+            diff_value(differences, format!("{}.header", path), left_header, right_header);
+            diff_items(differences, &path, left_list, right_list, |i| format!(".list[{}]", i));
+            for i in 0..left_dict.len().max(right_dict.len()) {
+                match (left_dict.get(i), right_dict.get(i)) {
+                    (Some((left_key, left)), Some((right_key, right))) if left_key.to_string() == right_key.to_string() => {
+                        diff_value(differences, format!("{}.dict[{}]", path, left_key), left, right)
+                    },
+                    (left, right) => {
+                        let path = format!("{}.dict[{}]", path, i);
+                        if let Some((key, value)) = left {
+                            let value = EVEValue::Tuple(vec![key.clone(), value.clone()]).into_owned();
+                            differences.push(Difference::Removed { path: path.clone(), value });
+                        }
+                        if let Some((key, value)) = right {
+                            let value = EVEValue::Tuple(vec![key.clone(), value.clone()]).into_owned();
+                            differences.push(Difference::Added { path, value });
+                        }
+                    }
+                }
+            }
+        },
+        (EVEValue::PackedRow(left), EVEValue::PackedRow(right)) if left.descriptor() == right.descriptor() => {
+            for (column, (left, right)) in left.descriptor().columns().iter().zip(left.values().iter().zip(right.values())) {
+                diff_value(differences, format!("{}.{}", path, column.name), &left.to_value(), &right.to_value());
+            }
+        },
+        // Anything else is the same if Python would print it the same
+        (left, right) => {
+            if left.to_string() != right.to_string() {
+                differences.push(Difference::Changed {
+                    path,
+                    left: left.clone().into_owned(),
+                    right: right.clone().into_owned()
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let short = |value: &EVEValue| {
+            let repr = value.to_string();
+            if repr.chars().count() > VALUE_WIDTH {
+                let mut repr: String = repr.chars().take(VALUE_WIDTH - 3).collect();
+                repr.push_str("...");
+                repr
+            } else {
+                repr
+            }
+        };
+
+        match self {
+            Difference::Changed { path, left, right } => write!(f, "~ {}: {} != {}", path, short(left), short(right)),
+            Difference::Removed { path, value } => write!(f, "- {}: {}", path, short(value)),
+            Difference::Added { path, value } => write!(f, "+ {}: {}", path, short(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::value::HashableEVEValue;
+    use super::*;
+
+    fn dict(entries: &[(&str, EVEValue<'static>)]) -> EVEValue<'static> {
+        let map: BTreeMap<_, _> = entries.iter()
+            .map(|(key, value)| (HashableEVEValue::String(key.as_bytes().to_vec().into()), value.clone()))
+            .collect();
+        EVEValue::Dict(map)
+    }
+
+    #[test_log::test]
+    fn test_diff() {
+        let left = [
+            EVEValue::Tuple(vec![
+                EVEValue::Byte(1),
+                dict(&[("a", EVEValue::None), ("b", EVEValue::Bool(true))]),
+                EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Tuple(vec![EVEValue::Integer(1)])) }
+            ]),
+            EVEValue::None
+        ];
+        let right = [
+            EVEValue::Tuple(vec![
+                // Same value, different width
+                EVEValue::Integer(1),
+                dict(&[("b", EVEValue::Bool(false)), ("c", EVEValue::Float(1.5))]),
+                EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Tuple(vec![EVEValue::Integer(2)])) }
+            ])
+        ];
+
+        let differences: Vec<_> = diff(&left, &right).iter().map(ToString::to_string).collect();
+        assert_eq!(differences, [
+            "- [0][1]['a']: None",
+            "~ [0][1]['b']: True != False",
+            "+ [0][1]['c']: 1.5",
+            "~ [0][2].args[0]: 1 != 2",
+            "- [1]: None"
+        ]);
+        assert!(diff(&left, &left).is_empty());
+    }
+
+    #[test_log::test]
+    fn test_diff_types() {
+        let left = [EVEValue::String(b"a"[..].into()), EVEValue::List(vec![EVEValue::None])];
+        let right = [
+            EVEValue::Unicode("a".into()),
+            EVEValue::ChecksummedStream { checksum: 1, value: Box::new(EVEValue::Tuple(vec![EVEValue::None])) }
+        ];
+        let differences: Vec<_> = diff(&left, &right).iter().map(ToString::to_string).collect();
+        assert_eq!(differences, ["~ [0]: 'a' != u'a'", "~ [1]: [None] != (None,)"]);
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use nom::Err as NomErr;
+use crate::decode::{DecodeLimits, Decoder};
+use crate::error::{Error, Limit, ValuePath};
+use crate::opcodes::{self, EVEOpCode, OPCODE_MASK, SHARED_FLAG, UNKNOWN_FLAG};
+use crate::packed_row::{self, DBRowDescriptor, DBValue, PackedRow};
+use crate::repr::PrettyPrinter;
+use crate::value::{EVEValue, ObjectExKind};
+
+/// Most bytes shown in hex on one line
+const HEX_BYTES: usize = 8;
+/// Longest decoded value shown next to a line
+const NOTE_WIDTH: usize = 60;
+/// Deepest containers shown in a decoded value
+const NOTE_DEPTH: usize = 4;
+
+/// An annotated listing of the bytes in a payload, one line per value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Disassembly {
+    pub lines: Vec<Line>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Where the line starts, inside the inflated data for compressed streams
+    pub offset: usize,
+    /// Every byte the line covers, not counting nested values
+    pub bytes: Vec<u8>,
+    /// How many containers the line is nested in
+    pub depth: usize,
+    pub kind: LineKind,
+    /// The decoded value, or what went wrong
+    pub note: String
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    /// Length prefix of the payload
+    Length(u32),
+    /// Start of a stream, with how many values it saves for later reference
+    StreamHeader { save_count: u32 },
+    /// A zlib compressed stream, the lines nested under it list the inflated data
+    Compressed { inflated: usize },
+    Value {
+        opcode: EVEOpCode,
+        /// The opcode byte with its flags
+        header: u8,
+        size: Option<usize>,
+        /// Save table slot the value is stored in, when shared
+        slot: Option<usize>
+    },
+    /// The zero compressed data of a packed row
+    PackedData { size: usize },
+    /// Ends the list or dict items of an ObjectEx
+    Marker,
+    /// The save table slots trailing a stream
+    SaveMap(Vec<u32>),
+    /// A byte that isn't any opcode, skipped over
+    Unknown { header: u8 },
+    /// The input ended early or couldn't be followed any further
+    Truncated
+}
+
+/// Lists every value in a payload along with the bytes it came from.
+///
+/// Unlike `decode_payload` this never fails, unknown opcodes are marked and
+/// skipped and anything that can't be followed ends the listing early.
+pub fn disassemble(payload: &[u8]) -> Disassembly {
+    disassemble_with_limits(payload, DecodeLimits::default())
+}
+
+/// Lists a payload like `disassemble`, leaving out the values that would
+/// go past `limits` rather than failing
+pub fn disassemble_with_limits(payload: &[u8], limits: DecodeLimits) -> Disassembly {
+    let mut disassembler = Disassembler::new(payload, 0, limits);
+    disassembler.payload(payload);
+    Disassembly { lines: disassembler.lines }
+}
+
+struct Disassembler<'a> {
+    base: &'a [u8],
+    limits: DecodeLimits,
+    // Decodes the values that don't hold any others
+    decoder: Decoder<'a>,
+    // Each saved value with the work it took, charged again for every reference
+    saved: Vec<Option<(EVEValue<'a>, usize)>>,
+    // Values walked so far, like `DecodeLimits::max_work`
+    work: usize,
+    save_map: &'a [u8],
+    lines: Vec<Line>,
+    depth: usize,
+    // Set once the current stream can't be followed any further
+    stopped: bool
+}
+
+type Walked<'a> = (&'a [u8], Option<EVEValue<'a>>);
+
+impl<'a> Disassembler<'a> {
+    fn new(base: &'a [u8], depth: usize, limits: DecodeLimits) -> Self {
+        Self {
+            base,
+            limits,
+            decoder: Decoder::new(base, limits),
+            saved: Vec::new(),
+            work: 0,
+            save_map: &[],
+            lines: Vec::new(),
+            depth,
+            stopped: false
+        }
+    }
+
+    fn offset(&self, at: &'a [u8]) -> usize {
+        at.as_ptr() as usize - self.base.as_ptr() as usize
+    }
+
+    fn push(&mut self, at: &'a [u8], rest: &'a [u8], kind: LineKind, note: String) {
+        self.lines.push(Line {
+            offset: self.offset(at),
+            bytes: at[..at.len() - rest.len()].to_vec(),
+            depth: self.depth,
+            kind,
+            note
+        });
+    }
+
+    /// Marks everything left as unreadable
+    fn stop(&mut self, at: &'a [u8], note: &str) -> &'a [u8] {
+        let rest = &at[at.len()..];
+        if !self.stopped {
+            self.push(at, rest, LineKind::Truncated, note.to_string());
+            self.stopped = true;
+        }
+        rest
+    }
+
+    fn payload(&mut self, payload: &'a [u8]) {
+        let Some((len, body)) = payload.split_first_chunk::<4>() else {
+            self.stop(payload, "no length");
+            return;
+        };
+
+        let len = u32::from_le_bytes(*len);
+        let note = if body.len() != len as usize {
+            format!("but {} bytes follow", body.len())
+        } else {
+            String::new()
+        };
+        self.push(payload, body, LineKind::Length(len), note);
+        self.stream(body);
+    }
+
+    fn stream(&mut self, body: &'a [u8]) -> Vec<Option<EVEValue<'a>>> {
+        if body.first() == Some(&0x78) {
+            return self.compressed(body);
+        }
+
+        let outer_stopped = std::mem::replace(&mut self.stopped, false);
+        let values = self.stream_values(body);
+        self.stopped = outer_stopped;
+        values
+    }
+
+    fn stream_values(&mut self, body: &'a [u8]) -> Vec<Option<EVEValue<'a>>> {
+        let Some((header, rest)) = body.split_first() else {
+            self.stop(body, "empty stream");
+            return Vec::new();
+        };
+        if *header != 0x7e {
+            self.push(body, rest, LineKind::Unknown { header: *header }, "expected a stream header".to_string());
+        }
+        let Some((save_count, rest)) = rest.split_first_chunk::<4>() else {
+            self.stop(body, "no save count");
+            return Vec::new();
+        };
+
+        let save_count = u32::from_le_bytes(*save_count);
+        let (mut data, map) = match (save_count as usize).checked_mul(4) {
+            Some(len) if len <= rest.len() => rest.split_at(rest.len() - len),
+            _ => (rest, &rest[rest.len()..])
+        };
+        let note = if map.len() / 4 != save_count as usize {
+            "save map doesn't fit".to_string()
+        } else {
+            String::new()
+        };
+        let start = if *header == 0x7e { body } else { &body[1..] };
+        self.push(start, rest, LineKind::StreamHeader { save_count }, note);
+
+        let outer_saved = std::mem::replace(&mut self.saved, vec![None; map.len() / 4]);
+        let outer_map = std::mem::replace(&mut self.save_map, map);
+        self.depth += 1;
+        let mut values = Vec::new();
+        while !data.is_empty() && !self.stopped {
+            let (rest, value) = self.value(data);
+            data = rest;
+            values.push(value);
+        }
+        self.depth -= 1;
+
+        if !map.is_empty() {
+            let slots = map.chunks(4).map(|slot| u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]])).collect();
+            self.push(map, &map[map.len()..], LineKind::SaveMap(slots), String::new());
+        }
+        self.saved = outer_saved;
+        self.save_map = outer_map;
+        values
+    }
+
+    fn compressed(&mut self, body: &'a [u8]) -> Vec<Option<EVEValue<'a>>> {
+        // Read one byte past the limit to tell if it was hit, like the decoder
+        let mut inflated = Vec::new();
+        let max_len = self.limits.max_length.saturating_add(1) as u64;
+        let rest = &body[body.len()..];
+        if ZlibDecoder::new(body).take(max_len).read_to_end(&mut inflated).is_err() {
+            self.push(body, rest, LineKind::Compressed { inflated: inflated.len() }, "invalid zlib data".to_string());
+            return Vec::new();
+        }
+        if inflated.len() > self.limits.max_length {
+            let err = Error::LimitExceeded { limit: Limit::Length, offset: self.offset(body), path: ValuePath::default() };
+            self.push(body, rest, LineKind::Compressed { inflated: self.limits.max_length }, format!("invalid, {}", err));
+            return Vec::new();
+        }
+        self.push(body, rest, LineKind::Compressed { inflated: inflated.len() }, "offsets below are in the inflated data".to_string());
+
+        let mut inner = Disassembler::new(&inflated, self.depth + 1, self.limits);
+        inner.work = self.work;
+        let values = inner.stream(&inflated)
+            .into_iter()
+            .map(|value| value.map(EVEValue::into_owned))
+            .collect();
+        self.work = inner.work;
+        self.lines.extend(inner.lines);
+        values
+    }
+
+    fn value(&mut self, data: &'a [u8]) -> Walked<'a> {
+        if self.stopped {
+            return (&data[data.len()..], None);
+        }
+        if self.depth > self.limits.max_depth {
+            return (self.stop(data, "nested too deep"), None);
+        }
+        let Some((&header, rest)) = data.split_first() else {
+            return (self.stop(data, "expected a value"), None);
+        };
+        let work = self.work;
+        self.work = self.work.saturating_add(1);
+        let opcode = match EVEOpCode::try_from(header & OPCODE_MASK) {
+            Ok(EVEOpCode::Marker) => {
+                self.push(data, rest, LineKind::Marker, "outside an ObjectEx".to_string());
+                return (rest, None);
+            },
+            Ok(opcode) => opcode,
+            Err(_) => {
+                self.push(data, rest, LineKind::Unknown { header }, String::new());
+                return (rest, None);
+            }
+        };
+
+        // Shared values claim their slot before anything nested in them
+        let slot = if header & SHARED_FLAG != 0 {
+            self.save_map.split_first_chunk::<4>().map(|(slot, map)| {
+                self.save_map = map;
+                u32::from_le_bytes(*slot) as usize
+            })
+        } else {
+            None
+        };
+
+        let kind = |size| LineKind::Value { opcode, header, size, slot };
+        let (rest, value) = match opcode {
+            EVEOpCode::Tuple | EVEOpCode::List | EVEOpCode::Dict => {
+                let Some((items, size)) = read_size(rest) else {
+                    return (self.stop(data, "no size"), None);
+                };
+                self.push(data, items, kind(Some(size)), String::new());
+                match opcode {
+                    EVEOpCode::Tuple => self.items(items, size, EVEValue::Tuple),
+                    EVEOpCode::List => self.items(items, size, EVEValue::List),
+                    _ => self.dict(items, size)
+                }
+            },
+            EVEOpCode::OneTuple | EVEOpCode::TwoTuple | EVEOpCode::OneList => {
+                self.push(data, rest, kind(None), String::new());
+                match opcode {
+                    EVEOpCode::OneTuple => self.items(rest, 1, EVEValue::Tuple),
+                    EVEOpCode::TwoTuple => self.items(rest, 2, EVEValue::Tuple),
+                    _ => self.items(rest, 1, EVEValue::List)
+                }
+            },
+            EVEOpCode::Object => {
+                self.push(data, rest, kind(None), String::new());
+                self.object(rest)
+            },
+            EVEOpCode::ObjectEx1 | EVEOpCode::ObjectEx2 => {
+                self.push(data, rest, kind(None), String::new());
+                let kind = if opcode == EVEOpCode::ObjectEx1 { ObjectExKind::Ex1 } else { ObjectExKind::Ex2 };
+                self.object_ex(rest, kind)
+            },
+            EVEOpCode::SavedStreamElement => {
+                let Some((rest, index)) = read_size(rest) else {
+                    return (self.stop(data, "no index"), None);
+                };
+                let saved = index.checked_sub(1).and_then(|slot| self.saved.get(slot)).and_then(Option::as_ref);
+                // Left out once copying it would go past the decoder's work limit
+                let (note, saved) = match saved {
+                    Some((_, work)) if self.work.saturating_add(*work) > self.limits.max_work => {
+                        (format!("slot {}, too much work to copy", index), None)
+                    },
+                    Some((value, work)) => {
+                        self.work += work;
+                        (format!("slot {} {}", index, summary(value)), Some(value.clone()))
+                    },
+                    None => (format!("slot {} isn't saved yet", index), None)
+                };
+                self.push(data, rest, kind(None), note);
+                (rest, saved)
+            },
+            EVEOpCode::ChecksummedStream => {
+                let Some((checksum, rest)) = rest.split_first_chunk::<4>() else {
+                    return (self.stop(data, "no checksum"), None);
+                };
+                let checksum = u32::from_le_bytes(*checksum);
+                self.push(data, rest, kind(None), format!("checksum {:#010x}", checksum));
+                self.depth += 1;
+                let (rest, value) = self.value(rest);
+                self.depth -= 1;
+                (rest, value.map(|value| EVEValue::ChecksummedStream { checksum, value: Box::new(value) }))
+            },
+            EVEOpCode::SubStream => {
+                let Some((body, size)) = read_size(rest) else {
+                    return (self.stop(data, "no size"), None);
+                };
+                let Some((body, rest)) = body.split_at_checked(size) else {
+                    return (self.stop(data, "sub stream runs past the end"), None);
+                };
+                self.push(data, body, kind(Some(size)), String::new());
+                self.depth += 1;
+                let values = self.stream(body);
+                self.depth -= 1;
+                (rest, values.into_iter().collect::<Option<_>>().map(EVEValue::SubStream))
+            },
+            EVEOpCode::PackedRow => {
+                self.push(data, rest, kind(None), String::new());
+                self.packed_row(rest)
+            },
+            _ => self.leaf(data, opcode, header, slot)
+        };
+
+        if let (Some(slot), Some(value)) = (slot, &value) {
+            if let Some(saved) = slot.checked_sub(1).and_then(|slot| self.saved.get_mut(slot)) {
+                *saved = Some((value.clone(), self.work - work));
+            }
+        }
+        (rest, value)
+    }
+
+    /// Values that don't hold any others are left to the decoder
+    fn leaf(&mut self, data: &'a [u8], opcode: EVEOpCode, header: u8, slot: Option<usize>) -> Walked<'a> {
+        let rest = &data[1..];
+        let size = if opcodes::has_size(opcode.into()) {
+            read_size(rest).map(|(_, size)| size)
+        } else {
+            None
+        };
+        let kind = LineKind::Value { opcode, header, size, slot };
+
+        match self.decoder.decode_opcode(opcode.into(), rest, data) {
+            Ok((rest, value)) => {
+                let mut note = summary(&value);
+                if header & UNKNOWN_FLAG != 0 {
+                    note.push_str(" (unknown flag set)");
+                }
+                self.push(data, rest, kind, note);
+                (rest, Some(value))
+            },
+            Err(NomErr::Error(Error::Truncated { .. }) | NomErr::Failure(Error::Truncated { .. }) | NomErr::Incomplete(_)) => {
+                (self.stop(data, "runs past the end"), None)
+            },
+            Err(NomErr::Error(err) | NomErr::Failure(err)) => {
+                // Skip whatever the size covers to carry on after it
+                let width = if opcode == EVEOpCode::WStringUCS2 { 2 } else { 1 };
+                let skipped = read_size(rest)
+                    .filter(|_| size.is_some())
+                    .and_then(|(data, size)| data.get(size.saturating_mul(width)..));
+                let rest = skipped.unwrap_or(rest);
+                self.push(data, rest, kind, format!("invalid, {}", err));
+                (rest, None)
+            }
+        }
+    }
+
+    fn items(&mut self, mut data: &'a [u8], len: usize, container: fn(Vec<EVEValue<'a>>) -> EVEValue<'a>) -> Walked<'a> {
+        self.depth += 1;
+        let mut items = Some(Vec::new());
+        for _ in 0..len {
+            if self.stopped {
+                break;
+            }
+            let (rest, item) = self.value(data);
+            data = rest;
+            items = items.zip(item).map(|(mut items, item)| {
+                items.push(item);
+                items
+            });
+        }
+        self.depth -= 1;
+        (data, items.filter(|items| items.len() == len).map(container))
+    }
+
+    fn dict(&mut self, mut data: &'a [u8], len: usize) -> Walked<'a> {
+        self.depth += 1;
+        let mut map = Some(BTreeMap::new());
+        for _ in 0..len {
+            if self.stopped {
+                break;
+            }
+            // Values come before their keys
+            let (rest, value) = self.value(data);
+            let (rest, key) = self.value(rest);
+            data = rest;
+
+            let key = key.and_then(|key| key.try_into().ok());
+            map = match (map, key, value) {
+                (Some(mut map), Some(key), Some(value)) => {
+                    map.insert(key, value);
+                    Some(map)
+                },
+                _ => None
+            };
+        }
+        self.depth -= 1;
+        (data, map.filter(|_| !self.stopped).map(EVEValue::Dict))
+    }
+
+    fn object(&mut self, data: &'a [u8]) -> Walked<'a> {
+        self.depth += 1;
+        let (rest, class) = self.value(data);
+        let (rest, args) = self.value(rest);
+        self.depth -= 1;
+
+        let class = match class {
+            Some(EVEValue::Unicode(class) | EVEValue::Global(class)) => Some(class),
+            Some(EVEValue::String(class)) => String::from_utf8(class.into_owned()).ok().map(Into::into),
+            _ => None
+        };
+        (rest, class.zip(args).map(|(class, args)| EVEValue::Object { class, args: Box::new(args) }))
+    }
+
+    fn object_ex(&mut self, data: &'a [u8], kind: ObjectExKind) -> Walked<'a> {
+        self.depth += 1;
+        let (mut data, header) = self.value(data);
+
+        let mut list = Some(Vec::new());
+        while let Some(rest) = self.unless_marker(data) {
+            let (rest, item) = self.value(rest);
+            data = rest;
+            list = list.zip(item).map(|(mut list, item)| {
+                list.push(item);
+                list
+            });
+        }
+        data = data.get(1..).unwrap_or(data);
+
+        // These go key first
+        let mut dict = Some(Vec::new());
+        while let Some(rest) = self.unless_marker(data) {
+            let (rest, key) = self.value(rest);
+            let (rest, value) = self.value(rest);
+            data = rest;
+            dict = match (dict, key, value) {
+                (Some(mut dict), Some(key), Some(value)) => {
+                    dict.push((key, value));
+                    Some(dict)
+                },
+                _ => None
+            };
+        }
+        data = data.get(1..).unwrap_or(data);
+        self.depth -= 1;
+
+        let value = match (header, list, dict) {
+            (Some(header), Some(list), Some(dict)) if !self.stopped => Some(EVEValue::ObjectEx {
+                kind,
+                header: Box::new(header),
+                list,
+                dict
+            }),
+            _ => None
+        };
+        (data, value)
+    }
+
+    /// Lists the marker ending an ObjectEx's items and gives back None if it's
+    /// next, or the data to carry on with if it isn't
+    fn unless_marker(&mut self, data: &'a [u8]) -> Option<&'a [u8]> {
+        if self.stopped {
+            return None;
+        }
+        match data.split_first() {
+            Some((opcode, rest)) if *opcode == u8::from(EVEOpCode::Marker) => {
+                self.push(data, rest, LineKind::Marker, String::new());
+                None
+            },
+            Some(_) => Some(data),
+            None => {
+                self.stop(data, "expected a marker");
+                None
+            }
+        }
+    }
+
+    fn packed_row(&mut self, data: &'a [u8]) -> Walked<'a> {
+        self.depth += 1;
+        let (start, header) = self.value(data);
+        let descriptor = header.as_ref().and_then(DBRowDescriptor::from_value);
+
+        let Some((packed, size)) = read_size(start) else {
+            self.depth -= 1;
+            return (self.stop(start, "no packed data"), None);
+        };
+        let Some((packed, mut rest)) = packed.split_at_checked(size) else {
+            self.depth -= 1;
+            return (self.stop(start, "packed data runs past the end"), None);
+        };
+        let data = descriptor.as_ref().map(|descriptor| packed_row::zero_decompress(packed, descriptor.fixed_len()));
+        let note = match (&descriptor, &data) {
+            (Some(descriptor), Some(None)) => format!("{} columns, but the data expands past them", descriptor.columns().len()),
+            (Some(descriptor), _) => format!("{} columns", descriptor.columns().len()),
+            (None, _) => "unknown row descriptor, its variable columns are listed as values after the row".to_string()
+        };
+        self.push(start, rest, LineKind::PackedData { size }, note);
+
+        let Some(descriptor) = descriptor else {
+            self.depth -= 1;
+            return (rest, None);
+        };
+        let mut values = data.flatten().map(|mut data| {
+            data.resize(descriptor.fixed_len(), 0);
+            descriptor.read_fixed(&data)
+        });
+        for i in descriptor.variable_columns().collect::<Vec<_>>() {
+            let (next, value) = self.value(rest);
+            rest = next;
+            let typ = descriptor.columns()[i].typ;
+            values = match (values, value.and_then(|value| DBValue::from_variable(typ, value))) {
+                (Some(mut values), Some(value)) => {
+                    values[i] = value;
+                    Some(values)
+                },
+                _ => None
+            };
+        }
+        self.depth -= 1;
+
+        (rest, values.map(|values| EVEValue::PackedRow(PackedRow::from_parts(descriptor, values))))
+    }
+}
+
+/// Reads a one byte size, or 0xff and a four byte one
+fn read_size(data: &[u8]) -> Option<(&[u8], usize)> {
+    match data.split_first()? {
+        (0xff, rest) => rest.split_first_chunk::<4>().map(|(size, rest)| (rest, u32::from_le_bytes(*size) as usize)),
+        (size, rest) => Some((rest, *size as usize))
+    }
+}
+
+fn summary(value: &EVEValue) -> String {
+    PrettyPrinter::new().max_depth(NOTE_DEPTH).max_width(NOTE_WIDTH).print_line(value)
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hex = self.bytes.iter()
+            .take(HEX_BYTES)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if self.bytes.len() > HEX_BYTES {
+            hex.push_str(" ..");
+        }
+        write!(f, "{:08x}  {:<w$}  {:indent$}", self.offset, hex, "", w = HEX_BYTES * 3 + 2, indent = self.depth * 2)?;
+
+        match &self.kind {
+            LineKind::Length(len) => write!(f, "length {}", len)?,
+            LineKind::StreamHeader { save_count } => write!(f, "stream, {} saved", save_count)?,
+            LineKind::Compressed { inflated } => write!(f, "zlib stream, {} bytes inflated", inflated)?,
+            LineKind::Value { opcode, header, size, slot } => {
+                write!(f, "{:?}", opcode)?;
+                if let Some(size) = size {
+                    write!(f, " size={}", size)?;
+                }
+                match slot {
+                    Some(slot) => write!(f, " shared={}", slot)?,
+                    None if header & SHARED_FLAG != 0 => write!(f, " shared=?")?,
+                    None => ()
+                }
+            },
+            LineKind::PackedData { size } => write!(f, "packed data, {} bytes", size)?,
+            LineKind::Marker => write!(f, "Marker")?,
+            LineKind::SaveMap(slots) => write!(f, "save map {:?}", slots)?,
+            LineKind::Unknown { header } => write!(f, "UNKNOWN {:#04x}", header)?,
+            LineKind::Truncated => write!(f, "TRUNCATED")?
+        }
+
+        if !self.note.is_empty() {
+            write!(f, "  {}", self.note)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_data::{self, with_length};
+    use super::*;
+
+    fn kinds(listing: &Disassembly) -> Vec<String> {
+        listing.lines.iter().map(|line| {
+            let line = line.to_string();
+            line[38..].trim().to_string()
+        }).collect()
+    }
+
+    #[test_log::test]
+    fn test_machonet_get_time() {
+        let listing = disassemble(test_data::MACHONET_GETTIME);
+        log::trace!("\n{}", listing);
+        assert_eq!(listing.lines.len(), 35);
+        assert!(listing.lines.iter().all(|line| !matches!(line.kind, LineKind::Unknown { .. } | LineKind::Truncated)));
+
+        let line = &listing.lines[17];
+        assert_eq!(line.offset, 0x22);
+        assert_eq!(line.bytes, [0x04, 0xaa, 0xff, 0x00, 0x00]);
+        assert_eq!(line.depth, 5);
+        assert_eq!(line.note, "65450");
+        assert_eq!(line.to_string(), "00000022  04 aa ff 00 00                        Long  65450");
+
+        let line = &listing.lines[18];
+        assert_eq!(line.kind, LineKind::Value { opcode: EVEOpCode::LongString, header: 0x13, size: Some(8), slot: None });
+        assert_eq!(line.to_string(), "00000027  13 08 6d 61 63 68 6f 4e ..            LongString size=8  'machoNet'");
+    }
+
+    #[test_log::test]
+    fn test_unknown_opcodes() {
+        // A tuple with an unknown opcode where its first item should be
+        let listing = disassemble(&with_length(&[0x7e, 0, 0, 0, 0, 0x14, 0x02, 0x3e, 0x09, 0x89, 0x2d]));
+        assert_eq!(kinds(&listing), [
+            "length 11",
+            "stream, 0 saved",
+            "Tuple size=2",
+            "UNKNOWN 0x3e",
+            "IntegerOne  1",
+            "IntegerOne  1 (unknown flag set)",
+            "Marker  outside an ObjectEx"
+        ]);
+        assert_eq!(listing.lines[3].offset, 11);
+    }
+
+    #[test_log::test]
+    fn test_truncated() {
+        let listing = disassemble(&with_length(&[0x7e, 0, 0, 0, 0, 0x14, 0x03, 0x09, 0x13, 0x05, b'a']));
+        assert_eq!(kinds(&listing), [
+            "length 11",
+            "stream, 0 saved",
+            "Tuple size=3",
+            "IntegerOne  1",
+            "TRUNCATED  runs past the end"
+        ]);
+        assert_eq!(listing.lines[4].bytes, [0x13, 0x05, b'a']);
+
+        let listing = disassemble(&[0x10, 0, 0, 0, 0x7e, 0, 0, 0, 0, 0x09]);
+        assert_eq!(kinds(&listing), ["length 16  but 6 bytes follow", "stream, 0 saved", "IntegerOne  1"]);
+    }
+
+    #[test_log::test]
+    fn test_save_table() {
+        let body = [
+            0x7e, 0x01, 0, 0, 0,
+            // Shared list of 1
+            0x55, 0x01, 0x09,
+            0x1b, 0x01,
+            0x01, 0, 0, 0
+        ];
+        let listing = disassemble(&with_length(&body));
+        assert_eq!(kinds(&listing), [
+            "length 14",
+            "stream, 1 saved",
+            "List size=1 shared=1",
+            "IntegerOne  1",
+            "SavedStreamElement  slot 1 [1]",
+            "save map [1]"
+        ]);
+    }
+
+    #[test_log::test]
+    fn test_nested_references() {
+        // Each shared two-tuple holds the one before it twice, so copying
+        // them all out doubles with every level
+        let mut body = vec![0x7e, 22, 0, 0, 0, 0x6c, 0x09, 0x09];
+        for slot in 1..22 {
+            body.extend_from_slice(&[0x6c, 0x1b, slot, 0x1b, slot]);
+        }
+        for slot in 1..=22u32 {
+            body.extend_from_slice(&slot.to_le_bytes());
+        }
+        let listing = disassemble(&with_length(&body));
+        assert!(listing.lines.iter().any(|line| line.note.ends_with("too much work to copy")));
+
+        let notes: Vec<_> = listing.lines.iter().filter(|line| line.note.starts_with("slot 3 ")).map(|line| &line.note).collect();
+        assert_eq!(notes, ["slot 3 (((1, 1), (1, 1)), ((1, 1), (1, 1)))"; 2]);
+        let note = &listing.lines.iter().find(|line| line.note.starts_with("slot 10 ")).unwrap().note;
+        let summary = note.strip_prefix("slot 10 ").unwrap();
+        assert_eq!(summary.chars().count(), NOTE_WIDTH);
+        assert!(summary.starts_with("(((((...), (...)), ((...), (...))), ") && summary.ends_with("..."), "{}", summary);
+    }
+
+    #[test_log::test]
+    fn test_compressed() {
+        let body = crate::encode::deflate(&[0x7e, 0, 0, 0, 0, 0x2b, 0x06, 0x7e, 0, 0, 0, 0, 0x08, 0x01]);
+        let listing = disassemble(&with_length(&body));
+        assert_eq!(kinds(&listing), [
+            format!("length {}", body.len()),
+            "zlib stream, 14 bytes inflated  offsets below are in the inflated data".to_string(),
+            "stream, 0 saved".to_string(),
+            "SubStream size=6".to_string(),
+            "stream, 0 saved".to_string(),
+            "IntegerZero  0".to_string(),
+            "None  None".to_string()
+        ]);
+        assert_eq!(listing.lines[5].offset, 12);
+        assert_eq!(listing.lines[5].depth, 4);
+
+        // Inflating stops at the length limit, the same as decoding
+        let limits = DecodeLimits { max_length: 8, ..Default::default() };
+        let listing = disassemble_with_limits(&with_length(&body), limits);
+        assert_eq!(kinds(&listing), [
+            format!("length {}", body.len()),
+            "zlib stream, 8 bytes inflated  invalid, length limit exceeded at offset 4 (<root>)".to_string()
+        ]);
+    }
+}
@@ -0,0 +1,964 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use crate::decode::checksummed_data;
+use crate::fidelity::{ExactEncoder, Fidelity};
+use crate::opcodes::{EVEOpCode, SHARED_FLAG};
+use crate::packed_row;
+use crate::error::EncodeError;
+use crate::pickle;
+use crate::string_table::DEFAULT_STRINGS;
+use crate::value::{EVEValue, HashableEVEValue, ObjectExKind};
+
+/// Smallest encoded value worth sharing, a reference costs two bytes
+/// plus four in the save table
+const MIN_SHARED_LEN: usize = 7;
+
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    share_repeated: bool,
+    compress_above: Option<usize>
+}
+
+/// Encodes values with the default `Encoder`, failing if a `Pickled`
+/// value holds something pickle has no form for or a size doesn't fit in
+/// the u32 marshal sends it as
+pub fn encode_payload(values: &[EVEValue]) -> Result<Vec<u8>, EncodeError> {
+    Encoder::new().encode_payload(values)
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store values that appear more than once in the save table and
+    /// send later copies as references to it
+    pub fn share_repeated(mut self, share_repeated: bool) -> Self {
+        self.share_repeated = share_repeated;
+        self
+    }
+
+    /// Deflate streams, including sub streams, whose body is longer than
+    /// `threshold` bytes
+    pub fn compress_above(mut self, threshold: usize) -> Self {
+        self.compress_above = Some(threshold);
+        self
+    }
+
+    pub fn encode_payload(&self, values: &[EVEValue]) -> Result<Vec<u8>, EncodeError> {
+        let body = StreamEncoder::new(self).encode_stream(values)?;
+        log::trace!("Encoded {} len body", body.len());
+
+        self::frame(body)
+    }
+
+    /// Encodes values decoded by `decode_payload_with_fidelity` exactly as
+    /// they were sent, or as `encode_payload` would if they have changed
+    /// since in a way the recorded layout can't hold
+    pub fn encode_payload_with_fidelity<'a>(&self, values: &[EVEValue<'a>], fidelity: &Fidelity) -> Result<Vec<u8>, EncodeError> {
+        let body = match ExactEncoder::new(fidelity).encode(values) {
+            Some(body) => body,
+            None => {
+                log::debug!("Values don't match their recorded layout, encoding normally");
+                return self.encode_payload(values);
+            }
+        };
+
+        self::frame(body)
+    }
+}
+
+/// Puts the length prefix in front of an encoded body
+fn frame(body: Vec<u8>) -> Result<Vec<u8>, EncodeError> {
+    let len = u32::try_from(body.len()).map_err(|_| EncodeError::TooLong(body.len()))?;
+    let mut payload = Vec::with_capacity(body.len() + 4);
+    payload.extend_from_slice(&len.to_le_bytes());
+    payload.extend_from_slice(&body);
+    Ok(payload)
+}
+
+enum Sharing {
+    Plain,
+    // Position of the opcode to flag once the value is written
+    Save(usize),
+    Referenced
+}
+
+/// What identifies a value's plain encoding: the bytes it writes itself,
+/// and the ids of the values nested in it with where they go in between
+#[derive(Default, PartialEq, Eq, Hash)]
+struct Key {
+    head: Vec<u8>,
+    nested: Vec<(usize, usize)>
+}
+
+/// A distinct plain encoding in the stream
+struct Node {
+    len: usize,
+    // Ids of the nested values, in the order they are written
+    nested: Vec<usize>,
+    // Number of times the value appears in the stream
+    seen: usize,
+    slot: Option<usize>
+}
+
+/// Ids of the values nested in the one being written, when sharing
+struct Nested(Option<std::vec::IntoIter<usize>>);
+
+impl Nested {
+    fn next(&mut self) -> Option<usize> {
+        self.0.as_mut().and_then(Iterator::next)
+    }
+}
+
+/// Encoding state for a single stream, sub streams get their own save table
+struct StreamEncoder<'e> {
+    options: &'e Encoder,
+    ids: HashMap<Key, usize>,
+    nodes: Vec<Node>,
+    save_map: Vec<u32>
+}
+
+impl<'e> StreamEncoder<'e> {
+    fn new(options: &'e Encoder) -> Self {
+        Self {
+            options,
+            ids: HashMap::new(),
+            nodes: Vec::new(),
+            save_map: Vec::new()
+        }
+    }
+
+    /// Encodes a stream body, compressed if it is over the threshold
+    fn encode_stream(mut self, values: &[EVEValue]) -> Result<Vec<u8>, EncodeError> {
+        let mut body = Vec::new();
+        self.encode_payload_body(&mut body, values)?;
+
+        Ok(match self.options.compress_above {
+            Some(threshold) if body.len() > threshold => self::deflate(&body),
+            _ => body
+        })
+    }
+
+    fn encode_payload_body(&mut self, buf: &mut Vec<u8>, values: &[EVEValue]) -> Result<(), EncodeError> {
+        let ids = values.iter()
+            .map(|value| self.options.share_repeated.then(|| self.intern(value)).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        for id in ids.iter().flatten() {
+            self.count_repeats(*id);
+        }
+
+        buf.push(0x7e);
+        let save_count = buf.len();
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        for (value, id) in values.iter().zip(ids) {
+            self.encode_value(buf, value, id)?;
+        }
+
+        buf[save_count..save_count + 4].copy_from_slice(&(self.save_map.len() as u32).to_le_bytes());
+        for slot in &self.save_map {
+            buf.extend_from_slice(&slot.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Values that can't be encoded fail once they are written, so what
+    /// they are counted under doesn't matter
+    fn plain_bytes(value: &EVEValue) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match StreamEncoder::new(&Encoder::default()).encode_value(&mut buf, value, None) {
+            Ok(()) => buf,
+            Err(_) => Vec::new()
+        }
+    }
+
+    fn plain_hashable_bytes(value: &HashableEVEValue) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match StreamEncoder::new(&Encoder::default()).encode_hashable(&mut buf, value, None) {
+            Ok(()) => buf,
+            Err(_) => Vec::new()
+        }
+    }
+
+    /// Gives a value an id that is the same for every value with the same
+    /// plain encoding. Containers are keyed by the ids of what they hold,
+    /// so each value in the stream is only visited once.
+    fn intern(&mut self, value: &EVEValue) -> Result<usize, EncodeError> {
+        let mut key = Key::default();
+        match value {
+            EVEValue::Tuple(vals) => {
+                self::encode_tuple_header(&mut key.head, vals.len())?;
+                for val in vals {
+                    self.nest(&mut key, val)?;
+                }
+            },
+            EVEValue::List(vals) => {
+                self::encode_list_header(&mut key.head, vals.len())?;
+                for val in vals {
+                    self.nest(&mut key, val)?;
+                }
+            },
+            EVEValue::Dict(map) => {
+                key.head.push(EVEOpCode::Dict.into());
+                self::encode_size(&mut key.head, map.len())?;
+                for (k, value) in map {
+                    self.nest(&mut key, value)?;
+                    let id = self.intern_hashable(k)?;
+                    key.nested.push((key.head.len(), id));
+                }
+            },
+            EVEValue::Object { class, args } => {
+                key.head.push(EVEOpCode::Object.into());
+                self.nest(&mut key, &EVEValue::String(Cow::Borrowed(class.as_bytes())))?;
+                self.nest(&mut key, args)?;
+            },
+            EVEValue::ObjectEx { kind, header, list, dict } => {
+                match kind {
+                    ObjectExKind::Ex1 => key.head.push(EVEOpCode::ObjectEx1.into()),
+                    ObjectExKind::Ex2 => key.head.push(EVEOpCode::ObjectEx2.into())
+                }
+                self.nest(&mut key, header)?;
+                for val in list {
+                    self.nest(&mut key, val)?;
+                }
+                key.head.push(EVEOpCode::Marker.into());
+                for (k, value) in dict {
+                    self.nest(&mut key, k)?;
+                    self.nest(&mut key, value)?;
+                }
+                key.head.push(EVEOpCode::Marker.into());
+            },
+            EVEValue::PackedRow(row) => {
+                key.head.push(EVEOpCode::PackedRow.into());
+                self.nest(&mut key, &row.descriptor().to_value())?;
+
+                let packed = packed_row::zero_compress(&row.fixed_data());
+                self::encode_size(&mut key.head, packed.len())?;
+                key.head.extend_from_slice(&packed);
+                for val in row.variable_values() {
+                    self.nest(&mut key, &val)?;
+                }
+            },
+            EVEValue::ChecksummedStream { value, .. } => {
+                // The checksum follows from the value, only its length counts
+                key.head.push(EVEOpCode::ChecksummedStream.into());
+                key.head.extend_from_slice(&[0; 4]);
+                self.nest(&mut key, value)?;
+            },
+            // Pickles and sub streams are shared whole, never what is in them
+            value => key.head = Self::plain_bytes(value)
+        }
+        Ok(self.insert(key))
+    }
+
+    fn intern_hashable(&mut self, value: &HashableEVEValue) -> Result<usize, EncodeError> {
+        let mut key = Key::default();
+        match value {
+            HashableEVEValue::Tuple(vals) => {
+                self::encode_tuple_header(&mut key.head, vals.len())?;
+                for val in vals {
+                    let id = self.intern_hashable(val)?;
+                    key.nested.push((key.head.len(), id));
+                }
+            },
+            value => key.head = Self::plain_hashable_bytes(value)
+        }
+        Ok(self.insert(key))
+    }
+
+    fn nest(&mut self, key: &mut Key, value: &EVEValue) -> Result<(), EncodeError> {
+        let id = self.intern(value)?;
+        key.nested.push((key.head.len(), id));
+        Ok(())
+    }
+
+    fn insert(&mut self, key: Key) -> usize {
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+
+        let nested: Vec<_> = key.nested.iter().map(|(_, id)| *id).collect();
+        let len = key.head.len() + nested.iter().map(|id| self.nodes[*id].len).sum::<usize>();
+        self.nodes.push(Node { len, nested, seen: 0, slot: None });
+        self.ids.insert(key, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// Counts how often each value appears, copies nested inside an already
+    /// counted value are skipped since they will be sent as a single reference
+    fn count_repeats(&mut self, id: usize) {
+        let node = &mut self.nodes[id];
+        if node.len >= MIN_SHARED_LEN {
+            node.seen += 1;
+            if node.seen > 1 {
+                return;
+            }
+        }
+
+        for i in 0..self.nodes[id].nested.len() {
+            self.count_repeats(self.nodes[id].nested[i]);
+        }
+    }
+
+    /// Writes a reference instead of the value if it is already in the save table
+    fn begin_shared(&mut self, buf: &mut Vec<u8>, id: Option<usize>) -> Result<(Sharing, Nested), EncodeError> {
+        let Some(id) = id else {
+            return Ok((Sharing::Plain, Nested(None)));
+        };
+
+        let node = &mut self.nodes[id];
+        let nested = Nested(Some(node.nested.clone().into_iter()));
+        if node.seen < 2 {
+            return Ok((Sharing::Plain, nested));
+        }
+
+        if let Some(slot) = node.slot {
+            buf.push(EVEOpCode::SavedStreamElement.into());
+            self::encode_size(buf, slot)?;
+            return Ok((Sharing::Referenced, nested));
+        }
+
+        self.save_map.push(self.save_map.len() as u32 + 1);
+        node.slot = Some(self.save_map.len());
+        Ok((Sharing::Save(buf.len()), nested))
+    }
+
+    fn encode_value(&mut self, buf: &mut Vec<u8>, value: &EVEValue, id: Option<usize>) -> Result<(), EncodeError> {
+        let (sharing, mut nested) = self.begin_shared(buf, id)?;
+        let shared = match sharing {
+            Sharing::Plain => None,
+            Sharing::Save(opcode) => Some(opcode),
+            Sharing::Referenced => return Ok(())
+        };
+
+        match value {
+            EVEValue::None => buf.push(EVEOpCode::None.into()),
+            EVEValue::Bool(b) => self::encode_bool(buf, *b),
+            EVEValue::Byte(i) => {
+                buf.push(EVEOpCode::Byte.into());
+                buf.push(*i);
+            },
+            EVEValue::Short(i) => {
+                buf.push(EVEOpCode::SignedShort.into());
+                buf.extend_from_slice(&i.to_le_bytes());
+            },
+            EVEValue::Integer(i) => self::encode_integer(buf, *i),
+            EVEValue::BigInt(i) => self::encode_var_int(buf, *i),
+            EVEValue::Float(f) => self::encode_float(buf, *f),
+            EVEValue::String(s) => self::encode_string(buf, s)?,
+            EVEValue::Unicode(s) => self::encode_unicode(buf, s)?,
+            EVEValue::Buffer(b) => self::encode_buffer(buf, b)?,
+            EVEValue::Tuple(vals) => self.encode_tuple(buf, vals, nested)?,
+            EVEValue::List(vals) => self.encode_list(buf, vals, nested)?,
+            EVEValue::Dict(map) => {
+                buf.push(EVEOpCode::Dict.into());
+                self::encode_size(buf, map.len())?;
+                for (key, value) in map {
+                    // Dicts go over the wire value first
+                    self.encode_value(buf, value, nested.next())?;
+                    self.encode_hashable(buf, key, nested.next())?;
+                }
+            },
+            EVEValue::Object { class, args } => {
+                // The class goes over the wire as a plain string
+                buf.push(EVEOpCode::Object.into());
+                self.encode_value(buf, &EVEValue::String(Cow::Borrowed(class.as_bytes())), nested.next())?;
+                self.encode_value(buf, args, nested.next())?;
+            },
+            EVEValue::ObjectEx { kind, header, list, dict } => {
+                match kind {
+                    ObjectExKind::Ex1 => buf.push(EVEOpCode::ObjectEx1.into()),
+                    ObjectExKind::Ex2 => buf.push(EVEOpCode::ObjectEx2.into())
+                }
+                self.encode_value(buf, header, nested.next())?;
+                for val in list {
+                    self.encode_value(buf, val, nested.next())?;
+                }
+                buf.push(EVEOpCode::Marker.into());
+                for (key, value) in dict {
+                    self.encode_value(buf, key, nested.next())?;
+                    self.encode_value(buf, value, nested.next())?;
+                }
+                buf.push(EVEOpCode::Marker.into());
+            },
+            EVEValue::Global(name) => {
+                buf.push(EVEOpCode::Global.into());
+                self::encode_size(buf, name.len())?;
+                buf.extend_from_slice(name.as_bytes());
+            },
+            EVEValue::PackedRow(row) => {
+                buf.push(EVEOpCode::PackedRow.into());
+                self.encode_value(buf, &row.descriptor().to_value(), nested.next())?;
+
+                let packed = packed_row::zero_compress(&row.fixed_data());
+                self::encode_size(buf, packed.len())?;
+                buf.extend_from_slice(&packed);
+                for val in row.variable_values() {
+                    self.encode_value(buf, &val, nested.next())?;
+                }
+            },
+            EVEValue::ChecksummedStream { value, .. } => {
+                let mut encoded = Vec::new();
+                self.encode_value(&mut encoded, value, nested.next())?;
+
+                buf.push(EVEOpCode::ChecksummedStream.into());
+                buf.extend_from_slice(&adler2::adler32_slice(checksummed_data(&encoded)).to_le_bytes());
+                buf.extend_from_slice(&encoded);
+            },
+            EVEValue::Pickled(value) => {
+                let data = pickle::encode_pickle(value)?;
+                buf.push(EVEOpCode::Pickle.into());
+                self::encode_size(buf, data.len())?;
+                buf.extend_from_slice(&data);
+            },
+            EVEValue::SubStream(vals) => {
+                let body = StreamEncoder::new(self.options).encode_stream(vals)?;
+
+                buf.push(EVEOpCode::SubStream.into());
+                self::encode_size(buf, body.len())?;
+                buf.extend_from_slice(&body);
+            }
+        }
+
+        if let Some(opcode) = shared {
+            buf[opcode] |= SHARED_FLAG;
+        }
+        Ok(())
+    }
+
+    fn encode_hashable(&mut self, buf: &mut Vec<u8>, value: &HashableEVEValue, id: Option<usize>) -> Result<(), EncodeError> {
+        let (sharing, mut nested) = self.begin_shared(buf, id)?;
+        let shared = match sharing {
+            Sharing::Plain => None,
+            Sharing::Save(opcode) => Some(opcode),
+            Sharing::Referenced => return Ok(())
+        };
+
+        match value {
+            HashableEVEValue::None => buf.push(EVEOpCode::None.into()),
+            HashableEVEValue::Bool(b) => self::encode_bool(buf, *b),
+            HashableEVEValue::Byte(i) => {
+                buf.push(EVEOpCode::Byte.into());
+                buf.push(*i);
+            },
+            HashableEVEValue::Short(i) => {
+                buf.push(EVEOpCode::SignedShort.into());
+                buf.extend_from_slice(&i.to_le_bytes());
+            },
+            HashableEVEValue::Integer(i) => self::encode_integer(buf, *i),
+            HashableEVEValue::BigInt(i) => self::encode_var_int(buf, *i),
+            HashableEVEValue::Float(f) => self::encode_float(buf, *f),
+            HashableEVEValue::String(s) => self::encode_string(buf, s)?,
+            HashableEVEValue::Unicode(s) => self::encode_unicode(buf, s)?,
+            HashableEVEValue::Buffer(b) => self::encode_buffer(buf, b)?,
+            HashableEVEValue::Tuple(vals) => {
+                self::encode_tuple_header(buf, vals.len())?;
+                for val in vals {
+                    self.encode_hashable(buf, val, nested.next())?;
+                }
+            }
+        }
+
+        if let Some(opcode) = shared {
+            buf[opcode] |= SHARED_FLAG;
+        }
+        Ok(())
+    }
+
+    fn encode_tuple(&mut self, buf: &mut Vec<u8>, vals: &[EVEValue], mut nested: Nested) -> Result<(), EncodeError> {
+        self::encode_tuple_header(buf, vals.len())?;
+        for val in vals {
+            self.encode_value(buf, val, nested.next())?;
+        }
+        Ok(())
+    }
+
+    fn encode_list(&mut self, buf: &mut Vec<u8>, vals: &[EVEValue], mut nested: Nested) -> Result<(), EncodeError> {
+        self::encode_list_header(buf, vals.len())?;
+        for val in vals {
+            self.encode_value(buf, val, nested.next())?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn deflate(body: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).and_then(|_| encoder.finish())
+        .expect("writing to a Vec can't fail")
+}
+
+fn encode_size(buf: &mut Vec<u8>, size: usize) -> Result<(), EncodeError> {
+    if size < 0xff {
+        buf.push(size as u8);
+    } else {
+        let size = u32::try_from(size).map_err(|_| EncodeError::TooLong(size))?;
+        buf.push(0xff);
+        buf.extend_from_slice(&size.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn encode_bool(buf: &mut Vec<u8>, b: bool) {
+    if b {
+        buf.push(EVEOpCode::True.into());
+    } else {
+        buf.push(EVEOpCode::False.into());
+    }
+}
+
+fn encode_integer(buf: &mut Vec<u8>, i: i64) {
+    match i {
+        -1 => buf.push(EVEOpCode::IntegerNegativeOne.into()),
+        0 => buf.push(EVEOpCode::IntegerZero.into()),
+        1 => buf.push(EVEOpCode::IntegerOne.into()),
+        _ => {
+            if let Ok(i) = i32::try_from(i) {
+                buf.push(EVEOpCode::Long.into());
+                buf.extend_from_slice(&i.to_le_bytes());
+            } else {
+                buf.push(EVEOpCode::LongLong.into());
+                buf.extend_from_slice(&i.to_le_bytes());
+            }
+        }
+    }
+}
+
+fn encode_tuple_header(buf: &mut Vec<u8>, len: usize) -> Result<(), EncodeError> {
+    match len {
+        0 => buf.push(EVEOpCode::EmptyTuple.into()),
+        1 => buf.push(EVEOpCode::OneTuple.into()),
+        2 => buf.push(EVEOpCode::TwoTuple.into()),
+        len => {
+            buf.push(EVEOpCode::Tuple.into());
+            self::encode_size(buf, len)?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_list_header(buf: &mut Vec<u8>, len: usize) -> Result<(), EncodeError> {
+    match len {
+        0 => buf.push(EVEOpCode::EmptyList.into()),
+        1 => buf.push(EVEOpCode::OneList.into()),
+        len => {
+            buf.push(EVEOpCode::List.into());
+            self::encode_size(buf, len)?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_buffer(buf: &mut Vec<u8>, b: &[u8]) -> Result<(), EncodeError> {
+    buf.push(EVEOpCode::Buffer.into());
+    self::encode_size(buf, b.len())?;
+    buf.extend_from_slice(b);
+    Ok(())
+}
+
+fn encode_var_int(buf: &mut Vec<u8>, i: i128) {
+    let bytes = self::var_int_bytes(i);
+    buf.push(EVEOpCode::VarInteger.into());
+    // An i128 takes at most 16 bytes, so the size is always one byte
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(&bytes);
+}
+
+/// Shortest little-endian two's complement form of an integer
+pub(crate) fn var_int_bytes(i: i128) -> Vec<u8> {
+    let bytes = i.to_le_bytes();
+    // Drop high bytes that only repeat the sign of the byte below them
+    let mut len = bytes.len();
+    while len > 1 {
+        let top = bytes[len - 1];
+        let sign = if bytes[len - 2] & 0x80 == 0 { 0x00 } else { 0xff };
+        if top != sign {
+            break;
+        }
+        len -= 1;
+    }
+    bytes[..len].to_vec()
+}
+
+fn encode_float(buf: &mut Vec<u8>, f: f64) {
+    // Only positive zero, RealZero would lose the sign of -0.0
+    if f.to_bits() == 0 {
+        buf.push(EVEOpCode::RealZero.into());
+    } else {
+        buf.push(EVEOpCode::Real.into());
+        buf.extend_from_slice(&f.to_le_bytes());
+    }
+}
+
+fn encode_string(buf: &mut Vec<u8>, s: &[u8]) -> Result<(), EncodeError> {
+    if let Some(index) = DEFAULT_STRINGS.iter().position(|string| string.as_bytes() == s) {
+        buf.push(EVEOpCode::StringTableString.into());
+        // String table indexes start at 1
+        buf.push((index + 1) as u8);
+        return Ok(());
+    }
+
+    match s.len() {
+        0 => buf.push(EVEOpCode::EmptyString.into()),
+        1 => {
+            buf.push(EVEOpCode::CharString.into());
+            buf.push(s[0]);
+        },
+        len => {
+            if len < 0xff {
+                buf.push(EVEOpCode::ShortString.into());
+            } else {
+                buf.push(EVEOpCode::LongString.into());
+            }
+            self::encode_size(buf, len)?;
+            buf.extend_from_slice(s);
+        }
+    }
+    Ok(())
+}
+
+fn encode_unicode(buf: &mut Vec<u8>, s: &str) -> Result<(), EncodeError> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => buf.push(EVEOpCode::EmptyUnicode.into()),
+        (Some(c), None) if (c as u32) <= 0xffff => {
+            buf.push(EVEOpCode::UnicodeChar.into());
+            buf.extend_from_slice(&(c as u16).to_le_bytes());
+        },
+        _ => {
+            buf.push(EVEOpCode::WStringUTF8.into());
+            self::encode_size(buf, s.len())?;
+            buf.extend_from_slice(s.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::decode::decode_payload;
+    use crate::pickle::UnsupportedValue;
+    use crate::packed_row::{DBColumn, DBRowDescriptor, DBType, DBValue, PackedRow};
+    use crate::tests::test_data;
+    use super::*;
+
+    fn assert_round_trip(payload: &'static [u8]) {
+        let values = decode_payload(payload).unwrap();
+        for encoder in [Encoder::new(), Encoder::new().share_repeated(true)] {
+            let encoded = encoder.encode_payload(&values).unwrap();
+            log::trace!("{:?}", encoded);
+
+            let decoded = decode_payload(&encoded).unwrap();
+            assert_eq!(values, decoded);
+        }
+    }
+
+    #[test_log::test]
+    fn test_round_trip_packet1() {
+        assert_round_trip(test_data::PACKET1);
+    }
+
+    #[test_log::test]
+    fn test_round_trip_packet2() {
+        assert_round_trip(test_data::PACKET2);
+    }
+
+    #[test_log::test]
+    fn test_round_trip_macho_net_get_time() {
+        assert_round_trip(test_data::MACHONET_GETTIME);
+    }
+
+    #[test_log::test]
+    fn test_compact_opcodes() {
+        let mut dict = BTreeMap::new();
+        dict.insert(b"machoVersion"[..].into(), EVEValue::Integer(1));
+        let values = vec![EVEValue::Tuple(vec![
+            EVEValue::Integer(0),
+            EVEValue::Tuple(vec![EVEValue::Integer(-1)]),
+            EVEValue::Dict(dict),
+            EVEValue::BigInt(7),
+        ])];
+
+        assert_eq!(encode_payload(&values).unwrap(), [
+            0x12, 0x00, 0x00, 0x00,
+            0x7e, 0x00, 0x00, 0x00, 0x00,
+            0x14, 0x04,
+            0x08,
+            0x25, 0x07,
+            0x16, 0x01, 0x09, 0x11, 0x87,
+            0x2f, 0x01, 0x07
+        ]);
+    }
+
+    #[test_log::test]
+    fn test_var_int_round_trip() {
+        for i in [0x7f, 0x80, -0x80, -0x81, i64::MAX as i128 + 1, i128::MIN, i128::MAX] {
+            let values = vec![EVEValue::BigInt(i)];
+            let encoded = encode_payload(&values).unwrap();
+            let decoded = decode_payload(&encoded).unwrap();
+            assert_eq!(values, decoded);
+        }
+    }
+
+    #[test_log::test]
+    fn test_negative_zero_round_trip() {
+        let encoded = encode_payload(&[EVEValue::Float(-0.0)]).unwrap();
+        match decode_payload(&encoded).unwrap()[..] {
+            [EVEValue::Float(f)] => assert_eq!(f.to_bits(), (-0.0f64).to_bits()),
+            ref values => panic!("expected a float, got {:?}", values)
+        }
+    }
+
+    #[test_log::test]
+    fn test_size_too_long() {
+        let size = u32::MAX as usize + 1;
+        assert_eq!(encode_size(&mut Vec::new(), size), Err(EncodeError::TooLong(size)));
+    }
+
+    #[test_log::test]
+    fn test_share_repeated() {
+        let name: EVEValue = b"EVE-EVE-TRANQUILITY"[..].into();
+        let row = EVEValue::Tuple(vec![name.clone(), EVEValue::Integer(360229), EVEValue::None]);
+        let values = vec![EVEValue::Tuple(vec![row.clone(), name.clone(), row])];
+
+        let plain = encode_payload(&values).unwrap();
+        let shared = Encoder::new().share_repeated(true).encode_payload(&values).unwrap();
+        assert!(shared.len() < plain.len());
+        assert_eq!(&shared[5..9], 2u32.to_le_bytes());
+        // Only the first copy of each value is flagged, the rest are references
+        assert_eq!(shared.iter().filter(|b| **b == 0x1b).count(), 2);
+
+        let decoded = decode_payload(&shared).unwrap();
+        assert_eq!(values, decoded);
+    }
+
+    #[test_log::test]
+    fn test_share_nested_repeats() {
+        // Each level holds the one below twice, so only the first copy of
+        // each is written out
+        let mut value: EVEValue = b"EVE-EVE-TRANQUILITY"[..].into();
+        for _ in 0..12 {
+            value = EVEValue::Tuple(vec![value.clone(), value]);
+        }
+        let values = vec![value];
+        let shared = Encoder::new().share_repeated(true).encode_payload(&values).unwrap();
+        assert_eq!(&shared[5..9], 12u32.to_le_bytes());
+        assert!(shared.len() < 256, "{} bytes", shared.len());
+        assert_eq!(decode_payload(&shared).unwrap(), values);
+
+        // Dict keys are shared with values that encode the same
+        let name = HashableEVEValue::String(b"EVE-EVE-TRANQUILITY"[..].into());
+        let key = HashableEVEValue::Tuple(vec![name.clone(), HashableEVEValue::Integer(360229)]);
+        let values = vec![EVEValue::Tuple(vec![
+            EVEValue::Dict([(key.clone(), name.into())].into_iter().collect()),
+            key.into()
+        ])];
+        let shared = Encoder::new().share_repeated(true).encode_payload(&values).unwrap();
+        assert_eq!(&shared[5..9], 2u32.to_le_bytes());
+        assert_eq!(decode_payload(&shared).unwrap(), values);
+    }
+
+    #[test_log::test]
+    fn test_list_round_trip() {
+        let values = vec![EVEValue::Tuple(vec![
+            EVEValue::List(vec![]),
+            EVEValue::List(vec![EVEValue::Integer(1)]),
+            EVEValue::List(vec![EVEValue::Tuple(vec![]), EVEValue::List(vec![]), EVEValue::None]),
+            EVEValue::Tuple(vec![EVEValue::Integer(1)]),
+        ])];
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..14], [0x14, 0x04, 0x26, 0x27, 0x09]);
+
+        let decoded = decode_payload(&encoded).unwrap();
+        assert_eq!(values, decoded);
+    }
+
+    #[test_log::test]
+    fn test_bool_round_trip() {
+        let mut dict = BTreeMap::new();
+        dict.insert(true.into(), false.into());
+        let values = vec![EVEValue::Tuple(vec![true.into(), false.into(), EVEValue::Dict(dict)])];
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..], [0x14, 0x03, 0x1f, 0x20, 0x16, 0x01, 0x20, 0x1f]);
+
+        let decoded = decode_payload(&encoded).unwrap();
+        assert_eq!(values, decoded);
+    }
+
+    #[test_log::test]
+    fn test_string_types_round_trip() {
+        let values = vec![EVEValue::Tuple(vec![
+            b""[..].into(),
+            b"a"[..].into(),
+            b"bytes"[..].into(),
+            vec![b'x'; 300].into(),
+            "".into(),
+            "\u{e9}".into(),
+            "text \u{2603}".into(),
+            EVEValue::Buffer(b"\x00\xff"[..].into()),
+        ])];
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..27], b"\x14\x08\x0e\x0f\x61\x10\x05bytes\x13\xff\x2c\x01\x00\x00");
+        assert_eq!(&encoded[327..], b"\x28\x29\xe9\x00\x2e\x08text \xe2\x98\x83\x0d\x02\x00\xff");
+
+        let decoded = decode_payload(&encoded).unwrap();
+        assert_eq!(values, decoded);
+    }
+
+    #[test_log::test]
+    fn test_object_round_trip() {
+        let values = vec![EVEValue::Object {
+            class: "macho.CallReq".into(),
+            args: Box::new(EVEValue::Tuple(vec![
+                EVEValue::Global("util.KeyVal".into()),
+                EVEValue::Object { class: "util.KeyVal".into(), args: Box::new(EVEValue::None) }
+            ]))
+        }];
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..15], [0x17, 0x11, 0x2e, 0x2c, 0x02, 0x0b]);
+
+        let decoded = decode_payload(&encoded).unwrap();
+        assert_eq!(values, decoded);
+    }
+
+    #[test_log::test]
+    fn test_object_ex_round_trip() {
+        let row_class = EVEValue::Global("util.Row".into());
+        let values = vec![EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex2,
+            header: Box::new(EVEValue::Tuple(vec![
+                EVEValue::Tuple(vec![EVEValue::Global("util.Rowset".into())]),
+                EVEValue::Dict(BTreeMap::new())
+            ])),
+            list: vec![
+                EVEValue::ObjectEx {
+                    kind: ObjectExKind::Ex1,
+                    header: Box::new(EVEValue::Tuple(vec![row_class.clone(), EVEValue::Tuple(vec![])])),
+                    list: vec![],
+                    dict: vec![]
+                },
+                EVEValue::Integer(7)
+            ],
+            dict: vec![(b"RowClass"[..].into(), row_class)]
+        }];
+
+        for encoder in [Encoder::new(), Encoder::new().share_repeated(true)] {
+            let encoded = encoder.encode_payload(&values).unwrap();
+            let decoded = decode_payload(&encoded).unwrap();
+            assert_eq!(values, decoded);
+        }
+    }
+
+    #[test_log::test]
+    fn test_packed_row_round_trip() {
+        let descriptor = DBRowDescriptor::new(vec![
+            DBColumn::new("characterID", DBType::I4),
+            DBColumn::new("characterName", DBType::WString),
+            DBColumn::new("balance", DBType::Currency),
+            DBColumn::new("online", DBType::Bool),
+            DBColumn::new("createDateTime", DBType::FileTime),
+            DBColumn::new("securityRating", DBType::R8),
+            DBColumn::new("gender", DBType::Bool),
+            DBColumn::new("description", DBType::String),
+            DBColumn::new("skillPoints", DBType::I8)
+        ]);
+        let row = |id, name: &'static str, online| PackedRow::new(descriptor.clone(), vec![
+            DBValue::I4(id),
+            DBValue::WString(name.into()),
+            DBValue::Currency(50_000_000_000),
+            DBValue::Bool(online),
+            DBValue::FileTime(129_000_000_000_000_000),
+            DBValue::R8(-0.5),
+            DBValue::Bool(true),
+            DBValue::Null,
+            DBValue::I8(0)
+        ]).map(EVEValue::PackedRow).unwrap();
+        let values = vec![EVEValue::List(vec![row(90000001, "Dreae", true), row(90000002, "Pilot \u{2603}", false)])];
+
+        for encoder in [Encoder::new(), Encoder::new().share_repeated(true)] {
+            let encoded = encoder.encode_payload(&values).unwrap();
+            let decoded = decode_payload(&encoded).unwrap();
+            assert_eq!(values, decoded);
+        }
+
+        // The second row refers back to the first row's descriptor
+        let encoded = Encoder::new().share_repeated(true).encode_payload(&values).unwrap();
+        assert_eq!(&encoded[5..9], [0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(&encoded[9..13], [0x15, 0x02, 0x2a, 0x62]);
+    }
+
+    #[test_log::test]
+    fn test_compress_above() {
+        let values = vec![EVEValue::Tuple(vec![
+            vec![b'x'; 300].into(),
+            EVEValue::SubStream(vec![vec![b'y'; 300].into()])
+        ])];
+
+        let encoded = Encoder::new().compress_above(256).encode_payload(&values).unwrap();
+        assert_eq!(encoded[4], 0x78);
+        assert!(encoded.len() < 100);
+        assert_eq!(decode_payload(&encoded).unwrap(), values);
+
+        let encoded = Encoder::new().compress_above(1024).encode_payload(&values).unwrap();
+        assert_eq!(encoded[4], 0x7e);
+        assert_eq!(decode_payload(&encoded).unwrap(), values);
+
+        for packet in [test_data::PACKET1, test_data::PACKET2, test_data::MACHONET_GETTIME] {
+            let values = decode_payload(packet).unwrap();
+            let encoded = Encoder::new().compress_above(0).encode_payload(&values).unwrap();
+            assert_eq!(decode_payload(&encoded).unwrap(), values);
+        }
+    }
+
+    #[test_log::test]
+    fn test_checksummed_stream_round_trip() {
+        let stream = EVEValue::SubStream(vec![EVEValue::Integer(1)]);
+        let values = vec![EVEValue::ChecksummedStream { checksum: 0, value: Box::new(stream.clone()) }];
+
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..14], [0x1c, 0x88, 0x00, 0x03, 0x03]);
+        assert_eq!(decode_payload(&encoded).unwrap(), [EVEValue::ChecksummedStream {
+            checksum: 0x03030088,
+            value: Box::new(stream)
+        }]);
+    }
+
+    #[test_log::test]
+    fn test_pickled_round_trip() {
+        let pickled = EVEValue::Pickled(Box::new(EVEValue::List(vec![1i64.into(), "two".into()])));
+        let values = vec![EVEValue::Tuple(vec![pickled.clone()])];
+        let encoded = encode_payload(&values).unwrap();
+        assert_eq!(&encoded[9..13], [0x25, 0x21, 0x10, 0x80]);
+        assert_eq!(decode_payload(&encoded).unwrap(), values);
+
+        // Values pickle has no form for fail rather than going as plain marshal
+        let values = vec![EVEValue::Tuple(vec![
+            pickled,
+            EVEValue::Pickled(Box::new(EVEValue::Buffer(b"raw"[..].into())))
+        ])];
+        assert_eq!(encode_payload(&values), Err(EncodeError::Pickle(UnsupportedValue("buffer"))));
+    }
+
+    #[test_log::test]
+    fn test_composite_keys_round_trip() {
+        let keys = [
+            HashableEVEValue::Tuple(vec![HashableEVEValue::Integer(34), HashableEVEValue::Byte(4)]),
+            HashableEVEValue::BigInt(1 << 80),
+            HashableEVEValue::Buffer(b"\x00\x01"[..].into()),
+            true.into()
+        ];
+        let values = vec![EVEValue::Dict(keys.into_iter().map(|k| (k, EVEValue::None)).collect())];
+
+        for encoder in [Encoder::new(), Encoder::new().share_repeated(true)] {
+            let encoded = encoder.encode_payload(&values).unwrap();
+            assert_eq!(decode_payload(&encoded).unwrap(), values);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::fmt;
+
+use crate::pickle::UnsupportedValue;
+
+/// One step into a container on the way to the value that failed to decode.
+///
+/// Dict values are sent before their keys, so entries are identified by
+/// their position in the stream rather than by key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Tuple(usize),
+    List(usize),
+    DictValue(usize),
+    DictKey(usize),
+    ObjectClass,
+    Object(String),
+    ObjectExHeader,
+    ObjectExList(usize),
+    ObjectExDictKey(usize),
+    ObjectExDictValue(usize),
+    SubStream(usize),
+    PackedRowHeader,
+    PackedRowColumn(String),
+    /// Offsets past this point are into the inflated data
+    Inflated,
+    Pickle
+}
+
+/// Which of the `DecodeLimits` was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    Elements,
+    Length,
+    Work
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValuePath(Vec<PathSegment>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    InvalidOpcode { opcode: u8, offset: usize, path: ValuePath },
+    InvalidHeader { header: u8, offset: usize, path: ValuePath },
+    BadLength { length: usize, offset: usize, path: ValuePath },
+    UnknownStringTableIndex { index: u8, offset: usize, path: ValuePath },
+    InvalidUtf8 { offset: usize, path: ValuePath },
+    InvalidUcs2 { offset: usize, path: ValuePath },
+    UnhashableKey { offset: usize, path: ValuePath },
+    InvalidClass { offset: usize, path: ValuePath },
+    UnknownSavedObject { index: usize, offset: usize, path: ValuePath },
+    InvalidRowDescriptor { offset: usize, path: ValuePath },
+    InvalidCompression { offset: usize, path: ValuePath },
+    InvalidPickle { offset: usize, path: ValuePath },
+    LimitExceeded { limit: Limit, offset: usize, path: ValuePath },
+    ChecksumMismatch { expected: u32, actual: u32, offset: usize, path: ValuePath },
+    InvalidColumnValue { offset: usize, path: ValuePath },
+    /// Packed row data that expands to more than the row's columns hold
+    PackedRowOverflow { offset: usize, path: ValuePath },
+    Truncated { offset: usize, path: ValuePath }
+}
+
+impl ValuePath {
+    pub fn new(segments: Vec<PathSegment>) -> Self {
+        Self(segments)
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+}
+
+impl Error {
+    /// Absolute offset into the payload, including the length prefix
+    pub fn offset(&self) -> usize {
+        use self::Error::*;
+        match *self {
+            InvalidOpcode { offset, .. } |
+            InvalidHeader { offset, .. } |
+            BadLength { offset, .. } |
+            UnknownStringTableIndex { offset, .. } |
+            InvalidUtf8 { offset, .. } |
+            InvalidUcs2 { offset, .. } |
+            UnhashableKey { offset, .. } |
+            InvalidClass { offset, .. } |
+            UnknownSavedObject { offset, .. } |
+            InvalidRowDescriptor { offset, .. } |
+            InvalidCompression { offset, .. } |
+            InvalidPickle { offset, .. } |
+            LimitExceeded { offset, .. } |
+            ChecksumMismatch { offset, .. } |
+            InvalidColumnValue { offset, .. } |
+            PackedRowOverflow { offset, .. } |
+            Truncated { offset, .. } => offset
+        }
+    }
+
+    pub fn path(&self) -> &ValuePath {
+        use self::Error::*;
+        match self {
+            InvalidOpcode { path, .. } |
+            InvalidHeader { path, .. } |
+            BadLength { path, .. } |
+            UnknownStringTableIndex { path, .. } |
+            InvalidUtf8 { path, .. } |
+            InvalidUcs2 { path, .. } |
+            UnhashableKey { path, .. } |
+            InvalidClass { path, .. } |
+            UnknownSavedObject { path, .. } |
+            InvalidRowDescriptor { path, .. } |
+            InvalidCompression { path, .. } |
+            InvalidPickle { path, .. } |
+            LimitExceeded { path, .. } |
+            ChecksumMismatch { path, .. } |
+            InvalidColumnValue { path, .. } |
+            PackedRowOverflow { path, .. } |
+            Truncated { path, .. } => path
+        }
+    }
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Tuple(i) => write!(f, "Tuple[{}]", i),
+            PathSegment::List(i) => write!(f, "List[{}]", i),
+            PathSegment::DictValue(i) => write!(f, "Dict[{}]", i),
+            PathSegment::DictKey(i) => write!(f, "DictKey[{}]", i),
+            PathSegment::ObjectClass => write!(f, "Object.class"),
+            PathSegment::Object(class) => write!(f, "Object({})", class),
+            PathSegment::ObjectExHeader => write!(f, "ObjectEx.header"),
+            PathSegment::ObjectExList(i) => write!(f, "ObjectEx.List[{}]", i),
+            PathSegment::ObjectExDictKey(i) => write!(f, "ObjectEx.DictKey[{}]", i),
+            PathSegment::ObjectExDictValue(i) => write!(f, "ObjectEx.Dict[{}]", i),
+            PathSegment::SubStream(i) => write!(f, "SubStream[{}]", i),
+            PathSegment::PackedRowHeader => write!(f, "PackedRow.header"),
+            PathSegment::PackedRowColumn(name) => write!(f, "PackedRow.{}", name),
+            PathSegment::Inflated => write!(f, "zlib"),
+            PathSegment::Pickle => write!(f, "Pickle")
+        }
+    }
+}
+
+impl fmt::Display for ValuePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<root>");
+        }
+
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            InvalidOpcode { opcode, .. } => write!(f, "invalid opcode {:#04x}", opcode)?,
+            InvalidHeader { header, .. } => write!(f, "invalid stream header {:#04x}", header)?,
+            BadLength { length, .. } => write!(f, "bad length {}", length)?,
+            UnknownStringTableIndex { index, .. } => write!(f, "unknown string table index {}", index)?,
+            InvalidUtf8 { .. } => write!(f, "invalid UTF-8 string")?,
+            InvalidUcs2 { .. } => write!(f, "invalid UCS-2 string")?,
+            UnhashableKey { .. } => write!(f, "unhashable dict key")?,
+            InvalidClass { .. } => write!(f, "object class is not a string")?,
+            UnknownSavedObject { index, .. } => write!(f, "unknown saved object {}", index)?,
+            InvalidRowDescriptor { .. } => write!(f, "packed row header is not a DBRowDescriptor")?,
+            InvalidCompression { .. } => write!(f, "invalid zlib stream")?,
+            InvalidPickle { .. } => write!(f, "malformed pickle")?,
+            LimitExceeded { limit, .. } => write!(f, "{} limit exceeded", match limit {
+                Limit::Depth => "nesting depth",
+                Limit::Elements => "element count",
+                Limit::Length => "length",
+                Limit::Work => "work"
+            })?,
+            ChecksumMismatch { expected, actual, .. } => write!(f, "checksum {:#010x} does not match {:#010x}", actual, expected)?,
+            InvalidColumnValue { .. } => write!(f, "packed row value does not match its column type")?,
+            PackedRowOverflow { .. } => write!(f, "packed row data expands past its columns")?,
+            Truncated { .. } => write!(f, "truncated input")?
+        }
+        write!(f, " at offset {} ({})", self.offset(), self.path())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Error encoding values into a payload
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    /// A length or count over the u32 marshal sends sizes as
+    TooLong(usize),
+    /// A `Pickled` value holds something pickle has no form for
+    Pickle(UnsupportedValue)
+}
+
+impl From<UnsupportedValue> for EncodeError {
+    fn from(err: UnsupportedValue) -> Self {
+        EncodeError::Pickle(err)
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooLong(size) => write!(f, "size of {} is too long for marshal", size),
+            EncodeError::Pickle(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncodeError::Pickle(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// Error converting between Rust types and marshal values through serde
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    Message(String),
+    Decode(Error),
+    UnhashableKey,
+    /// A payload held some other number of values than the one expected
+    ValueCount(usize),
+    Encode(EncodeError)
+}
+
+impl From<Error> for ConvertError {
+    fn from(err: Error) -> Self {
+        ConvertError::Decode(err)
+    }
+}
+
+impl From<EncodeError> for ConvertError {
+    fn from(err: EncodeError) -> Self {
+        ConvertError::Encode(err)
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Message(msg) => write!(f, "{}", msg),
+            ConvertError::Decode(err) => write!(f, "{}", err),
+            ConvertError::UnhashableKey => write!(f, "unhashable dict key"),
+            ConvertError::ValueCount(count) => write!(f, "expected a single value, got {}", count),
+            ConvertError::Encode(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// Error building a typed value from an `EVEValue`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FromEveError {
+    WrongType { expected: &'static str, found: &'static str },
+    WrongLength { expected: usize, found: usize },
+    WrongClass { expected: &'static str, found: String },
+    MissingField { ty: &'static str, field: &'static str },
+    /// A field was there but couldn't be converted
+    InvalidField { ty: &'static str, field: &'static str, source: Box<FromEveError> },
+    UnknownVariant { ty: &'static str, variant: String },
+    OutOfRange { ty: &'static str }
+}
+
+impl fmt::Display for FromEveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::FromEveError::*;
+        match self {
+            WrongType { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            WrongLength { expected, found } => write!(f, "expected {} items, found {}", expected, found),
+            WrongClass { expected, found } => write!(f, "expected a {} object, found {}", expected, found),
+            MissingField { ty, field } => write!(f, "missing field `{}` of {}", field, ty),
+            InvalidField { ty, field, source } => write!(f, "field `{}` of {}: {}", field, ty, source),
+            UnknownVariant { ty, variant } => write!(f, "unknown {} variant `{}`", ty, variant),
+            OutOfRange { ty } => write!(f, "value out of range for {}", ty)
+        }
+    }
+}
+
+impl std::error::Error for FromEveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FromEveError::InvalidField { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
+    }
+}
+
+/// Error reading values back from their Python repr, with the offset into
+/// the text where it went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReprError {
+    Expected { expected: &'static str, offset: usize },
+    InvalidNumber { offset: usize },
+    InvalidEscape { offset: usize },
+    UnhashableKey { offset: usize },
+    /// Reprs that leave out too much to be read back, like packed rows
+    Unsupported { what: &'static str, offset: usize }
+}
+
+impl ReprError {
+    pub fn offset(&self) -> usize {
+        use self::ReprError::*;
+        match *self {
+            Expected { offset, .. } |
+            InvalidNumber { offset } |
+            InvalidEscape { offset } |
+            UnhashableKey { offset } |
+            Unsupported { offset, .. } => offset
+        }
+    }
+}
+
+impl fmt::Display for ReprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ReprError::*;
+        match self {
+            Expected { expected, .. } => write!(f, "expected {}", expected)?,
+            InvalidNumber { .. } => write!(f, "invalid number")?,
+            InvalidEscape { .. } => write!(f, "invalid escape sequence")?,
+            UnhashableKey { .. } => write!(f, "unhashable dict key")?,
+            Unsupported { what, .. } => write!(f, "can't read back {}", what)?
+        }
+        write!(f, " at offset {}", self.offset())
+    }
+}
+
+impl std::error::Error for ReprError {}
+
+/// Error reading a value from its JSON mapping, with a JSON pointer to
+/// where it went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    Invalid { expected: &'static str, path: String },
+    UnknownTag { tag: String, path: String },
+    MissingField { field: &'static str, path: String },
+    UnhashableKey { path: String },
+    OutOfRange { path: String }
+}
+
+impl JsonError {
+    pub fn path(&self) -> &str {
+        use self::JsonError::*;
+        match self {
+            Invalid { path, .. } |
+            UnknownTag { path, .. } |
+            MissingField { path, .. } |
+            UnhashableKey { path } |
+            OutOfRange { path } => path
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::JsonError::*;
+        match self {
+            Invalid { expected, .. } => write!(f, "expected {}", expected)?,
+            UnknownTag { tag, .. } => write!(f, "unknown type tag `{}`", tag)?,
+            MissingField { field, .. } => write!(f, "missing field `{}`", field)?,
+            UnhashableKey { .. } => write!(f, "unhashable dict key")?,
+            OutOfRange { .. } => write!(f, "number out of range")?
+        }
+        match self.path() {
+            "" => write!(f, " at <root>"),
+            path => write!(f, " at {}", path)
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Error splitting a stream into frames
+#[derive(Debug)]
+pub enum FrameError {
+    /// The length prefix, or an encoded frame, is over the codec's maximum
+    TooLarge { length: usize, max: usize },
+    Encode(EncodeError),
+    Io(std::io::Error)
+}
+
+impl From<std::io::Error> for FrameError {
+    fn from(err: std::io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+impl From<EncodeError> for FrameError {
+    fn from(err: EncodeError) -> Self {
+        FrameError::Encode(err)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLarge { length, max } => write!(f, "frame of {} bytes is over the {} byte maximum", length, max),
+            FrameError::Encode(err) => write!(f, "{}", err),
+            FrameError::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FrameError::Encode(err) => Some(err),
+            FrameError::Io(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// Error looking a value up by path, with the part of the path where it
+/// went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    InvalidPath { offset: usize },
+    /// A key or index that isn't there, the path ends at it
+    Missing { path: String },
+    /// The path ends at the value that had the wrong type
+    WrongType { path: String, expected: &'static str, found: &'static str },
+    /// The value at the path couldn't be converted for some other reason
+    Convert { path: String, source: FromEveError }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = match self {
+            QueryError::InvalidPath { offset } => return write!(f, "invalid path at offset {}", offset),
+            QueryError::Missing { path } => {
+                write!(f, "no value")?;
+                path
+            },
+            QueryError::WrongType { path, expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)?;
+                path
+            },
+            QueryError::Convert { path, source } => {
+                write!(f, "{}", source)?;
+                path
+            }
+        };
+        match path.as_str() {
+            "" => write!(f, " at <root>"),
+            path => write!(f, " at {}", path)
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Convert { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
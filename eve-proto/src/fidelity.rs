@@ -0,0 +1,537 @@
+use std::borrow::Cow;
+
+use crate::decode::checksummed_data;
+use crate::encode::{deflate, var_int_bytes};
+use crate::opcodes::{EVEOpCode, OPCODE_MASK, SHARED_FLAG};
+use crate::packed_row;
+use crate::pickle;
+use crate::string_table::DEFAULT_STRINGS;
+use crate::value::{EVEValue, ObjectExKind};
+
+/// How a payload was laid out on the wire, everything the decoded values
+/// leave out that the encoder needs to reproduce it byte for byte.
+///
+/// Recorded by `decode_payload_with_fidelity` and replayed by
+/// `Encoder::encode_payload_with_fidelity`. Hints are matched to values in
+/// the order they were decoded, any value that no longer fits its hint
+/// makes the encoder fall back to its usual choices for the whole payload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fidelity {
+    pub(crate) hints: Vec<Hint>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Hint {
+    /// Start of a stream, with the slot map of its save table
+    Stream { save_map: Vec<u32> },
+    /// Start of a zlib stream, followed by the hints for what it inflated to
+    Compressed { inflated: Vec<u8>, deflated: Vec<u8> },
+    Value {
+        // Opcode byte including its flags
+        header: u8,
+        // Size was sent as 0xff and a u32
+        long_size: bool,
+        detail: Detail
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Detail {
+    None,
+    /// Position of each dict entry in the sorted map, in wire order
+    Order(Vec<usize>),
+    StringTable(u8),
+    Reference(usize),
+    /// Bytes a VarInteger was sent with
+    Width(usize),
+    PackedRow { long_size: bool, packed: Vec<u8> },
+    Pickle(Vec<u8>)
+}
+
+fn as_int(value: &EVEValue) -> Option<i128> {
+    match *value {
+        EVEValue::Byte(i) => Some(i as i128),
+        EVEValue::Short(i) => Some(i as i128),
+        EVEValue::Integer(i) => Some(i as i128),
+        EVEValue::BigInt(i) => Some(i),
+        _ => None
+    }
+}
+
+fn string_bytes<'v>(value: &'v EVEValue) -> Option<&'v [u8]> {
+    match value {
+        EVEValue::String(s) => Some(s),
+        _ => None
+    }
+}
+
+fn unicode<'v>(value: &'v EVEValue) -> Option<&'v str> {
+    match value {
+        EVEValue::Unicode(s) => Some(s),
+        _ => None
+    }
+}
+
+fn encode_size(buf: &mut Vec<u8>, size: usize, long_size: bool) -> Option<()> {
+    if long_size {
+        buf.push(0xff);
+        buf.extend_from_slice(&u32::try_from(size).ok()?.to_le_bytes());
+    } else if size < 0xff {
+        buf.push(size as u8);
+    } else {
+        return None;
+    }
+    Some(())
+}
+
+/// Writes values the way the hints say they were sent, `None` as soon as
+/// one doesn't fit
+pub(crate) struct ExactEncoder<'h, 'a> {
+    hints: std::slice::Iter<'h, Hint>,
+    save_map: std::slice::Iter<'h, u32>,
+    // What was written to each slot of the current stream's save table
+    slots: Vec<Option<EVEValue<'a>>>
+}
+
+impl<'h, 'a> ExactEncoder<'h, 'a> {
+    pub(crate) fn new(fidelity: &'h Fidelity) -> Self {
+        Self {
+            hints: fidelity.hints.iter(),
+            save_map: [].iter(),
+            slots: Vec::new()
+        }
+    }
+
+    /// Encodes a payload body, only if every hint was used
+    pub(crate) fn encode(mut self, values: &[EVEValue<'a>]) -> Option<Vec<u8>> {
+        let body = self.encode_stream(values)?;
+        if self.hints.next().is_some() {
+            return None;
+        }
+        Some(body)
+    }
+
+    fn encode_stream(&mut self, values: &[EVEValue<'a>]) -> Option<Vec<u8>> {
+        match self.hints.next()? {
+            Hint::Compressed { inflated, deflated } => {
+                let body = self.encode_stream(values)?;
+                if body == *inflated {
+                    Some(deflated.clone())
+                } else {
+                    Some(deflate(&body))
+                }
+            },
+            Hint::Stream { save_map } => {
+                let outer_map = std::mem::replace(&mut self.save_map, save_map.iter());
+                let outer_slots = std::mem::replace(&mut self.slots, vec![None; save_map.len()]);
+
+                let mut body = vec![0x7e];
+                body.extend_from_slice(&(save_map.len() as u32).to_le_bytes());
+                for value in values {
+                    self.encode_value(&mut body, value)?;
+                }
+                // Every slot has to have been claimed by a shared value
+                if self.save_map.next().is_some() {
+                    return None;
+                }
+                for slot in save_map {
+                    body.extend_from_slice(&slot.to_le_bytes());
+                }
+
+                self.save_map = outer_map;
+                self.slots = outer_slots;
+                Some(body)
+            },
+            Hint::Value { .. } => None
+        }
+    }
+
+    fn next_opcode(&self) -> Option<u8> {
+        match self.hints.as_slice().first()? {
+            Hint::Value { header, .. } => Some(header & OPCODE_MASK),
+            _ => None
+        }
+    }
+
+    fn encode_value(&mut self, buf: &mut Vec<u8>, value: &EVEValue<'a>) -> Option<()> {
+        let (header, long_size, detail) = match self.hints.next()? {
+            Hint::Value { header, long_size, detail } => (*header, *long_size, detail),
+            _ => return None
+        };
+        let opcode = header & OPCODE_MASK;
+
+        // Shared values claim their slot before anything nested inside them
+        let slot = if header & SHARED_FLAG != 0 {
+            let slot = *self.save_map.next()? as usize;
+            if slot == 0 || slot > self.slots.len() {
+                return None;
+            }
+            Some(slot - 1)
+        } else {
+            None
+        };
+
+        buf.push(header);
+        match (detail, value) {
+            (Detail::Reference(index), value) => {
+                let saved = index.checked_sub(1).and_then(|slot| self.slots.get(slot))?;
+                if saved.as_ref() != Some(value) {
+                    return None;
+                }
+                encode_size(buf, *index, long_size)?;
+            },
+            (Detail::StringTable(index), EVEValue::String(s)) => {
+                let string = (*index as usize).checked_sub(1).and_then(|i| DEFAULT_STRINGS.get(i))?;
+                if string.as_bytes() != &s[..] {
+                    return None;
+                }
+                buf.push(*index);
+            },
+            (Detail::Width(width), value) => {
+                let int = as_int(value)?;
+                let mut bytes = if *width == 0 && int == 0 { Vec::new() } else { var_int_bytes(int) };
+                if bytes.len() > *width || *width > 16 {
+                    return None;
+                }
+                let fill = if bytes.last().is_some_and(|byte| byte & 0x80 != 0) { 0xff } else { 0x00 };
+                bytes.resize(*width, fill);
+                encode_size(buf, bytes.len(), long_size)?;
+                buf.extend_from_slice(&bytes);
+            },
+            (Detail::Order(order), EVEValue::Dict(map)) => {
+                if order.len() != map.len() {
+                    return None;
+                }
+                encode_size(buf, order.len(), long_size)?;
+
+                let entries: Vec<_> = map.iter().collect();
+                for index in order {
+                    let (key, value) = entries.get(*index)?;
+                    // Dicts go over the wire value first
+                    self.encode_value(buf, value)?;
+                    self.encode_value(buf, &EVEValue::from((*key).clone()))?;
+                }
+            },
+            (Detail::PackedRow { long_size, packed }, EVEValue::PackedRow(row)) => {
+                self.encode_value(buf, &row.descriptor().to_value())?;
+
+                // The sender's packing may differ from ours, keep it if the row is unchanged
+                let fixed = row.fixed_data();
+                let unpacked = packed_row::zero_decompress(packed, fixed.len()).map(|mut unpacked| {
+                    unpacked.resize(fixed.len(), 0);
+                    unpacked
+                });
+                let packed = if unpacked.as_ref() == Some(&fixed) {
+                    Cow::Borrowed(packed)
+                } else {
+                    Cow::Owned(packed_row::zero_compress(&fixed))
+                };
+                encode_size(buf, packed.len(), *long_size)?;
+                buf.extend_from_slice(&packed);
+
+                for val in row.variable_values() {
+                    self.encode_value(buf, &val)?;
+                }
+            },
+            (Detail::Pickle(data), EVEValue::Pickled(inner)) => {
+                let data = match pickle::decode_pickle(data) {
+                    Ok(original) if original == **inner => Cow::Borrowed(data),
+                    _ => Cow::Owned(pickle::encode_pickle(inner).ok()?)
+                };
+                encode_size(buf, data.len(), long_size)?;
+                buf.extend_from_slice(&data);
+            },
+            (Detail::None, value) => self.encode_plain(buf, opcode, long_size, value)?,
+            _ => return None
+        }
+
+        if let Some(slot) = slot {
+            self.slots[slot] = Some(value.clone());
+        }
+        Some(())
+    }
+
+    /// Writes everything after the opcode for values whose hint has no details
+    fn encode_plain(&mut self, buf: &mut Vec<u8>, opcode: u8, long_size: bool, value: &EVEValue<'a>) -> Option<()> {
+        match opcode {
+            _ if opcode == u8::from(EVEOpCode::None) => matches!(value, EVEValue::None).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::Global) => match value {
+                EVEValue::Global(name) => {
+                    encode_size(buf, name.len(), long_size)?;
+                    buf.extend_from_slice(name.as_bytes());
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::LongLong) => {
+                buf.extend_from_slice(&i64::try_from(as_int(value)?).ok()?.to_le_bytes());
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::Long) => {
+                buf.extend_from_slice(&i32::try_from(as_int(value)?).ok()?.to_le_bytes());
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::SignedShort) => {
+                buf.extend_from_slice(&i16::try_from(as_int(value)?).ok()?.to_le_bytes());
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::Byte) => {
+                buf.push(u8::try_from(as_int(value)?).ok()?);
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::IntegerNegativeOne) => (as_int(value)? == -1).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::IntegerZero) => (as_int(value)? == 0).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::IntegerOne) => (as_int(value)? == 1).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::Real) => match value {
+                EVEValue::Float(f) => {
+                    buf.extend_from_slice(&f.to_le_bytes());
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::RealZero) => match value {
+                EVEValue::Float(f) if f.to_bits() == 0 => Some(()),
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::Buffer) => match value {
+                EVEValue::Buffer(b) => {
+                    encode_size(buf, b.len(), long_size)?;
+                    buf.extend_from_slice(b);
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::EmptyString) => string_bytes(value)?.is_empty().then_some(()),
+            _ if opcode == u8::from(EVEOpCode::CharString) => match string_bytes(value)? {
+                [c] => {
+                    buf.push(*c);
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::ShortString) || opcode == u8::from(EVEOpCode::LongString) => {
+                let s = string_bytes(value)?;
+                encode_size(buf, s.len(), long_size)?;
+                buf.extend_from_slice(s);
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::WStringUCS2) => {
+                let chars = unicode(value)?.chars()
+                    .map(|c| u16::try_from(c as u32).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                encode_size(buf, chars.len(), long_size)?;
+                for c in chars {
+                    buf.extend_from_slice(&c.to_le_bytes());
+                }
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::WStringUTF8) => {
+                let s = unicode(value)?;
+                encode_size(buf, s.len(), long_size)?;
+                buf.extend_from_slice(s.as_bytes());
+                Some(())
+            },
+            _ if opcode == u8::from(EVEOpCode::EmptyUnicode) => unicode(value)?.is_empty().then_some(()),
+            _ if opcode == u8::from(EVEOpCode::UnicodeChar) => {
+                let mut chars = unicode(value)?.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => {
+                        buf.extend_from_slice(&u16::try_from(c as u32).ok()?.to_le_bytes());
+                        Some(())
+                    },
+                    _ => None
+                }
+            },
+            _ if opcode == u8::from(EVEOpCode::True) => matches!(value, EVEValue::Bool(true)).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::False) => matches!(value, EVEValue::Bool(false)).then_some(()),
+            _ if opcode == u8::from(EVEOpCode::Tuple) => match value {
+                EVEValue::Tuple(vals) => self.encode_items(buf, vals, Some(long_size)),
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::List) => match value {
+                EVEValue::List(vals) => self.encode_items(buf, vals, Some(long_size)),
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::EmptyTuple) => self.encode_fixed_items(buf, value, 0, true),
+            _ if opcode == u8::from(EVEOpCode::OneTuple) => self.encode_fixed_items(buf, value, 1, true),
+            _ if opcode == u8::from(EVEOpCode::TwoTuple) => self.encode_fixed_items(buf, value, 2, true),
+            _ if opcode == u8::from(EVEOpCode::EmptyList) => self.encode_fixed_items(buf, value, 0, false),
+            _ if opcode == u8::from(EVEOpCode::OneList) => self.encode_fixed_items(buf, value, 1, false),
+            _ if opcode == u8::from(EVEOpCode::Object) => match value {
+                EVEValue::Object { class, args } => {
+                    // Send the class as whichever kind of string it came as
+                    let class = match self.next_opcode()? {
+                        op if op == u8::from(EVEOpCode::Global) => EVEValue::Global(class.clone()),
+                        op if op == u8::from(EVEOpCode::WStringUTF8)
+                            || op == u8::from(EVEOpCode::WStringUCS2)
+                            || op == u8::from(EVEOpCode::UnicodeChar) => EVEValue::Unicode(class.clone()),
+                        _ => EVEValue::String(match class {
+                            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+                            Cow::Owned(s) => Cow::Owned(s.clone().into_bytes())
+                        })
+                    };
+                    self.encode_value(buf, &class)?;
+                    self.encode_value(buf, args)
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::ObjectEx1) || opcode == u8::from(EVEOpCode::ObjectEx2) => match value {
+                EVEValue::ObjectEx { kind, header, list, dict } => {
+                    let expected = match kind {
+                        ObjectExKind::Ex1 => EVEOpCode::ObjectEx1,
+                        ObjectExKind::Ex2 => EVEOpCode::ObjectEx2
+                    };
+                    if opcode != u8::from(expected) {
+                        return None;
+                    }
+
+                    self.encode_value(buf, header)?;
+                    for val in list {
+                        self.encode_value(buf, val)?;
+                    }
+                    buf.push(EVEOpCode::Marker.into());
+                    for (key, value) in dict {
+                        self.encode_value(buf, key)?;
+                        self.encode_value(buf, value)?;
+                    }
+                    buf.push(EVEOpCode::Marker.into());
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::ChecksummedStream) => match value {
+                EVEValue::ChecksummedStream { value, .. } => {
+                    let mut encoded = Vec::new();
+                    self.encode_value(&mut encoded, value)?;
+                    buf.extend_from_slice(&adler2::adler32_slice(checksummed_data(&encoded)).to_le_bytes());
+                    buf.extend_from_slice(&encoded);
+                    Some(())
+                },
+                _ => None
+            },
+            _ if opcode == u8::from(EVEOpCode::SubStream) => match value {
+                EVEValue::SubStream(vals) => {
+                    let body = self.encode_stream(vals)?;
+                    encode_size(buf, body.len(), long_size)?;
+                    buf.extend_from_slice(&body);
+                    Some(())
+                },
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn encode_fixed_items(&mut self, buf: &mut Vec<u8>, value: &EVEValue<'a>, len: usize, tuple: bool) -> Option<()> {
+        match value {
+            EVEValue::Tuple(vals) if tuple && vals.len() == len => self.encode_items(buf, vals, None),
+            EVEValue::List(vals) if !tuple && vals.len() == len => self.encode_items(buf, vals, None),
+            _ => None
+        }
+    }
+
+    fn encode_items(&mut self, buf: &mut Vec<u8>, vals: &[EVEValue<'a>], long_size: Option<bool>) -> Option<()> {
+        if let Some(long_size) = long_size {
+            encode_size(buf, vals.len(), long_size)?;
+        }
+        for val in vals {
+            self.encode_value(buf, val)?;
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    use crate::decode::{decode_payload, decode_payload_with_fidelity};
+    use crate::encode::Encoder;
+    use crate::tests::test_data::{with_header, with_length, with_save_map};
+    use super::*;
+
+    fn assert_exact(payload: &[u8]) {
+        let (values, fidelity) = decode_payload_with_fidelity(payload).unwrap();
+        let encoded = Encoder::new().encode_payload_with_fidelity(&values, &fidelity).unwrap();
+        assert_eq!(encoded, payload);
+    }
+
+    #[test_log::test]
+    fn test_exact_test_data() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/test_data");
+        let mut files = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "bin") {
+                log::debug!("Re-encoding {}", path.display());
+                assert_exact(&std::fs::read(&path).unwrap());
+                files += 1;
+            }
+        }
+        assert!(files > 0);
+    }
+
+    #[test_log::test]
+    fn test_exact_opcode_choices() {
+        let mut keys = vec![
+            // 'b' as a LongString with a long size, and 'a' as a ShortString
+            0x16, 0x02,
+            0x06, 0x05, 0x13, 0xff, 0x01, 0x00, 0x00, 0x00, b'b',
+            0x04, 0x01, 0x00, 0x00, 0x00, 0x10, 0x01, b'a',
+        ];
+        keys.extend_from_slice(&[
+            // 1 as a Long, a padded VarInteger, a string table string, a UCS-2 string
+            0x14, 0x04,
+            0x04, 0x01, 0x00, 0x00, 0x00,
+            0x2f, 0x04, 0xfe, 0xff, 0xff, 0xff,
+            0x11, 0x01,
+            0x12, 0x02, b'h', 0x00, b'i', 0x00,
+        ]);
+        let payload = with_header(&keys);
+
+        let values = decode_payload(&payload).unwrap();
+        assert_ne!(Encoder::new().encode_payload(&values).unwrap(), payload);
+        assert_exact(&payload);
+    }
+
+    #[test_log::test]
+    fn test_exact_save_table() {
+        // Two shared strings given slots in reverse order, then a reference to the second
+        let body = [
+            0x2c, 0x50, 0x03, b'a', b'b', b'c', 0x2c, 0x50, 0x03, b'd', b'e', b'f', 0x1b, 0x01
+        ];
+        assert_exact(&with_save_map(&body, &[2, 1]));
+    }
+
+    #[test_log::test]
+    fn test_exact_compressed() {
+        // Deflated at a level our encoder wouldn't pick
+        let stream = [0x7e, 0x00, 0x00, 0x00, 0x00, 0x10, 0x03, b'a', b'b', b'c'];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&stream).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let payload = with_length(&body);
+        assert_exact(&payload);
+    }
+
+    #[test_log::test]
+    fn test_changed_values_fall_back() {
+        let payload = with_header(&[0x14, 0x02, 0x04, 0x01, 0x00, 0x00, 0x00, 0x10, 0x01, b'a']);
+        let (mut values, fidelity) = decode_payload_with_fidelity(&payload).unwrap();
+
+        // Still fits a Long
+        values[0] = EVEValue::Tuple(vec![EVEValue::Integer(2), b"a"[..].into()]);
+        let encoded = Encoder::new().encode_payload_with_fidelity(&values, &fidelity).unwrap();
+        assert_eq!(encoded[11..16], [0x04, 0x02, 0x00, 0x00, 0x00]);
+
+        // No longer the same shape
+        values[0] = EVEValue::Tuple(vec![EVEValue::Integer(2)]);
+        let encoded = Encoder::new().encode_payload_with_fidelity(&values, &fidelity).unwrap();
+        assert_eq!(encoded, Encoder::new().encode_payload(&values).unwrap());
+        assert_eq!(decode_payload(&encoded).unwrap(), values);
+    }
+}
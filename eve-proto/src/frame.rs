@@ -0,0 +1,165 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::encode::Encoder;
+use crate::error::FrameError;
+use crate::value::EVEValue;
+
+/// Size of the little endian length in front of every frame
+const LENGTH_SIZE: usize = 4;
+
+/// Splits a stream into the length prefixed frames `decode_payload` takes,
+/// however reads happen to split or join them, and frames values for
+/// sending.
+///
+/// Frames are handed out whole, length prefix included, ready for
+/// `decode_shared`. With the `codec`
+/// feature this is also a tokio-util `Decoder` and `Encoder`, so it can
+/// drive a `Framed` socket.
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+    encoder: Encoder
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            // Same as the longest stream `DecodeLimits` allows by default
+            max_frame_size: 16 << 20,
+            encoder: Encoder::default()
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Longest frame body to accept or send, not counting the length prefix
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// How values are encoded by `encode_frame`
+    pub fn encoder(mut self, encoder: Encoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Takes the next whole frame off the front of `src`, or returns `None`
+    /// and leaves `src` as it is if more has to be read first.
+    ///
+    /// Fails as soon as a length prefix over the maximum arrives, without
+    /// waiting for the frame behind it.
+    pub fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, FrameError> {
+        if src.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+        let length = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > self.max_frame_size {
+            log::error!("Frame length {} is over the maximum {}", length, self.max_frame_size);
+            return Err(FrameError::TooLarge { length, max: self.max_frame_size });
+        }
+
+        let frame_len = LENGTH_SIZE + length;
+        if src.len() < frame_len {
+            // Make room for the rest so it doesn't take several reallocations
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+        Ok(Some(src.split_to(frame_len).freeze()))
+    }
+
+    /// Encodes values as a single frame onto the end of `dst`
+    pub fn encode_frame(&mut self, values: &[EVEValue], dst: &mut BytesMut) -> Result<(), FrameError> {
+        let payload = self.encoder.encode_payload(values)?;
+        let length = payload.len() - LENGTH_SIZE;
+        if length > self.max_frame_size {
+            log::error!("Encoded frame length {} is over the maximum {}", length, self.max_frame_size);
+            return Err(FrameError::TooLarge { length, max: self.max_frame_size });
+        }
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = Bytes;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_frame(src)
+    }
+}
+
+#[cfg(feature = "codec")]
+impl<'a, 'v> tokio_util::codec::Encoder<&'a [EVEValue<'v>]> for FrameCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, values: &'a [EVEValue<'v>], dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_frame(values, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_payload;
+    use crate::tests::test_data;
+    use super::*;
+
+    #[test_log::test]
+    fn test_split_reads() {
+        let payload = test_data::MACHONET_GETTIME;
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::new();
+
+        // A byte at a time, only complete once the last one arrives
+        for (i, byte) in payload.iter().enumerate() {
+            src.extend_from_slice(&[*byte]);
+            let frame = codec.decode_frame(&mut src).unwrap();
+            assert_eq!(frame.is_some(), i == payload.len() - 1);
+            if let Some(frame) = frame {
+                assert_eq!(&frame[..], payload);
+            }
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test_log::test]
+    fn test_joined_reads() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::new();
+        src.extend_from_slice(test_data::PACKET1);
+        src.extend_from_slice(test_data::PACKET2);
+        src.extend_from_slice(&test_data::MACHONET_GETTIME[..10]);
+
+        assert_eq!(&codec.decode_frame(&mut src).unwrap().unwrap()[..], test_data::PACKET1);
+        assert_eq!(&codec.decode_frame(&mut src).unwrap().unwrap()[..], test_data::PACKET2);
+        assert_eq!(codec.decode_frame(&mut src).unwrap(), None);
+        assert_eq!(src.len(), 10);
+
+        src.extend_from_slice(&test_data::MACHONET_GETTIME[10..]);
+        let frame = codec.decode_frame(&mut src).unwrap().unwrap();
+        assert_eq!(decode_payload(&frame).unwrap(), decode_payload(test_data::MACHONET_GETTIME).unwrap());
+    }
+
+    #[test_log::test]
+    fn test_max_frame_size() {
+        let mut codec = FrameCodec::new().max_frame_size(16);
+        let mut src = BytesMut::from(&[17, 0, 0, 0][..]);
+        assert!(matches!(codec.decode_frame(&mut src), Err(FrameError::TooLarge { length: 17, max: 16 })));
+
+        let mut dst = BytesMut::new();
+        let values = decode_payload(test_data::MACHONET_GETTIME).unwrap();
+        assert!(matches!(codec.encode_frame(&values, &mut dst), Err(FrameError::TooLarge { max: 16, .. })));
+        assert!(dst.is_empty());
+
+        codec.encode_frame(&[EVEValue::None], &mut dst).unwrap();
+        codec.encode_frame(&[EVEValue::Integer(1)], &mut dst).unwrap();
+        assert_eq!(decode_payload(&codec.decode_frame(&mut dst).unwrap().unwrap()).unwrap(), [EVEValue::None]);
+        assert_eq!(decode_payload(&codec.decode_frame(&mut dst).unwrap().unwrap()).unwrap(), [EVEValue::Integer(1)]);
+    }
+}
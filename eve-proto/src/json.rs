@@ -0,0 +1,455 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Number, Value as Json};
+
+use crate::error::JsonError;
+use crate::packed_row::{DBColumn, DBRowDescriptor, DBType, DBValue, PackedRow};
+use crate::value::{EVEValue, HashableEVEValue, ObjectExKind};
+
+/// Maps a value to JSON that `from_json` reads back to exactly the same
+/// value.
+///
+/// The most common types are plain JSON, everything else is an object with
+/// a single key naming its type:
+///
+/// | Value             | JSON                                                        |
+/// |-------------------|-------------------------------------------------------------|
+/// | None              | `null`                                                      |
+/// | Bool              | `true`, `false`                                             |
+/// | Integer           | `1`                                                         |
+/// | Byte, Short       | `{"byte": 1}`, `{"short": 1}`                               |
+/// | BigInt            | `{"long": "1"}`                                             |
+/// | Float             | `1.0`, `{"float": "inf"}` for what JSON can't hold          |
+/// | String            | `"text"`, `{"str_hex": "ff00"}` if it isn't UTF-8           |
+/// | Unicode           | `{"unicode": "text"}`                                       |
+/// | Buffer            | `{"buffer": "ff00"}`                                        |
+/// | Tuple             | `[1, 2]`                                                    |
+/// | List              | `{"list": [1, 2]}`                                          |
+/// | Dict              | `{"dict": {"key": 1}}` if every key is a UTF-8 string, else `{"dict": [[key, value], ...]}` |
+/// | Object            | `{"object": {"class": "util.KeyVal", "args": ...}}`         |
+/// | Global            | `{"global": "util.KeyVal"}`                                 |
+/// | SubStream         | `{"substream": [...]}`                                      |
+/// | ChecksummedStream | `{"checksummed": {"checksum": 1, "value": ...}}`            |
+/// | Pickled           | `{"pickled": ...}`                                          |
+/// | ObjectEx          | `{"object_ex1": {"header": ..., "list": [...], "dict": [[key, value], ...]}}`, or `object_ex2` |
+/// | PackedRow         | `{"packed_row": {"columns": [["name", 130], ...], "values": [...]}}` |
+///
+/// Packed row columns go by their `DBType` code, and their values are
+/// numbers, bools, `null`, hex for bytes or text like strings above.
+pub fn to_json(value: &EVEValue) -> Json {
+    match value {
+        EVEValue::None => Json::Null,
+        EVEValue::Bool(b) => Json::Bool(*b),
+        EVEValue::Integer(i) => Json::from(*i),
+        EVEValue::Byte(i) => tagged("byte", Json::from(*i)),
+        EVEValue::Short(i) => tagged("short", Json::from(*i)),
+        // Strings keep all 128 bits
+        EVEValue::BigInt(i) => tagged("long", Json::String(i.to_string())),
+        EVEValue::Float(f) => float_to_json(*f),
+        EVEValue::String(s) => str_to_json(s),
+        EVEValue::Unicode(s) => tagged("unicode", Json::String(s.to_string())),
+        EVEValue::Buffer(b) => tagged("buffer", Json::String(hex(b))),
+        EVEValue::Tuple(vals) => array(vals),
+        EVEValue::List(vals) => tagged("list", array(vals)),
+        EVEValue::Dict(map) => {
+            let keys: Option<Vec<_>> = map.keys().map(|key| match key {
+                HashableEVEValue::String(s) => std::str::from_utf8(s).ok(),
+                _ => None
+            }).collect();
+            let dict = match keys {
+                Some(keys) => Json::Object(keys.into_iter().zip(map.values()).map(|(key, value)| (key.to_string(), to_json(value))).collect()),
+                None => Json::Array(map.iter().map(|(key, value)| pair(&key.clone().into(), value)).collect())
+            };
+            tagged("dict", dict)
+        },
+        EVEValue::Object { class, args } => tagged("object", object([
+            ("class", Json::String(class.to_string())),
+            ("args", to_json(args))
+        ])),
+        EVEValue::Global(name) => tagged("global", Json::String(name.to_string())),
+        EVEValue::SubStream(vals) => tagged("substream", array(vals)),
+        EVEValue::ChecksummedStream { checksum, value } => tagged("checksummed", object([
+            ("checksum", Json::from(*checksum)),
+            ("value", to_json(value))
+        ])),
+        EVEValue::Pickled(value) => tagged("pickled", to_json(value)),
+        EVEValue::ObjectEx { kind, header, list, dict } => {
+            let tag = match kind {
+                ObjectExKind::Ex1 => "object_ex1",
+                ObjectExKind::Ex2 => "object_ex2"
+            };
+            tagged(tag, object([
+                ("header", to_json(header)),
+                ("list", array(list)),
+                ("dict", Json::Array(dict.iter().map(|(key, value)| pair(key, value)).collect()))
+            ]))
+        },
+        EVEValue::PackedRow(row) => {
+            let columns = row.descriptor().columns().iter()
+                .map(|column| Json::Array(vec![Json::String(column.name.to_string()), Json::from(column.typ as u8)]))
+                .collect();
+            tagged("packed_row", object([
+                ("columns", Json::Array(columns)),
+                ("values", Json::Array(row.values().iter().map(db_value_to_json).collect()))
+            ]))
+        }
+    }
+}
+
+/// Reads a value back from the JSON `to_json` maps it to
+pub fn from_json(json: &Json) -> Result<EVEValue<'static>, JsonError> {
+    value(json, "")
+}
+
+fn tagged(tag: &str, json: Json) -> Json {
+    let mut map = Map::new();
+    map.insert(tag.to_string(), json);
+    Json::Object(map)
+}
+
+fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+    Json::Object(fields.into_iter().map(|(name, json)| (name.to_string(), json)).collect())
+}
+
+fn array(vals: &[EVEValue]) -> Json {
+    Json::Array(vals.iter().map(to_json).collect())
+}
+
+fn pair(key: &EVEValue, value: &EVEValue) -> Json {
+    Json::Array(vec![to_json(key), to_json(value)])
+}
+
+fn float_to_json(f: f64) -> Json {
+    match Number::from_f64(f) {
+        Some(f) => Json::Number(f),
+        None => tagged("float", Json::String(f.to_string()))
+    }
+}
+
+fn str_to_json(s: &[u8]) -> Json {
+    match std::str::from_utf8(s) {
+        Ok(s) => Json::String(s.to_string()),
+        Err(_) => tagged("str_hex", Json::String(hex(s)))
+    }
+}
+
+fn db_value_to_json(value: &DBValue) -> Json {
+    match value {
+        DBValue::I1(i) => Json::from(*i),
+        DBValue::UI1(i) => Json::from(*i),
+        DBValue::I2(i) => Json::from(*i),
+        DBValue::UI2(i) => Json::from(*i),
+        DBValue::I4(i) => Json::from(*i),
+        DBValue::UI4(i) => Json::from(*i),
+        DBValue::I8(i) | DBValue::Currency(i) | DBValue::FileTime(i) => Json::from(*i),
+        DBValue::UI8(i) => Json::from(*i),
+        DBValue::R4(f) => float_to_json(*f as f64),
+        DBValue::R8(f) => float_to_json(*f),
+        DBValue::Bool(b) => Json::Bool(*b),
+        DBValue::Bytes(b) => Json::String(hex(b)),
+        DBValue::String(s) => str_to_json(s),
+        DBValue::WString(s) => Json::String(s.to_string()),
+        DBValue::Null => Json::Null
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn invalid(expected: &'static str, path: &str) -> JsonError {
+    JsonError::Invalid { expected, path: path.to_string() }
+}
+
+fn value(json: &Json, path: &str) -> Result<EVEValue<'static>, JsonError> {
+    let (tag, inner) = match json {
+        Json::Null => return Ok(EVEValue::None),
+        Json::Bool(b) => return Ok(EVEValue::Bool(*b)),
+        Json::Number(n) => return Ok(match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => EVEValue::Integer(i),
+            (None, Some(i)) => EVEValue::BigInt(i as i128),
+            _ => EVEValue::Float(n.as_f64().ok_or_else(|| invalid("a number", path))?)
+        }),
+        Json::String(s) => return Ok(EVEValue::String(Cow::Owned(s.clone().into_bytes()))),
+        Json::Array(items) => return Ok(EVEValue::Tuple(values(items, path)?)),
+        Json::Object(map) if map.len() == 1 => map.iter().next().expect("map has one entry"),
+        Json::Object(_) => return Err(invalid("an object with a single type tag", path))
+    };
+
+    let path = &format!("{}/{}", path, tag);
+    Ok(match tag.as_str() {
+        "byte" => EVEValue::Byte(integer(inner, path)?),
+        "short" => EVEValue::Short(integer(inner, path)?),
+        "long" => {
+            let digits = inner.as_str().ok_or_else(|| invalid("a string of digits", path))?;
+            EVEValue::BigInt(digits.parse().map_err(|_| invalid("a string of digits", path))?)
+        },
+        "float" => EVEValue::Float(float(inner, path)?),
+        "str_hex" => EVEValue::String(unhex(inner, path)?.into()),
+        "unicode" => EVEValue::Unicode(string(inner, path)?.into()),
+        "buffer" => EVEValue::Buffer(unhex(inner, path)?.into()),
+        "list" => EVEValue::List(values(items(inner, path)?, path)?),
+        "dict" => {
+            let mut map = BTreeMap::new();
+            match inner {
+                Json::Object(entries) => for (key, value_json) in entries {
+                    let value = value(value_json, &format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1")))?;
+                    map.insert(HashableEVEValue::String(Cow::Owned(key.clone().into_bytes())), value);
+                },
+                _ => for (i, (key, value)) in pairs(inner, path)?.into_iter().enumerate() {
+                    let key = key.try_into().map_err(|_| JsonError::UnhashableKey { path: format!("{}/{}/0", path, i) })?;
+                    map.insert(key, value);
+                }
+            }
+            EVEValue::Dict(map)
+        },
+        "object" => EVEValue::Object {
+            class: string(field(inner, "class", path)?, &format!("{}/class", path))?.into(),
+            args: Box::new(value(field(inner, "args", path)?, &format!("{}/args", path))?)
+        },
+        "global" => EVEValue::Global(string(inner, path)?.into()),
+        "substream" => EVEValue::SubStream(values(items(inner, path)?, path)?),
+        "checksummed" => EVEValue::ChecksummedStream {
+            checksum: integer(field(inner, "checksum", path)?, &format!("{}/checksum", path))?,
+            value: Box::new(value(field(inner, "value", path)?, &format!("{}/value", path))?)
+        },
+        "pickled" => EVEValue::Pickled(Box::new(value(inner, path)?)),
+        "object_ex1" | "object_ex2" => EVEValue::ObjectEx {
+            kind: if tag == "object_ex1" { ObjectExKind::Ex1 } else { ObjectExKind::Ex2 },
+            header: Box::new(value(field(inner, "header", path)?, &format!("{}/header", path))?),
+            list: {
+                let path = format!("{}/list", path);
+                values(items(field(inner, "list", &path)?, &path)?, &path)?
+            },
+            dict: pairs(field(inner, "dict", path)?, &format!("{}/dict", path))?
+        },
+        "packed_row" => EVEValue::PackedRow(packed_row(inner, path)?),
+        _ => return Err(JsonError::UnknownTag { tag: tag.clone(), path: path.to_string() })
+    })
+}
+
+fn values(items: &[Json], path: &str) -> Result<Vec<EVEValue<'static>>, JsonError> {
+    items.iter().enumerate().map(|(i, item)| value(item, &format!("{}/{}", path, i))).collect()
+}
+
+fn items<'j>(json: &'j Json, path: &str) -> Result<&'j [Json], JsonError> {
+    json.as_array().map(Vec::as_slice).ok_or_else(|| invalid("an array", path))
+}
+
+fn pairs(json: &Json, path: &str) -> Result<Vec<(EVEValue<'static>, EVEValue<'static>)>, JsonError> {
+    items(json, path)?.iter().enumerate().map(|(i, pair)| {
+        let path = format!("{}/{}", path, i);
+        match pair.as_array().map(Vec::as_slice) {
+            Some([key, value_json]) => Ok((value(key, &format!("{}/0", path))?, value(value_json, &format!("{}/1", path))?)),
+            _ => Err(invalid("a [key, value] pair", &path))
+        }
+    }).collect()
+}
+
+fn field<'j>(json: &'j Json, field: &'static str, path: &str) -> Result<&'j Json, JsonError> {
+    json.as_object()
+        .ok_or_else(|| invalid("an object", path))?
+        .get(field)
+        .ok_or_else(|| JsonError::MissingField { field, path: path.to_string() })
+}
+
+fn string(json: &Json, path: &str) -> Result<String, JsonError> {
+    json.as_str().map(str::to_string).ok_or_else(|| invalid("a string", path))
+}
+
+fn integer<T: TryFrom<i128>>(json: &Json, path: &str) -> Result<T, JsonError> {
+    let i = match (json.as_i64(), json.as_u64()) {
+        (Some(i), _) => i as i128,
+        (None, Some(i)) => i as i128,
+        _ => return Err(invalid("an integer", path))
+    };
+    T::try_from(i).map_err(|_| JsonError::OutOfRange { path: path.to_string() })
+}
+
+/// A number, or a string for the ones JSON can't hold
+fn float(json: &Json, path: &str) -> Result<f64, JsonError> {
+    match json {
+        Json::Number(n) => n.as_f64(),
+        Json::String(s) => s.parse().ok(),
+        _ => None
+    }.ok_or_else(|| invalid("a number", path))
+}
+
+fn unhex(json: &Json, path: &str) -> Result<Vec<u8>, JsonError> {
+    let s = json.as_str().ok_or_else(|| invalid("a hex string", path))?;
+    if s.len() % 2 != 0 {
+        return Err(invalid("a hex string", path));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("a hex string", path))
+}
+
+/// A str, plain text or hex when it isn't UTF-8
+fn str_bytes(json: &Json, path: &str) -> Result<Vec<u8>, JsonError> {
+    match json {
+        Json::String(s) => Ok(s.clone().into_bytes()),
+        Json::Object(map) if map.len() == 1 && map.contains_key("str_hex") => unhex(&map["str_hex"], &format!("{}/str_hex", path)),
+        _ => Err(invalid("a string", path))
+    }
+}
+
+fn packed_row(json: &Json, path: &str) -> Result<PackedRow<'static>, JsonError> {
+    let columns_path = format!("{}/columns", path);
+    let columns = items(field(json, "columns", path)?, &columns_path)?.iter().enumerate().map(|(i, column)| {
+        let path = format!("{}/{}", columns_path, i);
+        match column.as_array().map(Vec::as_slice) {
+            Some([name, typ]) => {
+                let typ = DBType::try_from(integer::<i64>(typ, &format!("{}/1", path))?)
+                    .map_err(|_| invalid("a DBType code", &format!("{}/1", path)))?;
+                Ok(DBColumn::new(string(name, &format!("{}/0", path))?, typ))
+            },
+            _ => Err(invalid("a [name, type] pair", &path))
+        }
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    let values_path = format!("{}/values", path);
+    let values = items(field(json, "values", path)?, &values_path)?;
+    if values.len() != columns.len() {
+        return Err(invalid("a value for each column", &values_path));
+    }
+    let values = columns.iter().zip(values).enumerate().map(|(i, (column, json))| {
+        let path = &format!("{}/{}", values_path, i);
+        if json.is_null() && column.typ.size_bits() == 0 {
+            return Ok(DBValue::Null);
+        }
+        Ok(match column.typ {
+            DBType::I1 => DBValue::I1(integer(json, path)?),
+            DBType::UI1 => DBValue::UI1(integer(json, path)?),
+            DBType::I2 => DBValue::I2(integer(json, path)?),
+            DBType::UI2 => DBValue::UI2(integer(json, path)?),
+            DBType::I4 => DBValue::I4(integer(json, path)?),
+            DBType::UI4 => DBValue::UI4(integer(json, path)?),
+            DBType::I8 => DBValue::I8(integer(json, path)?),
+            DBType::UI8 => DBValue::UI8(integer(json, path)?),
+            DBType::R4 => DBValue::R4(float(json, path)? as f32),
+            DBType::R8 => DBValue::R8(float(json, path)?),
+            DBType::Currency => DBValue::Currency(integer(json, path)?),
+            DBType::FileTime => DBValue::FileTime(integer(json, path)?),
+            DBType::Bool => DBValue::Bool(json.as_bool().ok_or_else(|| invalid("a bool", path))?),
+            DBType::Bytes => DBValue::Bytes(unhex(json, path)?.into()),
+            DBType::String => DBValue::String(str_bytes(json, path)?.into()),
+            DBType::WString => DBValue::WString(string(json, path)?.into())
+        })
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    PackedRow::new(DBRowDescriptor::new(columns), values).map_err(|_| invalid("values matching the columns", &values_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::decode::decode_payload;
+    use crate::tests::test_data;
+    use super::*;
+
+    fn round_trip(value: EVEValue<'static>) -> Json {
+        let json = to_json(&value);
+        assert_eq!(from_json(&json).unwrap(), value, "{}", json);
+        json
+    }
+
+    #[test_log::test]
+    fn test_tags() {
+        assert_eq!(round_trip(EVEValue::Integer(1)), json!(1));
+        assert_eq!(round_trip(EVEValue::Byte(1)), json!({"byte": 1}));
+        assert_eq!(round_trip(EVEValue::Short(-1)), json!({"short": -1}));
+        assert_eq!(round_trip(EVEValue::BigInt(i128::MIN)), json!({"long": i128::MIN.to_string()}));
+        assert_eq!(round_trip(EVEValue::Float(1.0)), json!(1.0));
+        assert_eq!(round_trip(EVEValue::Float(f64::NEG_INFINITY)), json!({"float": "-inf"}));
+        assert_eq!(round_trip(EVEValue::String(b"a"[..].into())), json!("a"));
+        assert_eq!(round_trip(EVEValue::String(b"\xff"[..].into())), json!({"str_hex": "ff"}));
+        assert_eq!(round_trip(EVEValue::Unicode("a".into())), json!({"unicode": "a"}));
+        assert_eq!(round_trip(EVEValue::Buffer(b"\x00\xff"[..].into())), json!({"buffer": "00ff"}));
+        assert_eq!(round_trip(EVEValue::Tuple(vec![EVEValue::None])), json!([null]));
+        assert_eq!(round_trip(EVEValue::List(vec![EVEValue::Bool(true)])), json!({"list": [true]}));
+        assert_eq!(round_trip(EVEValue::Global("util.KeyVal".into())), json!({"global": "util.KeyVal"}));
+        assert_eq!(
+            round_trip(EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Tuple(vec![])) }),
+            json!({"object": {"class": "C", "args": []}})
+        );
+
+        let mut map = BTreeMap::new();
+        map.insert(HashableEVEValue::String(b"a/b"[..].into()), EVEValue::Integer(1));
+        assert_eq!(round_trip(EVEValue::Dict(map.clone())), json!({"dict": {"a/b": 1}}));
+        map.insert(HashableEVEValue::Integer(2), EVEValue::None);
+        assert_eq!(round_trip(EVEValue::Dict(map)), json!({"dict": [[2, null], ["a/b", 1]]}));
+
+        assert_eq!(float(&json!({"float": "nan"})["float"], "").map(f64::is_nan), Ok(true));
+    }
+
+    #[test_log::test]
+    fn test_nested() {
+        round_trip(EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex2,
+            header: Box::new(EVEValue::Tuple(vec![EVEValue::Global("collections.defaultdict".into())])),
+            list: vec![EVEValue::Integer(1)],
+            dict: vec![(EVEValue::List(vec![]), EVEValue::SubStream(vec![EVEValue::None]))]
+        });
+        round_trip(EVEValue::ChecksummedStream {
+            checksum: u32::MAX,
+            value: Box::new(EVEValue::Pickled(Box::new(EVEValue::Byte(0))))
+        });
+
+        let descriptor = DBRowDescriptor::new(vec![
+            DBColumn::new("id", DBType::I4),
+            DBColumn::new("price", DBType::Currency),
+            DBColumn::new("ratio", DBType::R4),
+            DBColumn::new("active", DBType::Bool),
+            DBColumn::new("data", DBType::Bytes),
+            DBColumn::new("name", DBType::WString),
+            DBColumn::new("desc", DBType::String)
+        ]);
+        let row = PackedRow::new(descriptor, vec![
+            DBValue::I4(-1),
+            DBValue::Currency(10000),
+            DBValue::R4(0.5),
+            DBValue::Bool(true),
+            DBValue::Bytes(b"\x01"[..].into()),
+            DBValue::WString("Jita".into()),
+            DBValue::Null
+        ]).unwrap();
+        assert_eq!(round_trip(EVEValue::PackedRow(row)), json!({"packed_row": {
+            "columns": [["id", 3], ["price", 6], ["ratio", 4], ["active", 11], ["data", 128], ["name", 130], ["desc", 129]],
+            "values": [-1, 10000, 0.5, true, "01", "Jita", null]
+        }}));
+    }
+
+    #[test_log::test]
+    fn test_test_data() {
+        for payload in [test_data::PACKET1, test_data::PACKET2, test_data::MACHONET_GETTIME] {
+            for value in decode_payload(payload).unwrap() {
+                round_trip(value.into_owned());
+            }
+        }
+
+        // Fixtures can be kept as JSON instead of binaries
+        let fixture: Json = serde_json::from_str(test_data::MACHONET_GETTIME_JSON).unwrap();
+        let values: Vec<_> = fixture.as_array().unwrap().iter().map(|json| from_json(json).unwrap()).collect();
+        assert_eq!(values, decode_payload(test_data::MACHONET_GETTIME).unwrap());
+    }
+
+    #[test_log::test]
+    fn test_errors() {
+        let error = |json: Json| from_json(&json).unwrap_err();
+        assert_eq!(error(json!({"byte": 256})), JsonError::OutOfRange { path: "/byte".to_string() });
+        assert_eq!(error(json!([{"list": [{"short": "1"}]}])), JsonError::Invalid { expected: "an integer", path: "/0/list/0/short".to_string() });
+        assert_eq!(error(json!({"int": 1})), JsonError::UnknownTag { tag: "int".to_string(), path: "/int".to_string() });
+        assert_eq!(error(json!({"a": 1, "b": 2})), JsonError::Invalid { expected: "an object with a single type tag", path: String::new() });
+        assert_eq!(error(json!({"object": {"class": "C"}})), JsonError::MissingField { field: "args", path: "/object".to_string() });
+        assert_eq!(error(json!({"dict": [[{"list": []}, 1]]})), JsonError::UnhashableKey { path: "/dict/0/0".to_string() });
+        assert_eq!(error(json!({"buffer": "0g"})).to_string(), "expected a hex string at /buffer");
+        assert_eq!(
+            error(json!({"packed_row": {"columns": [["id", 3]], "values": ["1"]}})).to_string(),
+            "expected an integer at /packed_row/values/0"
+        );
+    }
+}
@@ -1,9 +1,34 @@
-#![feature(let_chains)]
-
+pub mod error;
 pub mod value;
 pub mod opcodes;
 pub mod decode;
+pub mod encode;
 pub mod string_table;
+pub mod packed_row;
+pub mod pickle;
+pub mod fidelity;
+pub mod types;
+pub mod ser;
+pub mod de;
+pub mod convert;
+pub mod repr;
+pub mod disasm;
+pub mod diff;
+pub mod frame;
+pub mod shared;
+pub mod query;
+#[cfg(feature = "json")]
+pub mod json;
+
+pub use error::{ConvertError, Error, FrameError, FromEveError, JsonError, QueryError, ReprError};
+pub use convert::{FromEve, ToEve};
+pub use ser::{to_bytes, to_value};
+pub use de::{from_bytes, from_value};
+pub use repr::PrettyPrinter;
+pub use disasm::{disassemble, disassemble_with_limits};
+pub use diff::diff;
+pub use frame::FrameCodec;
+pub use shared::{decode_shared, SharedPayload};
 
 #[cfg(test)]
 mod tests {
@@ -1,5 +1,7 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EVEOpCode {
     None = 0x01,
+    Global = 0x02,
     LongLong = 0x03,
     Long = 0x04,
     SignedShort = 0x05,
@@ -9,23 +11,120 @@ pub enum EVEOpCode {
     IntegerOne = 0x09,
     Real = 0x0a,
     RealZero = 0x0b,
+    Buffer = 0x0d,
+    EmptyString = 0x0e,
+    CharString = 0x0f,
     ShortString = 0x10,
     StringTableString = 0x11,
     WStringUCS2 = 0x12,
     LongString = 0x13,
     Tuple = 0x14,
+    List = 0x15,
     Dict = 0x16,
     Object = 0x17,
+    SavedStreamElement = 0x1b,
+    ChecksummedStream = 0x1c,
+    True = 0x1f,
+    False = 0x20,
+    /// A cPickle blob
+    Pickle = 0x21,
+    ObjectEx1 = 0x22,
+    ObjectEx2 = 0x23,
     EmptyTuple = 0x24,
     OneTuple = 0x25,
+    EmptyList = 0x26,
+    OneList = 0x27,
+    EmptyUnicode = 0x28,
+    UnicodeChar = 0x29,
+    PackedRow = 0x2a,
     SubStream = 0x2b,
     TwoTuple = 0x2c,
+    /// Ends the list and dict items of an ObjectEx
+    Marker = 0x2d,
     WStringUTF8 = 0x2e,
     VarInteger = 0x2f
 }
 
-impl Into<u8> for EVEOpCode {
-    fn into(self) -> u8 {
-        self as u8
+/// Low bits of an opcode byte that hold the opcode itself
+pub const OPCODE_MASK: u8 = 0x3f;
+/// Set on values that are stored in the stream's save table
+pub const SHARED_FLAG: u8 = 0x40;
+pub const UNKNOWN_FLAG: u8 = 0x80;
+
+/// Opcodes followed by a size
+pub(crate) fn has_size(opcode: u8) -> bool {
+    [
+        EVEOpCode::Global,
+        EVEOpCode::Buffer,
+        EVEOpCode::ShortString,
+        EVEOpCode::WStringUCS2,
+        EVEOpCode::LongString,
+        EVEOpCode::Tuple,
+        EVEOpCode::List,
+        EVEOpCode::Dict,
+        EVEOpCode::SavedStreamElement,
+        EVEOpCode::Pickle,
+        EVEOpCode::SubStream,
+        EVEOpCode::WStringUTF8,
+        EVEOpCode::VarInteger
+    ].into_iter().any(|sized| opcode == u8::from(sized))
+}
+
+impl From<EVEOpCode> for u8 {
+    fn from(opcode: EVEOpCode) -> u8 {
+        opcode as u8
+    }
+}
+
+/// Looks up an opcode with the flags already masked off, giving back
+/// anything that isn't one
+impl TryFrom<u8> for EVEOpCode {
+    type Error = u8;
+
+    fn try_from(opcode: u8) -> Result<Self, u8> {
+        match opcode {
+            _ if opcode == EVEOpCode::None as u8 => Ok(EVEOpCode::None),
+            _ if opcode == EVEOpCode::Global as u8 => Ok(EVEOpCode::Global),
+            _ if opcode == EVEOpCode::LongLong as u8 => Ok(EVEOpCode::LongLong),
+            _ if opcode == EVEOpCode::Long as u8 => Ok(EVEOpCode::Long),
+            _ if opcode == EVEOpCode::SignedShort as u8 => Ok(EVEOpCode::SignedShort),
+            _ if opcode == EVEOpCode::Byte as u8 => Ok(EVEOpCode::Byte),
+            _ if opcode == EVEOpCode::IntegerNegativeOne as u8 => Ok(EVEOpCode::IntegerNegativeOne),
+            _ if opcode == EVEOpCode::IntegerZero as u8 => Ok(EVEOpCode::IntegerZero),
+            _ if opcode == EVEOpCode::IntegerOne as u8 => Ok(EVEOpCode::IntegerOne),
+            _ if opcode == EVEOpCode::Real as u8 => Ok(EVEOpCode::Real),
+            _ if opcode == EVEOpCode::RealZero as u8 => Ok(EVEOpCode::RealZero),
+            _ if opcode == EVEOpCode::Buffer as u8 => Ok(EVEOpCode::Buffer),
+            _ if opcode == EVEOpCode::EmptyString as u8 => Ok(EVEOpCode::EmptyString),
+            _ if opcode == EVEOpCode::CharString as u8 => Ok(EVEOpCode::CharString),
+            _ if opcode == EVEOpCode::ShortString as u8 => Ok(EVEOpCode::ShortString),
+            _ if opcode == EVEOpCode::StringTableString as u8 => Ok(EVEOpCode::StringTableString),
+            _ if opcode == EVEOpCode::WStringUCS2 as u8 => Ok(EVEOpCode::WStringUCS2),
+            _ if opcode == EVEOpCode::LongString as u8 => Ok(EVEOpCode::LongString),
+            _ if opcode == EVEOpCode::Tuple as u8 => Ok(EVEOpCode::Tuple),
+            _ if opcode == EVEOpCode::List as u8 => Ok(EVEOpCode::List),
+            _ if opcode == EVEOpCode::Dict as u8 => Ok(EVEOpCode::Dict),
+            _ if opcode == EVEOpCode::Object as u8 => Ok(EVEOpCode::Object),
+            _ if opcode == EVEOpCode::SavedStreamElement as u8 => Ok(EVEOpCode::SavedStreamElement),
+            _ if opcode == EVEOpCode::ChecksummedStream as u8 => Ok(EVEOpCode::ChecksummedStream),
+            _ if opcode == EVEOpCode::True as u8 => Ok(EVEOpCode::True),
+            _ if opcode == EVEOpCode::False as u8 => Ok(EVEOpCode::False),
+            _ if opcode == EVEOpCode::Pickle as u8 => Ok(EVEOpCode::Pickle),
+            _ if opcode == EVEOpCode::ObjectEx1 as u8 => Ok(EVEOpCode::ObjectEx1),
+            _ if opcode == EVEOpCode::ObjectEx2 as u8 => Ok(EVEOpCode::ObjectEx2),
+            _ if opcode == EVEOpCode::EmptyTuple as u8 => Ok(EVEOpCode::EmptyTuple),
+            _ if opcode == EVEOpCode::OneTuple as u8 => Ok(EVEOpCode::OneTuple),
+            _ if opcode == EVEOpCode::EmptyList as u8 => Ok(EVEOpCode::EmptyList),
+            _ if opcode == EVEOpCode::OneList as u8 => Ok(EVEOpCode::OneList),
+            _ if opcode == EVEOpCode::EmptyUnicode as u8 => Ok(EVEOpCode::EmptyUnicode),
+            _ if opcode == EVEOpCode::UnicodeChar as u8 => Ok(EVEOpCode::UnicodeChar),
+            _ if opcode == EVEOpCode::PackedRow as u8 => Ok(EVEOpCode::PackedRow),
+            _ if opcode == EVEOpCode::SubStream as u8 => Ok(EVEOpCode::SubStream),
+            _ if opcode == EVEOpCode::TwoTuple as u8 => Ok(EVEOpCode::TwoTuple),
+            _ if opcode == EVEOpCode::Marker as u8 => Ok(EVEOpCode::Marker),
+            _ if opcode == EVEOpCode::WStringUTF8 as u8 => Ok(EVEOpCode::WStringUTF8),
+            _ if opcode == EVEOpCode::VarInteger as u8 => Ok(EVEOpCode::VarInteger),
+            _ => Err(opcode)
+        }
     }
 }
\ No newline at end of file
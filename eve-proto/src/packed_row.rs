@@ -0,0 +1,588 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::value::{EVEValue, ObjectExKind};
+
+const DESCRIPTOR_CLASS: &str = "blue.DBRowDescriptor";
+
+/// Column types, using the OLE DB `DBTYPE` codes the client sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DBType {
+    I2 = 2,
+    I4 = 3,
+    R4 = 4,
+    R8 = 5,
+    Currency = 6,
+    Bool = 11,
+    I1 = 16,
+    UI1 = 17,
+    UI2 = 18,
+    UI4 = 19,
+    I8 = 20,
+    UI8 = 21,
+    FileTime = 64,
+    Bytes = 128,
+    String = 129,
+    WString = 130
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBColumn<'a> {
+    pub name: Cow<'a, str>,
+    pub typ: DBType
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBRowDescriptor<'a> {
+    columns: Vec<DBColumn<'a>>
+}
+
+/// A single column value of a packed row
+#[derive(Debug, Clone, PartialEq)]
+pub enum DBValue<'a> {
+    I1(i8),
+    UI1(u8),
+    I2(i16),
+    UI2(u16),
+    I4(i32),
+    UI4(u32),
+    I8(i64),
+    UI8(u64),
+    R4(f32),
+    R8(f64),
+    /// Fixed point with four decimal places
+    Currency(i64),
+    /// 100ns intervals since 1601-01-01
+    FileTime(i64),
+    Bool(bool),
+    Bytes(Cow<'a, [u8]>),
+    String(Cow<'a, [u8]>),
+    WString(Cow<'a, str>),
+    /// Only variable width columns can be null, fixed width ones are sent as zero
+    Null
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedRow<'a> {
+    descriptor: DBRowDescriptor<'a>,
+    values: Vec<DBValue<'a>>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowError {
+    ColumnCount { expected: usize, found: usize },
+    ColumnType { column: usize, expected: DBType }
+}
+
+impl DBType {
+    /// Width of the column in the fixed part of a row, zero for columns that
+    /// are sent as separate values after it
+    pub fn size_bits(self) -> usize {
+        use self::DBType::*;
+        match self {
+            I8 | UI8 | R8 | Currency | FileTime => 64,
+            I4 | UI4 | R4 => 32,
+            I2 | UI2 => 16,
+            I1 | UI1 => 8,
+            Bool => 1,
+            Bytes | String | WString => 0
+        }
+    }
+}
+
+impl TryFrom<i64> for DBType {
+    type Error = ();
+
+    fn try_from(code: i64) -> Result<Self, Self::Error> {
+        use self::DBType::*;
+        Ok(match code {
+            2 => I2,
+            3 => I4,
+            4 => R4,
+            5 => R8,
+            6 => Currency,
+            11 => Bool,
+            16 => I1,
+            17 => UI1,
+            18 => UI2,
+            19 => UI4,
+            20 => I8,
+            21 => UI8,
+            64 => FileTime,
+            128 => Bytes,
+            129 => String,
+            130 => WString,
+            _ => return Err(())
+        })
+    }
+}
+
+impl<'a> DBColumn<'a> {
+    pub fn new(name: impl Into<Cow<'a, str>>, typ: DBType) -> Self {
+        Self {
+            name: name.into(),
+            typ
+        }
+    }
+}
+
+impl<'a> DBRowDescriptor<'a> {
+    pub fn new(columns: Vec<DBColumn<'a>>) -> Self {
+        Self {
+            columns
+        }
+    }
+
+    pub fn columns(&self) -> &[DBColumn<'a>] {
+        &self.columns
+    }
+
+    /// Reads a descriptor from the `blue.DBRowDescriptor` object heading a packed row
+    pub fn from_value(value: &EVEValue<'a>) -> Option<Self> {
+        let header = match value {
+            EVEValue::ObjectEx { header, .. } => header,
+            _ => return None
+        };
+        let columns = match header.as_ref() {
+            EVEValue::Tuple(header) => match header.as_slice() {
+                [EVEValue::Global(class), EVEValue::Tuple(args)] if class == DESCRIPTOR_CLASS => match args.as_slice() {
+                    [EVEValue::Tuple(columns)] => columns,
+                    _ => return None
+                },
+                _ => return None
+            },
+            _ => return None
+        };
+
+        let mut descriptor = Vec::with_capacity(columns.len());
+        for column in columns {
+            let (name, typ) = match column {
+                EVEValue::Tuple(column) => match column.as_slice() {
+                    [name, typ] => (name, typ),
+                    _ => return None
+                },
+                _ => return None
+            };
+
+            let name = match name {
+                EVEValue::String(Cow::Borrowed(name)) => Cow::Borrowed(std::str::from_utf8(name).ok()?),
+                EVEValue::String(Cow::Owned(name)) => Cow::Owned(std::str::from_utf8(name).ok()?.to_owned()),
+                EVEValue::Unicode(name) => name.clone(),
+                _ => return None
+            };
+            let typ = match *typ {
+                EVEValue::Byte(typ) => typ as i64,
+                EVEValue::Short(typ) => typ as i64,
+                EVEValue::Integer(typ) => typ,
+                _ => return None
+            };
+            descriptor.push(DBColumn::new(name, DBType::try_from(typ).ok()?));
+        }
+        Some(Self::new(descriptor))
+    }
+
+    pub fn into_owned(self) -> DBRowDescriptor<'static> {
+        DBRowDescriptor::new(self.columns.into_iter()
+            .map(|column| DBColumn::new(column.name.into_owned(), column.typ))
+            .collect())
+    }
+
+    pub fn to_value(&self) -> EVEValue<'a> {
+        let columns = self.columns.iter()
+            .map(|column| EVEValue::Tuple(vec![
+                EVEValue::String(match &column.name {
+                    Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+                    Cow::Owned(name) => Cow::Owned(name.as_bytes().to_vec())
+                }),
+                EVEValue::Integer(column.typ as i64)
+            ]))
+            .collect();
+
+        EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex1,
+            header: Box::new(EVEValue::Tuple(vec![
+                EVEValue::Global(Cow::Borrowed(DESCRIPTOR_CLASS)),
+                EVEValue::Tuple(vec![EVEValue::Tuple(columns)])
+            ])),
+            list: vec![],
+            dict: vec![]
+        }
+    }
+
+    /// Column indexes in the order they are packed, widest first and
+    /// otherwise in column order
+    fn packing_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.columns.len()).collect();
+        order.sort_by_key(|i| std::cmp::Reverse(self.columns[*i].typ.size_bits()));
+        order
+    }
+
+    /// Length in bytes of the fixed width part of a row once unpacked
+    pub fn fixed_len(&self) -> usize {
+        let bits: usize = self.columns.iter().map(|column| column.typ.size_bits()).sum();
+        bits.div_ceil(8)
+    }
+
+    /// Columns sent as separate values after the fixed width data
+    pub fn variable_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.packing_order().into_iter().filter(|i| self.columns[*i].typ.size_bits() == 0)
+    }
+
+    /// Reads the fixed width columns out of unpacked row data, leaving
+    /// `DBValue::Null` in place of the variable width ones
+    pub fn read_fixed(&self, data: &[u8]) -> Vec<DBValue<'a>> {
+        let mut values = vec![DBValue::Null; self.columns.len()];
+        let mut pos = 0;
+        let mut bit = 0;
+
+        fn take<const N: usize>(data: &[u8], pos: &mut usize) -> [u8; N] {
+            let mut bytes = [0u8; N];
+            let available = data.len().saturating_sub(*pos).min(N);
+            bytes[..available].copy_from_slice(&data[*pos..*pos + available]);
+            *pos += N;
+            bytes
+        }
+
+        for i in self.packing_order() {
+            values[i] = match self.columns[i].typ {
+                DBType::I1 => DBValue::I1(i8::from_le_bytes(take(data, &mut pos))),
+                DBType::UI1 => DBValue::UI1(u8::from_le_bytes(take(data, &mut pos))),
+                DBType::I2 => DBValue::I2(i16::from_le_bytes(take(data, &mut pos))),
+                DBType::UI2 => DBValue::UI2(u16::from_le_bytes(take(data, &mut pos))),
+                DBType::I4 => DBValue::I4(i32::from_le_bytes(take(data, &mut pos))),
+                DBType::UI4 => DBValue::UI4(u32::from_le_bytes(take(data, &mut pos))),
+                DBType::I8 => DBValue::I8(i64::from_le_bytes(take(data, &mut pos))),
+                DBType::UI8 => DBValue::UI8(u64::from_le_bytes(take(data, &mut pos))),
+                DBType::R4 => DBValue::R4(f32::from_le_bytes(take(data, &mut pos))),
+                DBType::R8 => DBValue::R8(f64::from_le_bytes(take(data, &mut pos))),
+                DBType::Currency => DBValue::Currency(i64::from_le_bytes(take(data, &mut pos))),
+                DBType::FileTime => DBValue::FileTime(i64::from_le_bytes(take(data, &mut pos))),
+                DBType::Bool => {
+                    // Bools are packed eight to a byte after everything else
+                    let byte = data.get(pos).copied().unwrap_or(0);
+                    let value = (byte >> bit) & 0x01 != 0;
+                    bit += 1;
+                    if bit == 8 {
+                        bit = 0;
+                        pos += 1;
+                    }
+                    DBValue::Bool(value)
+                },
+                DBType::Bytes | DBType::String | DBType::WString => DBValue::Null
+            };
+        }
+        values
+    }
+}
+
+impl<'a> DBValue<'a> {
+    pub fn matches(&self, typ: DBType) -> bool {
+        matches!((self, typ),
+            (DBValue::I1(_), DBType::I1) |
+            (DBValue::UI1(_), DBType::UI1) |
+            (DBValue::I2(_), DBType::I2) |
+            (DBValue::UI2(_), DBType::UI2) |
+            (DBValue::I4(_), DBType::I4) |
+            (DBValue::UI4(_), DBType::UI4) |
+            (DBValue::I8(_), DBType::I8) |
+            (DBValue::UI8(_), DBType::UI8) |
+            (DBValue::R4(_), DBType::R4) |
+            (DBValue::R8(_), DBType::R8) |
+            (DBValue::Currency(_), DBType::Currency) |
+            (DBValue::FileTime(_), DBType::FileTime) |
+            (DBValue::Bool(_), DBType::Bool) |
+            (DBValue::Bytes(_), DBType::Bytes) |
+            (DBValue::String(_), DBType::String) |
+            (DBValue::WString(_), DBType::WString) |
+            (DBValue::Null, DBType::Bytes | DBType::String | DBType::WString))
+    }
+
+    /// Converts a value sent after the fixed width data of a row
+    pub fn from_variable(typ: DBType, value: EVEValue<'a>) -> Option<Self> {
+        Some(match (typ, value) {
+            (_, EVEValue::None) => DBValue::Null,
+            (DBType::Bytes, EVEValue::Buffer(b) | EVEValue::String(b)) => DBValue::Bytes(b),
+            (DBType::String, EVEValue::String(s)) => DBValue::String(s),
+            (DBType::String, EVEValue::Unicode(s)) => DBValue::String(match s {
+                Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+                Cow::Owned(s) => Cow::Owned(s.into_bytes())
+            }),
+            (DBType::WString, EVEValue::Unicode(s)) => DBValue::WString(s),
+            (DBType::WString, EVEValue::String(s)) => DBValue::WString(match s {
+                Cow::Borrowed(s) => Cow::Borrowed(std::str::from_utf8(s).ok()?),
+                Cow::Owned(s) => Cow::Owned(std::string::String::from_utf8(s).ok()?)
+            }),
+            _ => return None
+        })
+    }
+
+    pub fn into_owned(self) -> DBValue<'static> {
+        match self {
+            DBValue::I1(i) => DBValue::I1(i),
+            DBValue::UI1(i) => DBValue::UI1(i),
+            DBValue::I2(i) => DBValue::I2(i),
+            DBValue::UI2(i) => DBValue::UI2(i),
+            DBValue::I4(i) => DBValue::I4(i),
+            DBValue::UI4(i) => DBValue::UI4(i),
+            DBValue::I8(i) => DBValue::I8(i),
+            DBValue::UI8(i) => DBValue::UI8(i),
+            DBValue::R4(f) => DBValue::R4(f),
+            DBValue::R8(f) => DBValue::R8(f),
+            DBValue::Currency(i) => DBValue::Currency(i),
+            DBValue::FileTime(i) => DBValue::FileTime(i),
+            DBValue::Bool(b) => DBValue::Bool(b),
+            DBValue::Bytes(b) => DBValue::Bytes(Cow::Owned(b.into_owned())),
+            DBValue::String(s) => DBValue::String(Cow::Owned(s.into_owned())),
+            DBValue::WString(s) => DBValue::WString(Cow::Owned(s.into_owned())),
+            DBValue::Null => DBValue::Null
+        }
+    }
+
+    /// The plain value a client sees for this column
+    pub fn to_value(&self) -> EVEValue<'a> {
+        match self {
+            DBValue::I1(i) => EVEValue::Integer(*i as i64),
+            DBValue::UI1(i) => EVEValue::Integer(*i as i64),
+            DBValue::I2(i) => EVEValue::Integer(*i as i64),
+            DBValue::UI2(i) => EVEValue::Integer(*i as i64),
+            DBValue::I4(i) => EVEValue::Integer(*i as i64),
+            DBValue::UI4(i) => EVEValue::Integer(*i as i64),
+            DBValue::I8(i) | DBValue::FileTime(i) => EVEValue::Integer(*i),
+            DBValue::UI8(i) => match i64::try_from(*i) {
+                Ok(i) => EVEValue::Integer(i),
+                Err(_) => EVEValue::BigInt(*i as i128)
+            },
+            DBValue::R4(f) => EVEValue::Float(*f as f64),
+            DBValue::R8(f) => EVEValue::Float(*f),
+            DBValue::Currency(i) => EVEValue::Float(*i as f64 / 10000.0),
+            DBValue::Bool(b) => EVEValue::Bool(*b),
+            DBValue::Bytes(b) => EVEValue::Buffer(b.clone()),
+            DBValue::String(s) => EVEValue::String(s.clone()),
+            DBValue::WString(s) => EVEValue::Unicode(s.clone()),
+            DBValue::Null => EVEValue::None
+        }
+    }
+}
+
+impl<'a> PackedRow<'a> {
+    pub fn new(descriptor: DBRowDescriptor<'a>, values: Vec<DBValue<'a>>) -> Result<Self, RowError> {
+        if descriptor.columns.len() != values.len() {
+            return Err(RowError::ColumnCount { expected: descriptor.columns.len(), found: values.len() });
+        }
+        for (column, (desc, value)) in descriptor.columns.iter().zip(values.iter()).enumerate() {
+            if !value.matches(desc.typ) {
+                return Err(RowError::ColumnType { column, expected: desc.typ });
+            }
+        }
+
+        Ok(Self {
+            descriptor,
+            values
+        })
+    }
+
+    /// For values already known to match their columns
+    pub(crate) fn from_parts(descriptor: DBRowDescriptor<'a>, values: Vec<DBValue<'a>>) -> Self {
+        Self {
+            descriptor,
+            values
+        }
+    }
+
+    pub fn into_owned(self) -> PackedRow<'static> {
+        PackedRow {
+            descriptor: self.descriptor.into_owned(),
+            values: self.values.into_iter().map(DBValue::into_owned).collect()
+        }
+    }
+
+    pub fn descriptor(&self) -> &DBRowDescriptor<'a> {
+        &self.descriptor
+    }
+
+    pub fn values(&self) -> &[DBValue<'a>] {
+        &self.values
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DBValue<'a>> {
+        let index = self.descriptor.columns.iter().position(|column| column.name == name)?;
+        self.values.get(index)
+    }
+
+    /// The fixed width part of the row before zero compression
+    pub fn fixed_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.descriptor.fixed_len());
+        let mut bit = 0;
+
+        for i in self.descriptor.packing_order() {
+            match self.values[i] {
+                DBValue::I1(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::UI1(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::I2(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::UI2(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::I4(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::UI4(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::I8(v) | DBValue::Currency(v) | DBValue::FileTime(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::UI8(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::R4(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::R8(v) => data.extend_from_slice(&v.to_le_bytes()),
+                DBValue::Bool(v) => {
+                    if bit == 0 {
+                        data.push(0);
+                    }
+                    if v {
+                        *data.last_mut().unwrap() |= 1 << bit;
+                    }
+                    bit = (bit + 1) % 8;
+                },
+                DBValue::Bytes(_) | DBValue::String(_) | DBValue::WString(_) | DBValue::Null => {}
+            }
+        }
+        data
+    }
+
+    /// Values sent after the fixed width data, in the order they are sent
+    pub fn variable_values(&self) -> impl Iterator<Item = EVEValue<'a>> + '_ {
+        self.descriptor.variable_columns().map(|i| self.values[i].to_value())
+    }
+}
+
+/// Expands zero compressed row data. Each opcode byte describes two runs,
+/// its low nibble first. A run with the high bit of its nibble set is
+/// `len + 1` zeros, otherwise it is `8 - len` bytes copied from the input.
+///
+/// Returns `None` as soon as the output would grow past `max_len`, which
+/// should be the row's `fixed_len`, since every byte past that is thrown
+/// away anyway.
+pub fn zero_decompress(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(max_len.min(data.len() * 2));
+    let mut data = data;
+
+    while let Some((opcode, rest)) = data.split_first() {
+        data = rest;
+        for nibble in [opcode & 0x0f, opcode >> 4] {
+            let len = (nibble & 0x07) as usize;
+            // The last run may be cut short by the end of the data
+            let run = if nibble & 0x08 != 0 { len + 1 } else { (8 - len).min(data.len()) };
+            if out.len() + run > max_len {
+                return None;
+            }
+            if nibble & 0x08 != 0 {
+                out.resize(out.len() + run, 0);
+            } else {
+                let (copied, rest) = data.split_at(run);
+                out.extend_from_slice(copied);
+                data = rest;
+            }
+        }
+    }
+    Some(out)
+}
+
+pub fn zero_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 1);
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let opcode = out.len();
+        out.push(0);
+        for shift in [0, 4] {
+            if rest.is_empty() {
+                break;
+            }
+
+            let nibble = if rest[0] == 0 {
+                let zeros = rest.iter().take(8).take_while(|b| **b == 0).count();
+                rest = &rest[zeros..];
+                0x08 | (zeros - 1) as u8
+            } else {
+                let bytes = rest.iter().take(8).take_while(|b| **b != 0).count();
+                out.extend_from_slice(&rest[..bytes]);
+                rest = &rest[bytes..];
+                ((8 - bytes) & 0x07) as u8
+            };
+            out[opcode] |= nibble << shift;
+        }
+    }
+    out
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::ColumnCount { expected, found } => write!(f, "expected {} columns, found {}", expected, found),
+            RowError::ColumnType { column, expected } => write!(f, "column {} should be {:?}", column, expected)
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor() -> DBRowDescriptor<'static> {
+        DBRowDescriptor::new(vec![
+            DBColumn::new("a", DBType::I4),
+            DBColumn::new("flag", DBType::Bool),
+            DBColumn::new("b", DBType::I8),
+            DBColumn::new("name", DBType::String),
+            DBColumn::new("ok", DBType::Bool)
+        ])
+    }
+
+    #[test_log::test]
+    fn test_fixed_data_layout() {
+        let row = PackedRow::new(descriptor(), vec![
+            DBValue::I4(0x0102),
+            DBValue::Bool(true),
+            DBValue::I8(1),
+            DBValue::String(b"Dreae"[..].into()),
+            DBValue::Bool(false)
+        ]).unwrap();
+
+        let data = row.fixed_data();
+        assert_eq!(data, [0x01, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x01, 0, 0, 0x01]);
+        assert_eq!(zero_compress(&data), [0xe7, 0x01, 0x96, 0x02, 0x01, 0x07, 0x01]);
+        assert_eq!(descriptor().read_fixed(&data), [
+            DBValue::I4(0x0102),
+            DBValue::Bool(true),
+            DBValue::I8(1),
+            DBValue::Null,
+            DBValue::Bool(false)
+        ]);
+    }
+
+    #[test_log::test]
+    fn test_zero_compression_round_trip() {
+        let inputs: [&[u8]; 5] = [
+            &[],
+            &[0; 20],
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            &[0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 7, 7, 7, 7, 7, 7, 7, 7, 0],
+            &[0xff, 0, 0xff, 0, 0xff]
+        ];
+        for input in inputs {
+            assert_eq!(zero_decompress(&zero_compress(input), input.len()).as_deref(), Some(input));
+        }
+    }
+
+    #[test_log::test]
+    fn test_row_type_checks() {
+        assert_eq!(PackedRow::new(descriptor(), vec![]), Err(RowError::ColumnCount { expected: 5, found: 0 }));
+        assert_eq!(PackedRow::new(descriptor(), vec![
+            DBValue::I4(0),
+            DBValue::Bool(true),
+            DBValue::I4(1),
+            DBValue::Null,
+            DBValue::Bool(false)
+        ]), Err(RowError::ColumnType { column: 2, expected: DBType::I8 }));
+    }
+
+    #[test_log::test]
+    fn test_descriptor_value_round_trip() {
+        let descriptor = descriptor();
+        assert_eq!(DBRowDescriptor::from_value(&descriptor.to_value()), Some(descriptor));
+    }
+}
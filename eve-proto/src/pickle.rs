@@ -0,0 +1,912 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::encode::var_int_bytes;
+use crate::decode::DecodeLimits;
+use crate::error::{Error, Limit, PathSegment, ValuePath};
+use crate::value::{EVEValue, ObjectExKind};
+
+const MARK: u8 = b'(';
+const STOP: u8 = b'.';
+const POP: u8 = b'0';
+const POP_MARK: u8 = b'1';
+const DUP: u8 = b'2';
+const FLOAT: u8 = b'F';
+const INT: u8 = b'I';
+const BININT: u8 = b'J';
+const BININT1: u8 = b'K';
+const LONG: u8 = b'L';
+const BININT2: u8 = b'M';
+const NONE: u8 = b'N';
+const REDUCE: u8 = b'R';
+const STRING: u8 = b'S';
+const BINSTRING: u8 = b'T';
+const SHORT_BINSTRING: u8 = b'U';
+const UNICODE: u8 = b'V';
+const BINUNICODE: u8 = b'X';
+const APPEND: u8 = b'a';
+const BUILD: u8 = b'b';
+const GLOBAL: u8 = b'c';
+const DICT: u8 = b'd';
+const EMPTY_DICT: u8 = b'}';
+const APPENDS: u8 = b'e';
+const GET: u8 = b'g';
+const BINGET: u8 = b'h';
+const INST: u8 = b'i';
+const LONG_BINGET: u8 = b'j';
+const LIST: u8 = b'l';
+const EMPTY_LIST: u8 = b']';
+const OBJ: u8 = b'o';
+const PUT: u8 = b'p';
+const BINPUT: u8 = b'q';
+const LONG_BINPUT: u8 = b'r';
+const SETITEM: u8 = b's';
+const TUPLE: u8 = b't';
+const EMPTY_TUPLE: u8 = b')';
+const SETITEMS: u8 = b'u';
+const BINFLOAT: u8 = b'G';
+const PROTO: u8 = 0x80;
+const NEWOBJ: u8 = 0x81;
+const TUPLE1: u8 = 0x85;
+const TUPLE2: u8 = 0x86;
+const TUPLE3: u8 = 0x87;
+const NEWTRUE: u8 = 0x88;
+const NEWFALSE: u8 = 0x89;
+const LONG1: u8 = 0x8a;
+const LONG4: u8 = 0x8b;
+
+/// Highest protocol the client's cPickle understands
+const PROTOCOL: u8 = 2;
+
+/// A value `encode_pickle` has no pickle representation for
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedValue(pub &'static str);
+
+/// Runs a pickle, protocols 0 to 2, and returns the object it builds, within
+/// the default `DecodeLimits`.
+///
+/// Instances come out as `EVEValue::ObjectEx`, the same way marshal sends
+/// them: `REDUCE`, `INST` and `OBJ` give an `Ex1` header of the callable and
+/// its arguments, `NEWOBJ` gives an `Ex2` header of the class and its
+/// arguments. `BUILD` adds the state to the end of the header.
+pub fn decode_pickle(data: &[u8]) -> Result<EVEValue<'_>, Error> {
+    Unpickler::new(data, 0, Vec::new(), DecodeLimits::default(), 0, 0).load()
+}
+
+/// Pickles a value with protocol 2, the inverse of `decode_pickle`
+pub fn encode_pickle(value: &EVEValue) -> Result<Vec<u8>, UnsupportedValue> {
+    let mut buf = vec![PROTO, PROTOCOL];
+    self::encode_value(&mut buf, value)?;
+    buf.push(STOP);
+    Ok(buf)
+}
+
+/// Objects are built up in place by later opcodes and can be shared through
+/// the memo, so they are kept in an arena until the pickle is done
+enum Node<'a> {
+    Value(EVEValue<'a>),
+    Tuple(Vec<usize>),
+    List(Vec<usize>),
+    Dict(Vec<(usize, usize)>),
+    Object {
+        kind: ObjectExKind,
+        header: Vec<usize>,
+        list: Vec<usize>,
+        dict: Vec<(usize, usize)>
+    }
+}
+
+pub(crate) struct Unpickler<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // Offset of the pickle in the enclosing payload
+    base: usize,
+    path: Vec<PathSegment>,
+    nodes: Vec<Node<'a>>,
+    stack: Vec<usize>,
+    marks: Vec<usize>,
+    memo: HashMap<usize, usize>,
+    limits: DecodeLimits,
+    work: usize,
+    // Nesting of the pickle in the enclosing stream
+    depth: usize
+}
+
+impl<'a> Unpickler<'a> {
+    /// Starts with `work` already spent by the enclosing stream and `depth`
+    /// levels into it, so the limits cover the whole payload
+    pub(crate) fn new(data: &'a [u8], base: usize, path: Vec<PathSegment>, limits: DecodeLimits, work: usize, depth: usize) -> Self {
+        Self {
+            data,
+            pos: 0,
+            base,
+            path,
+            nodes: Vec::new(),
+            stack: Vec::new(),
+            marks: Vec::new(),
+            memo: HashMap::new(),
+            limits,
+            work,
+            depth
+        }
+    }
+
+    pub(crate) fn work(&self) -> usize {
+        self.work
+    }
+
+    fn charge(&mut self, at: usize) -> Result<(), Error> {
+        self.work = self.work.saturating_add(1);
+        if self.work > self.limits.max_work {
+            return Err(self.fail(at, |offset, path| Error::LimitExceeded { limit: Limit::Work, offset, path }));
+        }
+        Ok(())
+    }
+
+    fn fail(&self, at: usize, error: impl FnOnce(usize, ValuePath) -> Error) -> Error {
+        error(self.base + at, ValuePath::new(self.path.clone()))
+    }
+
+    fn invalid(&self, at: usize) -> Error {
+        self.fail(at, |offset, path| Error::InvalidPickle { offset, path })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            },
+            None => Err(self.fail(self.data.len(), |offset, path| Error::Truncated { offset, path }))
+        }
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    /// Reads up to the next newline, for the text opcodes of protocol 0
+    fn take_line(&mut self) -> Result<&'a [u8], Error> {
+        match self.data[self.pos..].iter().position(|b| *b == b'\n') {
+            Some(len) => {
+                let line = &self.data[self.pos..self.pos + len];
+                self.pos += len + 1;
+                Ok(line)
+            },
+            None => Err(self.fail(self.data.len(), |offset, path| Error::Truncated { offset, path }))
+        }
+    }
+
+    fn take_str_line(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        let line = self.take_line()?;
+        std::str::from_utf8(line).map_err(|_| self.fail(start, |offset, path| Error::InvalidUtf8 { offset, path }))
+    }
+
+    fn take_parsed_line<T: std::str::FromStr>(&mut self) -> Result<T, Error> {
+        let start = self.pos;
+        self.take_str_line()?.parse().map_err(|_| self.invalid(start))
+    }
+
+    fn push(&mut self, node: Node<'a>) {
+        self.nodes.push(node);
+        self.stack.push(self.nodes.len() - 1);
+    }
+
+    fn push_value(&mut self, value: EVEValue<'a>) {
+        self.push(Node::Value(value));
+    }
+
+    fn pop(&mut self, at: usize) -> Result<usize, Error> {
+        match self.stack.pop() {
+            Some(id) => Ok(id),
+            None => Err(self.invalid(at))
+        }
+    }
+
+    fn top(&self, at: usize) -> Result<usize, Error> {
+        self.stack.last().copied().ok_or_else(|| self.invalid(at))
+    }
+
+    fn pop_mark(&mut self, at: usize) -> Result<Vec<usize>, Error> {
+        match self.marks.pop() {
+            Some(mark) if mark <= self.stack.len() => Ok(self.stack.split_off(mark)),
+            _ => Err(self.invalid(at))
+        }
+    }
+
+    fn tuple_items(&self, id: usize, at: usize) -> Result<Vec<usize>, Error> {
+        match &self.nodes[id] {
+            Node::Tuple(items) => Ok(items.clone()),
+            _ => Err(self.invalid(at))
+        }
+    }
+
+    fn global(&mut self) -> Result<EVEValue<'a>, Error> {
+        let module = self.take_str_line()?;
+        let name = self.take_str_line()?;
+        Ok(EVEValue::Global(Cow::Owned(format!("{}.{}", module, name))))
+    }
+
+    fn instance(&mut self, callable: usize, args: Vec<usize>) {
+        self.nodes.push(Node::Tuple(args));
+        let args = self.nodes.len() - 1;
+        self.push(Node::Object { kind: ObjectExKind::Ex1, header: vec![callable, args], list: vec![], dict: vec![] });
+    }
+
+    fn append(&mut self, items: Vec<usize>, at: usize) -> Result<(), Error> {
+        let target = self.top(at)?;
+        match &mut self.nodes[target] {
+            Node::List(list) | Node::Object { list, .. } => list.extend(items),
+            _ => return Err(self.invalid(at))
+        }
+        Ok(())
+    }
+
+    fn set_items(&mut self, items: Vec<usize>, at: usize) -> Result<(), Error> {
+        if !items.len().is_multiple_of(2) {
+            return Err(self.invalid(at));
+        }
+
+        let target = self.top(at)?;
+        match &mut self.nodes[target] {
+            Node::Dict(dict) | Node::Object { dict, .. } => dict.extend(items.chunks(2).map(|pair| (pair[0], pair[1]))),
+            _ => return Err(self.invalid(at))
+        }
+        Ok(())
+    }
+
+    fn memo_get(&mut self, index: usize, at: usize) -> Result<(), Error> {
+        match self.memo.get(&index) {
+            Some(id) => {
+                self.stack.push(*id);
+                Ok(())
+            },
+            None => Err(self.invalid(at))
+        }
+    }
+
+    fn memo_put(&mut self, index: usize, at: usize) -> Result<(), Error> {
+        let id = self.top(at)?;
+        self.memo.insert(index, id);
+        Ok(())
+    }
+
+    pub(crate) fn load(&mut self) -> Result<EVEValue<'a>, Error> {
+        loop {
+            let at = self.pos;
+            self.charge(at)?;
+            let opcode = self.take(1)?[0];
+            log::trace!("Got pickle opcode {:#04x}", opcode);
+
+            match opcode {
+                PROTO => {
+                    let protocol = self.take(1)?[0];
+                    if protocol > PROTOCOL {
+                        return Err(self.invalid(at));
+                    }
+                },
+                STOP => {
+                    let id = self.pop(at)?;
+                    let mut active = vec![false; self.nodes.len()];
+                    return self.build(id, &mut active, self.depth);
+                },
+                MARK => self.marks.push(self.stack.len()),
+                POP => {
+                    self.pop(at)?;
+                },
+                POP_MARK => {
+                    self.pop_mark(at)?;
+                },
+                DUP => {
+                    let id = self.top(at)?;
+                    self.stack.push(id);
+                },
+                NONE => self.push_value(EVEValue::None),
+                NEWTRUE => self.push_value(EVEValue::Bool(true)),
+                NEWFALSE => self.push_value(EVEValue::Bool(false)),
+                INT => {
+                    // Protocol 0 sends bools as the ints 01 and 00
+                    let start = self.pos;
+                    let value = match self.take_str_line()? {
+                        "01" => EVEValue::Bool(true),
+                        "00" => EVEValue::Bool(false),
+                        line => EVEValue::Integer(line.parse().map_err(|_| self.invalid(start))?)
+                    };
+                    self.push_value(value);
+                },
+                BININT => {
+                    let value = i32::from_le_bytes(self.take_array()?);
+                    self.push_value(value.into());
+                },
+                BININT1 => {
+                    let value = self.take(1)?[0];
+                    self.push_value(EVEValue::Integer(value as i64));
+                },
+                BININT2 => {
+                    let value = u16::from_le_bytes(self.take_array()?);
+                    self.push_value(EVEValue::Integer(value as i64));
+                },
+                LONG => {
+                    let start = self.pos;
+                    let line = self.take_str_line()?;
+                    let value: i128 = line.strip_suffix('L').unwrap_or(line).parse().map_err(|_| self.invalid(start))?;
+                    self.push_value(self::long_value(value));
+                },
+                LONG1 | LONG4 => {
+                    let len = match opcode {
+                        LONG1 => self.take(1)?[0] as usize,
+                        _ => u32::from_le_bytes(self.take_array()?) as usize
+                    };
+                    if len > 16 {
+                        return Err(self.fail(at, |offset, path| Error::BadLength { length: len, offset, path }));
+                    }
+
+                    let bytes = self.take(len)?;
+                    let fill = match bytes.last() {
+                        Some(byte) if byte & 0x80 != 0 => 0xff,
+                        _ => 0x00
+                    };
+                    let mut value = [fill; 16];
+                    value[..len].copy_from_slice(bytes);
+                    self.push_value(self::long_value(i128::from_le_bytes(value)));
+                },
+                FLOAT => {
+                    let value: f64 = self.take_parsed_line()?;
+                    self.push_value(value.into());
+                },
+                BINFLOAT => {
+                    let value = f64::from_be_bytes(self.take_array()?);
+                    self.push_value(value.into());
+                },
+                STRING => {
+                    let start = self.pos;
+                    let line = self.take_line()?;
+                    let value = self::unescape_string(line).ok_or_else(|| self.invalid(start))?;
+                    self.push_value(EVEValue::String(value));
+                },
+                BINSTRING | SHORT_BINSTRING => {
+                    let len = match opcode {
+                        BINSTRING => u32::from_le_bytes(self.take_array()?) as usize,
+                        _ => self.take(1)?[0] as usize
+                    };
+                    let value = self.take(len)?;
+                    self.push_value(EVEValue::String(Cow::Borrowed(value)));
+                },
+                UNICODE => {
+                    let start = self.pos;
+                    let line = self.take_line()?;
+                    let value = self::unescape_unicode(line).ok_or_else(|| self.invalid(start))?;
+                    self.push_value(EVEValue::Unicode(value));
+                },
+                BINUNICODE => {
+                    let len = u32::from_le_bytes(self.take_array()?) as usize;
+                    let start = self.pos;
+                    let value = std::str::from_utf8(self.take(len)?)
+                        .map_err(|_| self.fail(start, |offset, path| Error::InvalidUtf8 { offset, path }))?;
+                    self.push_value(EVEValue::Unicode(Cow::Borrowed(value)));
+                },
+                EMPTY_TUPLE => self.push(Node::Tuple(vec![])),
+                TUPLE1 | TUPLE2 | TUPLE3 => {
+                    let len = (opcode - TUPLE1 + 1) as usize;
+                    if self.stack.len() < len {
+                        return Err(self.invalid(at));
+                    }
+                    let items = self.stack.split_off(self.stack.len() - len);
+                    self.push(Node::Tuple(items));
+                },
+                TUPLE => {
+                    let items = self.pop_mark(at)?;
+                    self.push(Node::Tuple(items));
+                },
+                EMPTY_LIST => self.push(Node::List(vec![])),
+                LIST => {
+                    let items = self.pop_mark(at)?;
+                    self.push(Node::List(items));
+                },
+                APPEND => {
+                    let item = self.pop(at)?;
+                    self.append(vec![item], at)?;
+                },
+                APPENDS => {
+                    let items = self.pop_mark(at)?;
+                    self.append(items, at)?;
+                },
+                EMPTY_DICT => self.push(Node::Dict(vec![])),
+                DICT => {
+                    let items = self.pop_mark(at)?;
+                    self.push(Node::Dict(vec![]));
+                    self.set_items(items, at)?;
+                },
+                SETITEM => {
+                    let value = self.pop(at)?;
+                    let key = self.pop(at)?;
+                    self.set_items(vec![key, value], at)?;
+                },
+                SETITEMS => {
+                    let items = self.pop_mark(at)?;
+                    self.set_items(items, at)?;
+                },
+                GLOBAL => {
+                    let global = self.global()?;
+                    self.push_value(global);
+                },
+                REDUCE => {
+                    let args = self.pop(at)?;
+                    let callable = self.pop(at)?;
+                    self.push(Node::Object { kind: ObjectExKind::Ex1, header: vec![callable, args], list: vec![], dict: vec![] });
+                },
+                NEWOBJ => {
+                    let args = self.pop(at)?;
+                    let class = self.pop(at)?;
+                    let mut items = vec![class];
+                    items.extend(self.tuple_items(args, at)?);
+
+                    self.nodes.push(Node::Tuple(items));
+                    let header = self.nodes.len() - 1;
+                    self.push(Node::Object { kind: ObjectExKind::Ex2, header: vec![header], list: vec![], dict: vec![] });
+                },
+                INST => {
+                    let class = self.global()?;
+                    let args = self.pop_mark(at)?;
+                    self.nodes.push(Node::Value(class));
+                    self.instance(self.nodes.len() - 1, args);
+                },
+                OBJ => {
+                    let mut args = self.pop_mark(at)?;
+                    if args.is_empty() {
+                        return Err(self.invalid(at));
+                    }
+                    let class = args.remove(0);
+                    self.instance(class, args);
+                },
+                BUILD => {
+                    let state = self.pop(at)?;
+                    let target = self.top(at)?;
+                    match &mut self.nodes[target] {
+                        Node::Object { header, .. } => header.push(state),
+                        _ => return Err(self.invalid(at))
+                    }
+                },
+                PUT => {
+                    let index = self.take_parsed_line()?;
+                    self.memo_put(index, at)?;
+                },
+                BINPUT => {
+                    let index = self.take(1)?[0] as usize;
+                    self.memo_put(index, at)?;
+                },
+                LONG_BINPUT => {
+                    let index = u32::from_le_bytes(self.take_array()?) as usize;
+                    self.memo_put(index, at)?;
+                },
+                GET => {
+                    let index = self.take_parsed_line()?;
+                    self.memo_get(index, at)?;
+                },
+                BINGET => {
+                    let index = self.take(1)?[0] as usize;
+                    self.memo_get(index, at)?;
+                },
+                LONG_BINGET => {
+                    let index = u32::from_le_bytes(self.take_array()?) as usize;
+                    self.memo_get(index, at)?;
+                },
+                _ => {
+                    log::error!("Unsupported pickle opcode {:#04x}", opcode);
+                    return Err(self.fail(at, |offset, path| Error::InvalidOpcode { opcode, offset, path }));
+                }
+            }
+        }
+    }
+
+    /// Turns a node from the arena into a value, copying anything shared through the memo
+    fn build(&mut self, id: usize, active: &mut Vec<bool>, depth: usize) -> Result<EVEValue<'a>, Error> {
+        // A container holding itself can't be represented as a tree
+        if active[id] {
+            return Err(self.invalid(self.pos - 1));
+        }
+        if depth >= self.limits.max_depth {
+            return Err(self.fail(self.pos - 1, |offset, path| Error::LimitExceeded { limit: Limit::Depth, offset, path }));
+        }
+        // Memo references are copied, so they are charged each time
+        self.charge(self.pos - 1)?;
+        active[id] = true;
+
+        let value = match &self.nodes[id] {
+            Node::Value(value) => value.clone(),
+            Node::Tuple(items) => {
+                let items = items.clone();
+                EVEValue::Tuple(self.build_all(&items, active, depth)?)
+            },
+            Node::List(items) => {
+                let items = items.clone();
+                EVEValue::List(self.build_all(&items, active, depth)?)
+            },
+            Node::Dict(items) => {
+                let mut map = BTreeMap::new();
+                for (key, value) in items.clone() {
+                    let key = match self.build(key, active, depth + 1)?.try_into() {
+                        Ok(key) => key,
+                        Err(_) => return Err(self.fail(self.pos - 1, |offset, path| Error::UnhashableKey { offset, path }))
+                    };
+                    map.insert(key, self.build(value, active, depth + 1)?);
+                }
+                EVEValue::Dict(map)
+            },
+            Node::Object { kind, header, list, dict } => {
+                let (kind, header, list, dict) = (*kind, header.clone(), list.clone(), dict.clone());
+                EVEValue::ObjectEx {
+                    kind,
+                    header: Box::new(EVEValue::Tuple(self.build_all(&header, active, depth)?)),
+                    list: self.build_all(&list, active, depth)?,
+                    dict: dict.into_iter()
+                        .map(|(key, value)| Ok((self.build(key, active, depth + 1)?, self.build(value, active, depth + 1)?)))
+                        .collect::<Result<_, Error>>()?
+                }
+            }
+        };
+
+        active[id] = false;
+        Ok(value)
+    }
+
+    fn build_all(&mut self, ids: &[usize], active: &mut Vec<bool>, depth: usize) -> Result<Vec<EVEValue<'a>>, Error> {
+        ids.iter().map(|id| self.build(*id, active, depth + 1)).collect()
+    }
+}
+
+fn long_value<'a>(value: i128) -> EVEValue<'a> {
+    match i64::try_from(value) {
+        Ok(value) => EVEValue::Integer(value),
+        Err(_) => EVEValue::BigInt(value)
+    }
+}
+
+/// Undoes the `repr` quoting protocol 0 uses for strings
+fn unescape_string(line: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let quote = *line.first()?;
+    if !(quote == b'\'' || quote == b'"') || line.len() < 2 || *line.last()? != quote {
+        return None;
+    }
+
+    let inner = &line[1..line.len() - 1];
+    if !inner.contains(&b'\\') {
+        return Some(Cow::Borrowed(inner));
+    }
+
+    let mut out = Vec::with_capacity(inner.len());
+    let mut bytes = inner.iter().copied();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        match bytes.next()? {
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'a' => out.push(0x07),
+            b'b' => out.push(0x08),
+            b'f' => out.push(0x0c),
+            b'v' => out.push(0x0b),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            d @ b'0'..=b'7' => {
+                // Up to three octal digits
+                let mut value = (d - b'0') as u32;
+                for _ in 0..2 {
+                    match bytes.clone().next() {
+                        Some(d @ b'0'..=b'7') => {
+                            bytes.next();
+                            value = value * 8 + (d - b'0') as u32;
+                        },
+                        _ => break
+                    }
+                }
+                out.push(value as u8);
+            },
+            b => out.push(b)
+        }
+    }
+    Some(Cow::Owned(out))
+}
+
+/// Decodes Python's `raw-unicode-escape`, latin-1 apart from `\u` and `\U` escapes
+fn unescape_unicode(line: &[u8]) -> Option<Cow<'_, str>> {
+    if line.is_ascii() && !line.contains(&b'\\') {
+        return std::str::from_utf8(line).ok().map(Cow::Borrowed);
+    }
+
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        let digits = match &line[i..] {
+            [b'\\', b'u', ..] => 4,
+            [b'\\', b'U', ..] => 8,
+            _ => 0
+        };
+
+        if digits > 0 {
+            let hex = std::str::from_utf8(line.get(i + 2..i + 2 + digits)?).ok()?;
+            out.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+            i += 2 + digits;
+        } else {
+            out.push(line[i] as char);
+            i += 1;
+        }
+    }
+    Some(Cow::Owned(out))
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: &EVEValue) -> Result<(), UnsupportedValue> {
+    match value {
+        EVEValue::None => buf.push(NONE),
+        EVEValue::Bool(true) => buf.push(NEWTRUE),
+        EVEValue::Bool(false) => buf.push(NEWFALSE),
+        EVEValue::Byte(i) => self::encode_integer(buf, *i as i128),
+        EVEValue::Short(i) => self::encode_integer(buf, *i as i128),
+        EVEValue::Integer(i) => self::encode_integer(buf, *i as i128),
+        EVEValue::BigInt(i) => self::encode_integer(buf, *i),
+        EVEValue::Float(f) => {
+            buf.push(BINFLOAT);
+            buf.extend_from_slice(&f.to_be_bytes());
+        },
+        EVEValue::String(s) => {
+            if s.len() < 0x100 {
+                buf.push(SHORT_BINSTRING);
+                buf.push(s.len() as u8);
+            } else {
+                buf.push(BINSTRING);
+                buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            }
+            buf.extend_from_slice(s);
+        },
+        EVEValue::Unicode(s) => {
+            buf.push(BINUNICODE);
+            buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            buf.extend_from_slice(s.as_bytes());
+        },
+        EVEValue::Tuple(vals) => {
+            if vals.is_empty() {
+                buf.push(EMPTY_TUPLE);
+            } else if vals.len() <= 3 {
+                self::encode_all(buf, vals)?;
+                buf.push(TUPLE1 + vals.len() as u8 - 1);
+            } else {
+                buf.push(MARK);
+                self::encode_all(buf, vals)?;
+                buf.push(TUPLE);
+            }
+        },
+        EVEValue::List(vals) => {
+            buf.push(EMPTY_LIST);
+            self::encode_appends(buf, vals)?;
+        },
+        EVEValue::Dict(map) => {
+            buf.push(EMPTY_DICT);
+            if !map.is_empty() {
+                buf.push(MARK);
+                for (key, value) in map {
+                    self::encode_value(buf, &key.clone().into())?;
+                    self::encode_value(buf, value)?;
+                }
+                buf.push(SETITEMS);
+            }
+        },
+        EVEValue::Global(name) => {
+            let (module, name) = name.rsplit_once('.').ok_or(UnsupportedValue("global without a module"))?;
+            buf.push(GLOBAL);
+            for part in [module, name] {
+                buf.extend_from_slice(part.as_bytes());
+                buf.push(b'\n');
+            }
+        },
+        EVEValue::ObjectEx { kind, header, list, dict } => {
+            let header = match header.as_ref() {
+                EVEValue::Tuple(header) => header.as_slice(),
+                _ => return Err(UnsupportedValue("object header that is not a tuple"))
+            };
+            let state = match (kind, header) {
+                (ObjectExKind::Ex1, [callable, args, state @ ..]) if state.len() <= 1 => {
+                    self::encode_value(buf, callable)?;
+                    self::encode_value(buf, args)?;
+                    buf.push(REDUCE);
+                    state.first()
+                },
+                (ObjectExKind::Ex2, [EVEValue::Tuple(new), state @ ..]) if !new.is_empty() && state.len() <= 1 => {
+                    self::encode_value(buf, &new[0])?;
+                    self::encode_value(buf, &EVEValue::Tuple(new[1..].to_vec()))?;
+                    buf.push(NEWOBJ);
+                    state.first()
+                },
+                _ => return Err(UnsupportedValue("object header that is not a reduce tuple"))
+            };
+
+            // Same order as pickle's save_reduce
+            self::encode_appends(buf, list)?;
+            if !dict.is_empty() {
+                buf.push(MARK);
+                for (key, value) in dict {
+                    self::encode_value(buf, key)?;
+                    self::encode_value(buf, value)?;
+                }
+                buf.push(SETITEMS);
+            }
+            if let Some(state) = state {
+                self::encode_value(buf, state)?;
+                buf.push(BUILD);
+            }
+        },
+        EVEValue::Object { .. } => return Err(UnsupportedValue("marshal object")),
+        EVEValue::Buffer(_) => return Err(UnsupportedValue("buffer")),
+        EVEValue::SubStream(_) => return Err(UnsupportedValue("sub stream")),
+        EVEValue::ChecksummedStream { .. } => return Err(UnsupportedValue("checksummed stream")),
+        EVEValue::PackedRow(_) => return Err(UnsupportedValue("packed row")),
+        EVEValue::Pickled(_) => return Err(UnsupportedValue("nested pickle"))
+    }
+    Ok(())
+}
+
+fn encode_all(buf: &mut Vec<u8>, vals: &[EVEValue]) -> Result<(), UnsupportedValue> {
+    for val in vals {
+        self::encode_value(buf, val)?;
+    }
+    Ok(())
+}
+
+fn encode_appends(buf: &mut Vec<u8>, vals: &[EVEValue]) -> Result<(), UnsupportedValue> {
+    if !vals.is_empty() {
+        buf.push(MARK);
+        self::encode_all(buf, vals)?;
+        buf.push(APPENDS);
+    }
+    Ok(())
+}
+
+fn encode_integer(buf: &mut Vec<u8>, i: i128) {
+    if let Ok(i) = u8::try_from(i) {
+        buf.push(BININT1);
+        buf.push(i);
+    } else if let Ok(i) = u16::try_from(i) {
+        buf.push(BININT2);
+        buf.extend_from_slice(&i.to_le_bytes());
+    } else if let Ok(i) = i32::try_from(i) {
+        buf.push(BININT);
+        buf.extend_from_slice(&i.to_le_bytes());
+    } else {
+        let bytes = var_int_bytes(i);
+        buf.push(LONG1);
+        buf.push(bytes.len() as u8);
+        buf.extend_from_slice(&bytes);
+    }
+}
+
+impl fmt::Display for UnsupportedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot pickle {}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedValue {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::HashableEVEValue;
+
+    // Made with Python 2.7's cPickle.dumps at protocols 0, 1 and 2
+    const PROTOCOL_0: &[u8] = b"(dp1\x0aS's'\x0aS'str\x5cn'\x0ap2\x0asS'u'\x0aVuni\x5cu2603\x0ap3\x0asS't'\x0a(I01\x0aI00\x0atp4\x0asS'big'\x0ap5\x0aL1180591620717411303424L\x0asS'int'\x0ap6\x0aI1\x0asS'neg'\x0ap7\x0aI-70000\x0asS'f'\x0aF1.5\x0asS'l'\x0a(lp8\x0aI1\x0aaI2\x0aaNasS'e'\x0a(ts.";
+    const PROTOCOL_1: &[u8] = b"}q\x01(U\x01sU\x04str\x0aq\x02U\x01uX\x06\x00\x00\x00uni\xe2\x98\x83q\x03U\x01t(I01\x0aI00\x0atU\x03bigq\x04L1180591620717411303424L\x0aU\x03intq\x05K\x01U\x03negq\x06J\x90\xee\xfe\xffU\x01fG?\xf8\x00\x00\x00\x00\x00\x00U\x01l]q\x07(K\x01K\x02NeU\x01e)u.";
+    const PROTOCOL_2: &[u8] = b"\x80\x02}q\x01(U\x01sU\x04str\x0aq\x02U\x01uX\x06\x00\x00\x00uni\xe2\x98\x83q\x03U\x01t\x88\x89\x86U\x03bigq\x04\x8a\x09\x00\x00\x00\x00\x00\x00\x00\x00@U\x03intq\x05K\x01U\x03negq\x06J\x90\xee\xfe\xffU\x01fG?\xf8\x00\x00\x00\x00\x00\x00U\x01l]q\x07(K\x01K\x02NeU\x01e)u.";
+
+    fn expected_data() -> EVEValue<'static> {
+        let entries: [(&[u8], EVEValue); 9] = [
+            (b"s", b"str\n"[..].into()),
+            (b"u", "uni\u{2603}".into()),
+            (b"t", EVEValue::Tuple(vec![true.into(), false.into()])),
+            (b"big", EVEValue::BigInt(1 << 70)),
+            (b"int", 1i64.into()),
+            (b"neg", (-70000i64).into()),
+            (b"f", 1.5.into()),
+            (b"l", EVEValue::List(vec![1i64.into(), 2i64.into(), EVEValue::None])),
+            (b"e", EVEValue::Tuple(vec![]))
+        ];
+        EVEValue::Dict(entries.into_iter().map(|(k, v)| (HashableEVEValue::from(k), v)).collect())
+    }
+
+    fn instance(kind: ObjectExKind, header: Vec<EVEValue<'static>>) -> EVEValue<'static> {
+        EVEValue::ObjectEx { kind, header: Box::new(EVEValue::Tuple(header)), list: vec![], dict: vec![] }
+    }
+
+    fn state(key: &'static [u8], value: i64) -> EVEValue<'static> {
+        EVEValue::Dict([(key.into(), value.into())].into_iter().collect())
+    }
+
+    #[test_log::test]
+    fn test_protocols() {
+        for data in [PROTOCOL_0, PROTOCOL_1, PROTOCOL_2] {
+            assert_eq!(decode_pickle(data).unwrap(), expected_data());
+        }
+    }
+
+    #[test_log::test]
+    fn test_instances() {
+        // [Foo(x=1), Old(y=2)] with a new style and an old style class
+        let data = b"\x80\x02]q\x01(c__main__\x0aFoo\x0aq\x02)\x81q\x03}q\x04U\x01xK\x01sb(c__main__\x0aOld\x0aq\x05oq\x06}q\x07U\x01yK\x02sbe.";
+        assert_eq!(decode_pickle(data).unwrap(), EVEValue::List(vec![
+            instance(ObjectExKind::Ex2, vec![EVEValue::Tuple(vec![EVEValue::Global("__main__.Foo".into())]), state(b"x", 1)]),
+            instance(ObjectExKind::Ex1, vec![EVEValue::Global("__main__.Old".into()), EVEValue::Tuple(vec![]), state(b"y", 2)])
+        ]));
+
+        let data = b"(lp1\x0accopy_reg\x0a_reconstructor\x0ap2\x0a(c__main__\x0aFoo\x0ap3\x0ac__builtin__\x0aobject\x0ap4\x0aNtRp5\x0a(dp6\x0aS'x'\x0aI1\x0asba(i__main__\x0aOld\x0ap7\x0a(dp8\x0aS'y'\x0aI2\x0asba.";
+        assert_eq!(decode_pickle(data).unwrap(), EVEValue::List(vec![
+            instance(ObjectExKind::Ex1, vec![
+                EVEValue::Global("copy_reg._reconstructor".into()),
+                EVEValue::Tuple(vec![EVEValue::Global("__main__.Foo".into()), EVEValue::Global("__builtin__.object".into()), EVEValue::None]),
+                state(b"x", 1)
+            ]),
+            instance(ObjectExKind::Ex1, vec![EVEValue::Global("__main__.Old".into()), EVEValue::Tuple(vec![]), state(b"y", 2)])
+        ]));
+    }
+
+    #[test_log::test]
+    fn test_memo() {
+        // A list stored in the memo before its items are appended
+        let data = b"\x80\x02]q\x01(K\x01K\x02eh\x01\x86.";
+        let list = EVEValue::List(vec![1i64.into(), 2i64.into()]);
+        assert_eq!(decode_pickle(data).unwrap(), EVEValue::Tuple(vec![list.clone(), list]));
+
+        // A list containing itself
+        let data = b"\x80\x02]q\x01h\x01a.";
+        assert!(matches!(decode_pickle(data), Err(Error::InvalidPickle { .. })));
+    }
+
+    #[test_log::test]
+    fn test_errors() {
+        assert!(matches!(decode_pickle(b"\x80\x02K"), Err(Error::Truncated { offset: 3, .. })));
+        assert!(matches!(decode_pickle(b"\x80\x02\x82\x01."), Err(Error::InvalidOpcode { opcode: 0x82, offset: 2, .. })));
+        assert!(matches!(decode_pickle(b"\x80\x02e."), Err(Error::InvalidPickle { offset: 2, .. })));
+        assert!(matches!(decode_pickle(b"\x80\x02}]K\x01s."), Err(Error::UnhashableKey { .. })));
+    }
+
+    #[test_log::test]
+    fn test_round_trip() {
+        let values = [
+            expected_data(),
+            instance(ObjectExKind::Ex2, vec![EVEValue::Tuple(vec![EVEValue::Global("__main__.Foo".into()), 3i64.into()]), state(b"x", 1)]),
+            EVEValue::ObjectEx {
+                kind: ObjectExKind::Ex1,
+                header: Box::new(EVEValue::Tuple(vec![EVEValue::Global("collections.OrderedDict".into()), EVEValue::Tuple(vec![])])),
+                list: vec![],
+                dict: vec![(b"a"[..].into(), EVEValue::Tuple(vec![1i64.into(), 2i64.into(), 3i64.into(), 4i64.into()]))]
+            },
+            EVEValue::List(vec![(-1i64).into(), 300i64.into(), 70000i64.into(), (1i64 << 40).into(), EVEValue::BigInt(-(1 << 100))])
+        ];
+        for value in values {
+            let data = encode_pickle(&value).unwrap();
+            assert_eq!(decode_pickle(&data).unwrap(), value);
+        }
+
+        assert_eq!(encode_pickle(&EVEValue::Buffer(b""[..].into())), Err(UnsupportedValue("buffer")));
+    }
+
+    #[test_log::test]
+    fn test_memo_work_limit() {
+        // Each tuple holds the one before it twice, doubling the copies
+        let mut data = b"\x80\x02]q\x00K\x01a".to_vec();
+        for i in 1..=30u8 {
+            data.extend_from_slice(&[BINGET, i - 1, BINGET, i - 1, TUPLE2, BINPUT, i]);
+        }
+        data.push(STOP);
+        let limits = DecodeLimits { max_work: 10_000, ..Default::default() };
+        let result = Unpickler::new(&data, 0, vec![], limits, 0, 0).load();
+        assert!(matches!(result, Err(Error::LimitExceeded { limit: Limit::Work, .. })));
+    }
+}
@@ -0,0 +1,287 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::convert::FromEve;
+use crate::error::{FromEveError, QueryError};
+use crate::value::{EVEValue, HashableEVEValue};
+
+/// One step of a path like `header.source[1]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step<'p> {
+    Key(&'p str),
+    Index(usize)
+}
+
+/// Accessors for picking values out of received packets. Checksummed
+/// streams and pickles are looked through, and integers are the same
+/// whatever width they were sent with.
+impl<'a> EVEValue<'a> {
+    /// The value inside any checksummed streams and pickles
+    fn inner(&self) -> &EVEValue<'a> {
+        match self {
+            EVEValue::ChecksummedStream { value, .. } | EVEValue::Pickled(value) => value.inner(),
+            value => value
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self.inner(), EVEValue::None)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self.inner() {
+            EVEValue::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    /// An int or long of any width
+    pub fn as_i128(&self) -> Option<i128> {
+        match *self.inner() {
+            EVEValue::Byte(i) => Some(i.into()),
+            EVEValue::Short(i) => Some(i.into()),
+            EVEValue::Integer(i) => Some(i.into()),
+            EVEValue::BigInt(i) => Some(i),
+            _ => None
+        }
+    }
+
+    /// An int, or a long small enough to fit
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_i128().and_then(|i| i64::try_from(i).ok())
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self.inner() {
+            EVEValue::Float(f) => Some(f),
+            _ => None
+        }
+    }
+
+    /// A unicode, or a str that is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        match self.inner() {
+            EVEValue::Unicode(s) => Some(s),
+            EVEValue::String(s) => std::str::from_utf8(s).ok(),
+            _ => None
+        }
+    }
+
+    /// The contents of a str or buffer
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self.inner() {
+            EVEValue::String(s) | EVEValue::Buffer(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_tuple(&self) -> Option<&[EVEValue<'a>]> {
+        match self.inner() {
+            EVEValue::Tuple(vals) => Some(vals),
+            _ => None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[EVEValue<'a>]> {
+        match self.inner() {
+            EVEValue::List(vals) => Some(vals),
+            _ => None
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<HashableEVEValue<'a>, EVEValue<'a>>> {
+        match self.inner() {
+            EVEValue::Dict(map) => Some(map),
+            _ => None
+        }
+    }
+
+    /// Looks up a str or unicode key of a dict, or an attribute of an
+    /// object. Objects keep their attributes in their args, or for reduced
+    /// objects in the state that follows the callable and its arguments.
+    pub fn get(&self, key: &str) -> Option<&EVEValue<'a>> {
+        self.entry(key).ok().flatten()
+    }
+
+    /// Like Python's `value[index]`, an item of a tuple, list or sub stream
+    /// or an int key of a dict. Objects are indexed by their args.
+    pub fn index(&self, index: usize) -> Option<&EVEValue<'a>> {
+        self.item(index).ok().flatten()
+    }
+
+    /// Follows a path of attributes and indexes such as
+    /// `header.source[1]`, each step done like `get` or `index`.
+    pub fn query(&self, path: &str) -> Result<&EVEValue<'a>, QueryError> {
+        let mut value = self;
+        for (step, start, end) in parse_path(path)? {
+            let found = match step {
+                Step::Key(key) => value.entry(key),
+                Step::Index(index) => value.item(index)
+            };
+            value = found
+                .map_err(|expected| QueryError::WrongType { path: path[..start].to_string(), expected, found: value.inner().type_name() })?
+                .ok_or_else(|| QueryError::Missing { path: path[..end].to_string() })?;
+        }
+        Ok(value)
+    }
+
+    /// Follows a path like `query` and converts the value it ends at
+    pub fn query_as<T: FromEve>(&self, path: &str) -> Result<T, QueryError> {
+        T::from_eve(self.query(path)?).map_err(|err| match err {
+            FromEveError::WrongType { expected, found } => QueryError::WrongType { path: path.to_string(), expected, found },
+            source => QueryError::Convert { path: path.to_string(), source }
+        })
+    }
+
+    /// `get`, failing with what was expected if the value has no keys
+    fn entry(&self, key: &str) -> Result<Option<&EVEValue<'a>>, &'static str> {
+        match self.inner() {
+            EVEValue::Dict(map) => {
+                // str and unicode keys compare the same, so this finds either.
+                // `get` would need the key to borrow for as long as the
+                // map's do, so compare in place rather than copy it.
+                let key = HashableEVEValue::String(Cow::Borrowed(key.as_bytes()));
+                Ok(map.iter().find(|(k, _)| **k == key).map(|(_, value)| value))
+            },
+            EVEValue::Object { args, .. } => args.entry(key).map_err(|_| "dict or object with attributes"),
+            EVEValue::ObjectEx { header, .. } => match header.index(2) {
+                Some(state) => state.entry(key).map_err(|_| "dict or object with attributes"),
+                None => Ok(None)
+            },
+            _ => Err("dict or object")
+        }
+    }
+
+    /// `index`, failing with what was expected if the value can't be indexed
+    fn item(&self, index: usize) -> Result<Option<&EVEValue<'a>>, &'static str> {
+        match self.inner() {
+            EVEValue::Tuple(vals) | EVEValue::List(vals) | EVEValue::SubStream(vals) => Ok(vals.get(index)),
+            EVEValue::Dict(map) => Ok(i64::try_from(index).ok().and_then(|index| map.get(&HashableEVEValue::Integer(index)))),
+            EVEValue::Object { args, .. } => args.item(index).map_err(|_| "sequence or object with args"),
+            _ => Err("sequence")
+        }
+    }
+}
+
+/// Splits a path into its steps, with where each starts and ends in it
+fn parse_path(path: &str) -> Result<Vec<(Step<'_>, usize, usize)>, QueryError> {
+    let mut steps = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        let start = path.len() - rest.len();
+        let step = if let Some(inner) = rest.strip_prefix('[') {
+            let close = inner.find(']').ok_or(QueryError::InvalidPath { offset: start })?;
+            let index = inner[..close].parse().map_err(|_| QueryError::InvalidPath { offset: start + 1 })?;
+            rest = &inner[close + 1..];
+            Step::Index(index)
+        } else {
+            // Only the first name can go without a dot
+            let name = match rest.strip_prefix('.') {
+                Some(name) => name,
+                None if start == 0 => rest,
+                None => return Err(QueryError::InvalidPath { offset: start })
+            };
+            let len = name.find(['.', '[']).unwrap_or(name.len());
+            if len == 0 {
+                return Err(QueryError::InvalidPath { offset: start });
+            }
+            rest = &name[len..];
+            Step::Key(&name[..len])
+        };
+        steps.push((step, start, path.len() - rest.len()));
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_payload;
+    use crate::tests::test_data;
+    use crate::value::ObjectExKind;
+    use super::*;
+
+    fn dict(entries: &[(&str, EVEValue<'static>)]) -> EVEValue<'static> {
+        let map = entries.iter()
+            .map(|(key, value)| (HashableEVEValue::Unicode(key.to_string().into()), value.clone()))
+            .collect();
+        EVEValue::Dict(map)
+    }
+
+    fn key_val(entries: &[(&str, EVEValue<'static>)]) -> EVEValue<'static> {
+        EVEValue::Object { class: "util.KeyVal".into(), args: Box::new(dict(entries)) }
+    }
+
+    #[test_log::test]
+    fn test_accessors() {
+        for value in [EVEValue::Byte(7), EVEValue::Short(7), EVEValue::Integer(7), EVEValue::BigInt(7)] {
+            assert_eq!(value.as_i64(), Some(7));
+        }
+        assert_eq!(EVEValue::BigInt(i128::MAX).as_i64(), None);
+        assert_eq!(EVEValue::BigInt(i128::MAX).as_i128(), Some(i128::MAX));
+        assert_eq!(EVEValue::Bool(true).as_i64(), None);
+        assert_eq!(EVEValue::Pickled(Box::new(EVEValue::Float(1.5))).as_f64(), Some(1.5));
+
+        assert_eq!(EVEValue::String(b"a"[..].into()).as_str(), Some("a"));
+        assert_eq!(EVEValue::String(b"\xff"[..].into()).as_str(), None);
+        assert_eq!(EVEValue::String(b"\xff"[..].into()).as_bytes(), Some(&b"\xff"[..]));
+        assert_eq!(EVEValue::Unicode("a".into()).as_bytes(), None);
+        assert_eq!(EVEValue::Tuple(vec![]).as_list(), None);
+        assert!(EVEValue::None.is_none());
+
+        let keyval = key_val(&[("service", "machoNet".into())]);
+        assert_eq!(keyval.get("service").and_then(EVEValue::as_str), Some("machoNet"));
+        assert_eq!(keyval.get("missing"), None);
+        assert_eq!(keyval.index(0), None);
+
+        let mut map = BTreeMap::new();
+        map.insert(HashableEVEValue::Byte(1), EVEValue::None);
+        assert_eq!(EVEValue::Dict(map).index(1), Some(&EVEValue::None));
+
+        let reduced = EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex2,
+            header: Box::new(EVEValue::Tuple(vec![
+                EVEValue::Global("util.Row".into()),
+                EVEValue::Tuple(vec![]),
+                dict(&[("id", 1.into())])
+            ])),
+            list: vec![],
+            dict: vec![]
+        };
+        assert_eq!(reduced.get("id").and_then(EVEValue::as_i64), Some(1));
+    }
+
+    #[test_log::test]
+    fn test_query() {
+        let call = decode_payload(test_data::MACHONET_GETTIME).unwrap().remove(0);
+        assert_eq!(call.query("[2][2]").unwrap().as_str(), Some("machoNet"));
+        assert_eq!(call.query("[1][2]").unwrap().as_i64(), Some(7));
+        assert_eq!(call.query("[4][0][1][0][1]").unwrap().as_str(), Some("GetTime"));
+        assert_eq!(call.query_as::<u32>("[4][0][1][0][3].machoVersion"), Ok(1));
+        assert_eq!(call.query(""), Ok(&call));
+
+        let header = key_val(&[("source", key_val(&[("service", "machoNet".into())]))]);
+        assert_eq!(header.query_as::<String>("source.service").unwrap(), "machoNet");
+
+        assert_eq!(call.query("[2].service"), Err(QueryError::WrongType {
+            path: "[2]".to_string(),
+            expected: "dict or object with attributes",
+            found: "object"
+        }));
+        assert_eq!(call.query("[4][0][1][0][3].machoVersion[0]"), Err(QueryError::WrongType {
+            path: "[4][0][1][0][3].machoVersion".to_string(),
+            expected: "sequence",
+            found: "int"
+        }));
+        assert_eq!(call.query("[4][1]"), Err(QueryError::Missing { path: "[4][1]".to_string() }));
+        assert_eq!(
+            header.query_as::<i64>("source.service").unwrap_err().to_string(),
+            "expected int, found unicode at source.service"
+        );
+        assert_eq!(call.query_as::<u8>("[2][1]").unwrap_err().to_string(), "value out of range for u8 at [2][1]");
+        assert_eq!(header.query("nope").unwrap_err().to_string(), "no value at nope");
+        assert_eq!(call.query("[0").unwrap_err(), QueryError::InvalidPath { offset: 0 });
+        assert_eq!(call.query("[x]").unwrap_err(), QueryError::InvalidPath { offset: 1 });
+        assert_eq!(header.query("source..service").unwrap_err(), QueryError::InvalidPath { offset: 6 });
+        assert_eq!(header.query("[0]source").unwrap_err(), QueryError::InvalidPath { offset: 3 });
+    }
+}
@@ -0,0 +1,814 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use crate::error::ReprError;
+use crate::value::{EVEValue, HashableEVEValue, ObjectExKind};
+
+/// Multi-line printer for values, for packet logs.
+///
+/// Containers that fit in the remaining width stay on one line, others get
+/// a line per item. Anything nested deeper than `max_depth` is elided and
+/// strings longer than `max_width` are cut short, unless told otherwise.
+/// Without either the output reads back with `parse` to the same values.
+#[derive(Debug, Clone)]
+pub struct PrettyPrinter {
+    max_depth: Option<usize>,
+    max_width: Option<usize>,
+    indent: usize,
+    truncate_strings: bool
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_width: Some(100),
+            indent: 4,
+            truncate_strings: true
+        }
+    }
+}
+
+impl PrettyPrinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn max_width(mut self, width: usize) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+
+    /// Never break lines or cut strings short
+    pub fn unlimited_width(mut self) -> Self {
+        self.max_width = None;
+        self
+    }
+
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn truncate_strings(mut self, truncate: bool) -> Self {
+        self.truncate_strings = truncate;
+        self
+    }
+
+    pub fn print(&self, value: &EVEValue) -> String {
+        let doc = Doc::build(value, self.max_depth, self.max_width.filter(|_| self.truncate_strings));
+        let mut out = String::new();
+        self.write_block(&mut out, &doc, 0, 0);
+        out
+    }
+
+    /// Prints on one line, cut short with `...` past `max_width` without
+    /// writing out the rest
+    pub(crate) fn print_line(&self, value: &EVEValue) -> String {
+        let doc = Doc::build(value, self.max_depth, self.max_width.filter(|_| self.truncate_strings));
+        let mut line = Bounded { out: String::new(), left: self.max_width.unwrap_or(usize::MAX) };
+        if doc.write_inline(&mut line).is_ok() {
+            return line.out;
+        }
+
+        let mut short: String = line.out.chars().take(line.out.chars().count().saturating_sub(3)).collect();
+        short.push_str("...");
+        short
+    }
+
+    fn write_block(&self, out: &mut String, doc: &Doc, level: usize, column: usize) {
+        let Doc::Group { open, items, close, one_tuple } = doc else {
+            return doc.write_inline(out).expect("writing to a String can't fail");
+        };
+
+        let mut inline = String::new();
+        doc.write_inline(&mut inline).expect("writing to a String can't fail");
+        let fits = self.max_width.is_none_or(|width| column + inline.chars().count() <= width);
+        if fits || items.is_empty() {
+            out.push_str(&inline);
+            return;
+        }
+
+        let indent = " ".repeat((level + 1) * self.indent);
+        out.push_str(open);
+        out.push('\n');
+        for (i, (prefix, item)) in items.iter().enumerate() {
+            out.push_str(&indent);
+            out.push_str(prefix);
+            self.write_block(out, item, level + 1, indent.len() + prefix.chars().count());
+            // A trailing comma only where it's needed to tell a tuple of one apart
+            if i + 1 < items.len() || *one_tuple {
+                out.push(',');
+            }
+            out.push('\n');
+        }
+        out.push_str(&" ".repeat(level * self.indent));
+        out.push_str(close);
+    }
+}
+
+/// A value laid out for printing
+enum Doc {
+    Atom(String),
+    Group {
+        open: String,
+        // Each item with what goes before it, like a dict key
+        items: Vec<(String, Doc)>,
+        close: &'static str,
+        // Needs a trailing comma on one line, like `(1,)`
+        one_tuple: bool
+    }
+}
+
+impl Doc {
+    fn build(value: &EVEValue, depth: Option<usize>, width: Option<usize>) -> Doc {
+        let group = |open: String, items: Vec<(String, Doc)>, close| Doc::Group { open, items, close, one_tuple: false };
+        let values = |vals: &[EVEValue]| -> Vec<(String, Doc)> {
+            vals.iter().map(|val| (String::new(), Doc::build(val, depth.map(|d| d - 1), width))).collect()
+        };
+
+        // Containers past the depth limit are elided
+        if depth == Some(0) {
+            let elided = match value {
+                EVEValue::Tuple(_) => Some(("(".to_string(), ")")),
+                EVEValue::List(_) => Some(("[".to_string(), "]")),
+                EVEValue::Dict(_) => Some(("{".to_string(), "}")),
+                EVEValue::Object { class, .. } => Some((format!("{}(", class), ")")),
+                EVEValue::ObjectEx { kind, .. } => Some((format!("{}(", object_ex_name(*kind)), ")")),
+                EVEValue::SubStream(_) => Some(("SubStream(".to_string(), ")")),
+                EVEValue::PackedRow(_) => Some(("DBRow(".to_string(), ")")),
+                _ => None
+            };
+            if let Some((open, close)) = elided {
+                return Doc::Atom(format!("{}...{}", open, close));
+            }
+        }
+
+        match value {
+            EVEValue::Tuple(vals) => Doc::Group {
+                open: "(".to_string(),
+                items: values(vals),
+                close: ")",
+                one_tuple: vals.len() == 1
+            },
+            EVEValue::List(vals) => group("[".to_string(), values(vals), "]"),
+            EVEValue::Dict(map) => group("{".to_string(), map.iter().map(|(key, value)| {
+                let mut prefix = String::new();
+                write_hashable(&mut prefix, key).expect("writing to a String can't fail");
+                prefix.push_str(": ");
+                (prefix, Doc::build(value, depth.map(|d| d - 1), width))
+            }).collect(), "}"),
+            // Objects look like a call to their class
+            EVEValue::Object { class, args } => {
+                let (items, one_tuple) = match args.as_ref() {
+                    EVEValue::Tuple(vals) => (values(vals), vals.len() == 1),
+                    args => (values(std::slice::from_ref(args)), false)
+                };
+                Doc::Group { open: format!("{}(", class), items, close: ")", one_tuple }
+            },
+            EVEValue::ObjectEx { kind, header, list, dict } => {
+                let inner = depth.map(|d| d - 1);
+                let dict = dict.iter().map(|(key, value)| {
+                    let mut prefix = String::new();
+                    Doc::build(key, None, None).write_inline(&mut prefix).expect("writing to a String can't fail");
+                    prefix.push_str(": ");
+                    (prefix, Doc::build(value, inner.map(|d| d.saturating_sub(1)), width))
+                }).collect();
+                group(format!("{}(", object_ex_name(*kind)), vec![
+                    (String::new(), Doc::build(header, inner, width)),
+                    (String::new(), group("[".to_string(), list.iter().map(|val| {
+                        (String::new(), Doc::build(val, inner.map(|d| d.saturating_sub(1)), width))
+                    }).collect(), "]")),
+                    (String::new(), group("{".to_string(), dict, "}"))
+                ], ")")
+            },
+            EVEValue::SubStream(vals) => group("SubStream(".to_string(), values(vals), ")"),
+            EVEValue::PackedRow(row) => group("DBRow(".to_string(), row.descriptor().columns().iter()
+                .zip(row.values())
+                .map(|(column, value)| (format!("{}=", column.name), Doc::build(&value.to_value(), depth.map(|d| d - 1), width)))
+                .collect(), ")"),
+            // These are transparent on the Python side
+            EVEValue::ChecksummedStream { value, .. } | EVEValue::Pickled(value) => Doc::build(value, depth, width),
+            scalar => {
+                let mut atom = String::new();
+                write_scalar(&mut atom, scalar).expect("writing to a String can't fail");
+                if let Some(width) = width {
+                    if atom.chars().count() > width {
+                        atom = atom.chars().take(width.saturating_sub(3)).collect();
+                        atom.push_str("...");
+                    }
+                }
+                Doc::Atom(atom)
+            }
+        }
+    }
+
+    fn write_inline(&self, out: &mut impl Write) -> fmt::Result {
+        match self {
+            Doc::Atom(atom) => out.write_str(atom),
+            Doc::Group { open, items, close, one_tuple } => {
+                out.write_str(open)?;
+                for (i, (prefix, item)) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.write_str(", ")?;
+                    }
+                    out.write_str(prefix)?;
+                    item.write_inline(out)?;
+                }
+                if *one_tuple {
+                    out.write_char(',')?;
+                }
+                out.write_str(close)
+            }
+        }
+    }
+}
+
+/// Takes up to `left` chars, failing once there are more
+struct Bounded {
+    out: String,
+    left: usize
+}
+
+impl Write for Bounded {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.left == 0 {
+                return Err(fmt::Error);
+            }
+            self.out.push(c);
+            self.left -= 1;
+        }
+        Ok(())
+    }
+}
+
+fn object_ex_name(kind: ObjectExKind) -> &'static str {
+    match kind {
+        ObjectExKind::Ex1 => "ObjectEx1",
+        ObjectExKind::Ex2 => "ObjectEx2"
+    }
+}
+
+/// Python 2's `repr` of a float, the shortest string that reads back the
+/// same with an exponent only for very large or small numbers
+fn write_float(out: &mut impl Write, f: f64) -> fmt::Result {
+    if f.is_nan() {
+        return out.write_str("nan");
+    }
+    if f.is_infinite() {
+        return out.write_str(if f > 0.0 { "inf" } else { "-inf" });
+    }
+
+    let sci = format!("{:e}", f);
+    let (mantissa, exp) = sci.split_once('e').expect("exponent format has an e");
+    let exp: i32 = exp.parse().expect("exponent is an integer");
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa)
+    };
+    let digits = mantissa.replace('.', "");
+
+    out.write_str(sign)?;
+    if (-4..16).contains(&exp) {
+        if exp < 0 {
+            write!(out, "0.{}{}", "0".repeat((-exp - 1) as usize), digits)
+        } else {
+            let int_len = exp as usize + 1;
+            if digits.len() <= int_len {
+                write!(out, "{}{}.0", digits, "0".repeat(int_len - digits.len()))
+            } else {
+                write!(out, "{}.{}", &digits[..int_len], &digits[int_len..])
+            }
+        }
+    } else {
+        out.write_str(&digits[..1])?;
+        if digits.len() > 1 {
+            write!(out, ".{}", &digits[1..])?;
+        }
+        write!(out, "e{}{:02}", if exp < 0 { '-' } else { '+' }, exp.abs())
+    }
+}
+
+fn quote_for(has_single: bool, has_double: bool) -> char {
+    if has_single && !has_double { '"' } else { '\'' }
+}
+
+fn write_str(out: &mut impl Write, s: &[u8]) -> fmt::Result {
+    let quote = quote_for(s.contains(&b'\''), s.contains(&b'"'));
+    out.write_char(quote)?;
+    for &b in s {
+        match b {
+            b'\\' => out.write_str("\\\\")?,
+            b'\t' => out.write_str("\\t")?,
+            b'\n' => out.write_str("\\n")?,
+            b'\r' => out.write_str("\\r")?,
+            _ if b == quote as u8 => write!(out, "\\{}", quote)?,
+            0x20..=0x7e => out.write_char(b as char)?,
+            _ => write!(out, "\\x{:02x}", b)?
+        }
+    }
+    out.write_char(quote)
+}
+
+fn write_unicode(out: &mut impl Write, s: &str) -> fmt::Result {
+    let quote = quote_for(s.contains('\''), s.contains('"'));
+    write!(out, "u{}", quote)?;
+    for c in s.chars() {
+        match c {
+            '\\' => out.write_str("\\\\")?,
+            '\t' => out.write_str("\\t")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            _ if c == quote => write!(out, "\\{}", quote)?,
+            ' '..='~' => out.write_char(c)?,
+            _ if (c as u32) < 0x100 => write!(out, "\\x{:02x}", c as u32)?,
+            _ if (c as u32) < 0x10000 => write!(out, "\\u{:04x}", c as u32)?,
+            _ => write!(out, "\\U{:08x}", c as u32)?
+        }
+    }
+    out.write_char(quote)
+}
+
+/// Values that aren't containers
+fn write_scalar(out: &mut impl Write, value: &EVEValue) -> fmt::Result {
+    match value {
+        EVEValue::None => out.write_str("None"),
+        EVEValue::Bool(true) => out.write_str("True"),
+        EVEValue::Bool(false) => out.write_str("False"),
+        EVEValue::Byte(i) => write!(out, "{}", i),
+        EVEValue::Short(i) => write!(out, "{}", i),
+        EVEValue::Integer(i) => write!(out, "{}", i),
+        EVEValue::BigInt(i) => write!(out, "{}L", i),
+        EVEValue::Float(f) => write_float(out, *f),
+        EVEValue::String(s) => write_str(out, s),
+        EVEValue::Unicode(s) => write_unicode(out, s),
+        EVEValue::Buffer(b) => {
+            out.write_str("buffer(")?;
+            write_str(out, b)?;
+            out.write_char(')')
+        },
+        // Globals are names in Python code
+        EVEValue::Global(name) => out.write_str(name),
+        container => {
+            let doc = Doc::build(container, None, None);
+            doc.write_inline(out)
+        }
+    }
+}
+
+fn write_hashable(out: &mut impl Write, value: &HashableEVEValue) -> fmt::Result {
+    match value {
+        HashableEVEValue::Tuple(vals) => {
+            out.write_char('(')?;
+            for (i, val) in vals.iter().enumerate() {
+                if i > 0 {
+                    out.write_str(", ")?;
+                }
+                write_hashable(out, val)?;
+            }
+            if vals.len() == 1 {
+                out.write_char(',')?;
+            }
+            out.write_char(')')
+        },
+        HashableEVEValue::Bool(b) => write_scalar(out, &EVEValue::Bool(*b)),
+        HashableEVEValue::Byte(i) => write!(out, "{}", i),
+        HashableEVEValue::Short(i) => write!(out, "{}", i),
+        HashableEVEValue::Integer(i) => write!(out, "{}", i),
+        HashableEVEValue::BigInt(i) => write!(out, "{}L", i),
+        HashableEVEValue::Float(f) => write_float(out, *f),
+        HashableEVEValue::String(s) => write_str(out, s),
+        HashableEVEValue::Unicode(s) => write_unicode(out, s),
+        HashableEVEValue::Buffer(b) => {
+            out.write_str("buffer(")?;
+            write_str(out, b)?;
+            out.write_char(')')
+        },
+        HashableEVEValue::None => out.write_str("None")
+    }
+}
+
+/// Renders the value the way Python 2's `repr` would
+impl fmt::Display for EVEValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Doc::build(self, None, None).write_inline(f)
+    }
+}
+
+impl fmt::Display for HashableEVEValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hashable(f, self)
+    }
+}
+
+/// Reads values back from their repr, as written by `Display` or a
+/// `PrettyPrinter` that leaves everything in, separated by whitespace.
+///
+/// Integers come back as `Integer` whatever width they had, or `BigInt` for
+/// longs. Packed rows leave out their column types so can't be read back.
+pub fn parse(text: &str) -> Result<Vec<EVEValue<'static>>, ReprError> {
+    let mut parser = Parser { text, pos: 0 };
+    let mut values = Vec::new();
+    parser.skip_space();
+    while parser.pos < text.len() {
+        values.push(parser.value()?);
+        parser.skip_space();
+    }
+    Ok(values)
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    pos: usize
+}
+
+impl<'t> Parser<'t> {
+    fn rest(&self) -> &'t str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_space(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), ReprError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(ReprError::Expected { expected: token, offset: self.pos })
+        }
+    }
+
+    /// Takes the longest run of characters matching `pred`
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'t str {
+        let rest = self.rest();
+        let len = rest.find(|c| !pred(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn value(&mut self) -> Result<EVEValue<'static>, ReprError> {
+        self.skip_space();
+        let rest = self.rest();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                // A single item without a comma is only in parentheses
+                match self.items(")")? {
+                    (items, false) if items.len() == 1 => Ok(items.into_iter().next().expect("one item")),
+                    (items, _) => Ok(EVEValue::Tuple(items))
+                }
+            },
+            Some('[') => {
+                self.pos += 1;
+                Ok(EVEValue::List(self.items("]")?.0))
+            },
+            Some('{') => {
+                self.pos += 1;
+                self.dict()
+            },
+            Some('\'' | '"') => Ok(EVEValue::String(self.str()?.into())),
+            Some('u') if rest[1..].starts_with(['\'', '"']) => {
+                self.pos += 1;
+                Ok(EVEValue::Unicode(self.unicode()?.into()))
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => self.name(),
+            _ => Err(ReprError::Expected { expected: "a value", offset: self.pos })
+        }
+    }
+
+    /// Items up to `close`, and whether there was a comma after the last one
+    fn items(&mut self, close: &'static str) -> Result<(Vec<EVEValue<'static>>, bool), ReprError> {
+        let mut items = Vec::new();
+        loop {
+            if self.eat(close) {
+                return Ok((items, true));
+            }
+            items.push(self.value()?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                return Ok((items, false));
+            }
+        }
+    }
+
+    /// Key value pairs up to the closing brace, with where each key started
+    fn pairs(&mut self) -> Result<Vec<(usize, EVEValue<'static>, EVEValue<'static>)>, ReprError> {
+        let mut pairs = Vec::new();
+        loop {
+            if self.eat("}") {
+                return Ok(pairs);
+            }
+            self.skip_space();
+            let offset = self.pos;
+            let key = self.value()?;
+            self.expect(":")?;
+            pairs.push((offset, key, self.value()?));
+            if !self.eat(",") {
+                self.expect("}")?;
+                return Ok(pairs);
+            }
+        }
+    }
+
+    fn dict(&mut self) -> Result<EVEValue<'static>, ReprError> {
+        let mut map = BTreeMap::new();
+        for (offset, key, value) in self.pairs()? {
+            let key = key.try_into().map_err(|_| ReprError::UnhashableKey { offset })?;
+            map.insert(key, value);
+        }
+        Ok(EVEValue::Dict(map))
+    }
+
+    fn str(&mut self) -> Result<Vec<u8>, ReprError> {
+        // Everything is below 0x100, characters outside ASCII are taken as UTF-8
+        Ok(self.quoted(false)?.into_iter().map(|b| b as u8).collect())
+    }
+
+    fn unicode(&mut self) -> Result<String, ReprError> {
+        let offset = self.pos;
+        self.quoted(true)?.into_iter()
+            .map(|c| char::from_u32(c).ok_or(ReprError::InvalidEscape { offset }))
+            .collect()
+    }
+
+    /// Reads a quoted string into code points, or bytes for a str
+    fn quoted(&mut self, unicode: bool) -> Result<Vec<u32>, ReprError> {
+        let quote = self.peek().ok_or(ReprError::Expected { expected: "a string", offset: self.pos })?;
+        self.pos += 1;
+
+        let mut out = Vec::new();
+        loop {
+            let offset = self.pos;
+            let c = self.peek().ok_or(ReprError::Expected { expected: "a closing quote", offset })?;
+            self.pos += c.len_utf8();
+            match c {
+                _ if c == quote => return Ok(out),
+                '\\' => {
+                    let escape = self.peek().ok_or(ReprError::InvalidEscape { offset })?;
+                    self.pos += escape.len_utf8();
+                    let digits = match escape {
+                        '\\' | '\'' | '"' => {
+                            out.push(escape as u32);
+                            continue;
+                        },
+                        't' | 'n' | 'r' => {
+                            out.push(match escape { 't' => '\t', 'n' => '\n', _ => '\r' } as u32);
+                            continue;
+                        },
+                        'x' => 2,
+                        'u' if unicode => 4,
+                        'U' if unicode => 8,
+                        _ => return Err(ReprError::InvalidEscape { offset })
+                    };
+                    let code = self.rest().get(..digits)
+                        .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .ok_or(ReprError::InvalidEscape { offset })?;
+                    self.pos += digits;
+                    out.push(code);
+                },
+                _ if unicode => out.push(c as u32),
+                _ => out.extend(c.to_string().bytes().map(u32::from))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<EVEValue<'static>, ReprError> {
+        let offset = self.pos;
+        let token = self.take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'));
+
+        if let Some(digits) = token.strip_suffix('L') {
+            return digits.parse().map(EVEValue::BigInt).map_err(|_| ReprError::InvalidNumber { offset });
+        }
+        if token.contains(['.', 'e', 'E', 'i', 'n']) {
+            return token.parse().map(EVEValue::Float).map_err(|_| ReprError::InvalidNumber { offset });
+        }
+        match token.parse() {
+            Ok(i) => Ok(EVEValue::Integer(i)),
+            Err(_) => token.parse().map(EVEValue::BigInt).map_err(|_| ReprError::InvalidNumber { offset })
+        }
+    }
+
+    /// Keywords, globals and anything that looks like a call
+    fn name(&mut self) -> Result<EVEValue<'static>, ReprError> {
+        let offset = self.pos;
+        let name = self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '.');
+        match name {
+            "None" => return Ok(EVEValue::None),
+            "True" => return Ok(EVEValue::Bool(true)),
+            "False" => return Ok(EVEValue::Bool(false)),
+            "inf" => return Ok(EVEValue::Float(f64::INFINITY)),
+            "nan" => return Ok(EVEValue::Float(f64::NAN)),
+            _ => ()
+        }
+        if !self.rest().starts_with('(') {
+            return Ok(EVEValue::Global(name.to_string().into()));
+        }
+        self.pos += 1;
+
+        match name {
+            "buffer" => {
+                self.skip_space();
+                let buffer = self.str()?;
+                self.expect(")")?;
+                Ok(EVEValue::Buffer(buffer.into()))
+            },
+            "SubStream" => Ok(EVEValue::SubStream(self.items(")")?.0)),
+            "ObjectEx1" | "ObjectEx2" => {
+                let header = self.value()?;
+                self.expect(",")?;
+                self.expect("[")?;
+                let (list, _) = self.items("]")?;
+                self.expect(",")?;
+                self.expect("{")?;
+                let dict = self.pairs()?.into_iter().map(|(_, key, value)| (key, value)).collect();
+                self.eat(",");
+                self.expect(")")?;
+                Ok(EVEValue::ObjectEx {
+                    kind: if name == "ObjectEx1" { ObjectExKind::Ex1 } else { ObjectExKind::Ex2 },
+                    header: Box::new(header),
+                    list,
+                    dict
+                })
+            },
+            "DBRow" => Err(ReprError::Unsupported { what: "packed rows", offset }),
+            class => {
+                // Tuple args are written out as the call's arguments
+                let args = match self.items(")")? {
+                    (items, false) if items.len() == 1 => items.into_iter().next().expect("one item"),
+                    (items, _) => EVEValue::Tuple(items)
+                };
+                Ok(EVEValue::Object { class: class.to_string().into(), args: Box::new(args) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packed_row::{DBColumn, DBRowDescriptor, DBType, DBValue, PackedRow};
+    use crate::tests::test_data;
+    use crate::decode::decode_payload;
+    use super::*;
+
+    fn call_req() -> EVEValue<'static> {
+        let mut header = BTreeMap::new();
+        header.insert(HashableEVEValue::String(b"source"[..].into()), EVEValue::Integer(1));
+        header.insert(HashableEVEValue::Unicode("name".into()), EVEValue::Unicode("machoNet".into()));
+        EVEValue::Object {
+            class: "macho.CallReq".into(),
+            args: Box::new(EVEValue::Tuple(vec![
+                EVEValue::Dict(header),
+                EVEValue::List(vec![EVEValue::Bool(true), EVEValue::None, EVEValue::BigInt(1 << 70)])
+            ]))
+        }
+    }
+
+    #[test_log::test]
+    fn test_scalars() {
+        let floats = [
+            (0.0, "0.0"), (-0.0, "-0.0"), (1.5, "1.5"), (0.1, "0.1"), (1e16, "1e+16"),
+            (1e15, "1000000000000000.0"), (123456789012345.6, "123456789012345.6"), (0.0001, "0.0001"),
+            (0.00001, "1e-05"), (2.5e-10, "2.5e-10"), (-3.25, "-3.25"), (f64::MAX, "1.7976931348623157e+308"),
+            (f64::INFINITY, "inf"), (f64::NAN, "nan")
+        ];
+        for (f, repr) in floats {
+            assert_eq!(EVEValue::Float(f).to_string(), repr);
+        }
+
+        assert_eq!(EVEValue::Integer(-7).to_string(), "-7");
+        assert_eq!(EVEValue::BigInt(1 << 70).to_string(), "1180591620717411303424L");
+        assert_eq!(EVEValue::String(b"it's \"x\""[..].into()).to_string(), r#"'it\'s "x"'"#);
+        assert_eq!(EVEValue::String(b"it's"[..].into()).to_string(), r#""it's""#);
+        assert_eq!(EVEValue::String(b"\x00\xff\\"[..].into()).to_string(), r"'\x00\xff\\'");
+        assert_eq!(EVEValue::Unicode("caf\u{e9} \u{4e2d} \u{1f600}".into()).to_string(), r"u'caf\xe9 \u4e2d \U0001f600'");
+        assert_eq!(EVEValue::Buffer(b"ab"[..].into()).to_string(), "buffer('ab')");
+        assert_eq!(EVEValue::Global("util.KeyVal".into()).to_string(), "util.KeyVal");
+    }
+
+    #[test_log::test]
+    fn test_containers() {
+        assert_eq!(EVEValue::Tuple(vec![]).to_string(), "()");
+        assert_eq!(EVEValue::Tuple(vec![EVEValue::Byte(1)]).to_string(), "(1,)");
+        assert_eq!(call_req().to_string(), "macho.CallReq({u'name': u'machoNet', 'source': 1}, [True, None, 1180591620717411303424L])");
+
+        let row = PackedRow::new(DBRowDescriptor::new(vec![
+            DBColumn::new("id", DBType::I4),
+            DBColumn::new("name", DBType::WString)
+        ]), vec![DBValue::I4(3), DBValue::WString("Dreae".into())]).unwrap();
+        assert_eq!(EVEValue::PackedRow(row).to_string(), "DBRow(id=3, name=u'Dreae')");
+
+        let key = HashableEVEValue::Tuple(vec![HashableEVEValue::Short(1), HashableEVEValue::None]);
+        assert_eq!(key.to_string(), "(1, None)");
+    }
+
+    #[test_log::test]
+    fn test_pretty() {
+        let value = call_req();
+        assert_eq!(PrettyPrinter::new().print(&value), value.to_string());
+        assert_eq!(PrettyPrinter::new().max_width(40).print(&value), "\
+macho.CallReq(
+    {u'name': u'machoNet', 'source': 1},
+    [
+        True,
+        None,
+        1180591620717411303424L
+    ]
+)");
+        assert_eq!(PrettyPrinter::new().max_depth(1).print(&value), "macho.CallReq({...}, [...])");
+        assert_eq!(PrettyPrinter::new().max_depth(0).print(&value), "macho.CallReq(...)");
+
+        let long = EVEValue::Tuple(vec![EVEValue::String(vec![b'a'; 50].into())]);
+        assert_eq!(PrettyPrinter::new().max_width(12).indent(2).print(&long), "(\n  'aaaaaaaa...,\n)");
+    }
+
+    #[test_log::test]
+    fn test_parse() {
+        let values = parse(r#"None True -1 2L 1.5 -inf 1e+16 'it\'s' "a'b" u'caf\xe9 \u4e2d' buffer('\x00') util.KeyVal"#).unwrap();
+        assert_eq!(values, [
+            EVEValue::None,
+            EVEValue::Bool(true),
+            EVEValue::Integer(-1),
+            EVEValue::BigInt(2),
+            EVEValue::Float(1.5),
+            EVEValue::Float(f64::NEG_INFINITY),
+            EVEValue::Float(1e16),
+            EVEValue::String(b"it's"[..].into()),
+            EVEValue::String(b"a'b"[..].into()),
+            EVEValue::Unicode("caf\u{e9} \u{4e2d}".into()),
+            EVEValue::Buffer(b"\x00"[..].into()),
+            EVEValue::Global("util.KeyVal".into())
+        ]);
+
+        assert_eq!(parse("(1) (1,) [1,] C(1) C(1,) C()").unwrap(), [
+            EVEValue::Integer(1),
+            EVEValue::Tuple(vec![EVEValue::Integer(1)]),
+            EVEValue::List(vec![EVEValue::Integer(1)]),
+            EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Integer(1)) },
+            EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Tuple(vec![EVEValue::Integer(1)])) },
+            EVEValue::Object { class: "C".into(), args: Box::new(EVEValue::Tuple(vec![])) }
+        ]);
+
+        assert_eq!(parse("[1, 2"), Err(ReprError::Expected { expected: "]", offset: 5 }));
+        assert_eq!(parse("{[]: 1}"), Err(ReprError::UnhashableKey { offset: 1 }));
+        assert_eq!(parse("'\\q'"), Err(ReprError::InvalidEscape { offset: 1 }));
+        assert_eq!(parse("12x"), Err(ReprError::InvalidNumber { offset: 0 }));
+        assert_eq!(parse("DBRow(id=1)"), Err(ReprError::Unsupported { what: "packed rows", offset: 0 }));
+    }
+
+    #[test_log::test]
+    fn test_parse_round_trip() {
+        let object_ex = EVEValue::ObjectEx {
+            kind: ObjectExKind::Ex2,
+            header: Box::new(EVEValue::Tuple(vec![EVEValue::Global("collections.defaultdict".into())])),
+            list: vec![EVEValue::Integer(1)],
+            dict: vec![(EVEValue::List(vec![]), EVEValue::SubStream(vec![EVEValue::None]))]
+        };
+        let values = [call_req(), object_ex, EVEValue::Tuple(vec![EVEValue::Tuple(vec![])])];
+        for value in values {
+            assert_eq!(parse(&value.to_string()).unwrap(), std::slice::from_ref(&value));
+            let printed = PrettyPrinter::new().max_width(10).truncate_strings(false).print(&value);
+            assert_eq!(parse(&printed).unwrap(), [value]);
+        }
+
+        // Integer widths aren't kept, but everything else is
+        for payload in [test_data::PACKET1, test_data::PACKET2, test_data::MACHONET_GETTIME] {
+            for value in decode_payload(payload).unwrap() {
+                let printed = value.to_string();
+                let parsed = parse(&printed).unwrap();
+                assert_eq!(parsed.len(), 1);
+                assert_eq!(parsed[0].to_string(), printed);
+            }
+        }
+    }
+
+    #[test_log::test]
+    fn test_print_test_data() {
+        let printer = PrettyPrinter::new().max_width(60);
+        for value in decode_payload(test_data::PACKET1).unwrap() {
+            let printed = printer.print(&value);
+            assert!(printed.lines().all(|line| line.chars().count() <= 60 + 4), "{}", printed);
+        }
+    }
+}
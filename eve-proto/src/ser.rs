@@ -0,0 +1,463 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::ser::{self, Serialize};
+
+use crate::convert::field_key;
+use crate::encode::encode_payload;
+use crate::error::ConvertError;
+use crate::types::{GLOBAL, KEYVAL, KEYVAL_CLASS, OBJECT};
+use crate::value::{EVEValue, HashableEVEValue};
+
+/// Converts `value` to a marshal value.
+///
+/// Structs and tuples become tuples, sequences lists, maps dicts and unit or
+/// `None` become None. Rust strings are sent as unicode and bytes as str.
+/// Enum variants go by name, with any contents in a dict keyed by it.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<EVEValue<'static>, ConvertError> {
+    value.serialize(ValueSerializer { structs_as_dicts: false })
+}
+
+/// Encodes `value` as a payload holding just it
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ConvertError> {
+    Ok(encode_payload(&[to_value(value)?])?)
+}
+
+impl ser::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::Message(msg.to_string())
+    }
+}
+
+fn hashable(key: EVEValue<'static>) -> Result<HashableEVEValue<'static>, ConvertError> {
+    key.try_into().map_err(|_| ConvertError::UnhashableKey)
+}
+
+fn variant_key(variant: &'static str) -> HashableEVEValue<'static> {
+    HashableEVEValue::Unicode(Cow::Borrowed(variant))
+}
+
+fn tagged(variant: &'static str, value: EVEValue<'static>) -> EVEValue<'static> {
+    EVEValue::Dict(BTreeMap::from([(variant_key(variant), value)]))
+}
+
+struct ValueSerializer {
+    // Only set for the value directly inside a `KeyVal`
+    structs_as_dicts: bool
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeStruct;
+    type SerializeStructVariant = SerializeStruct;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        Ok(i64::try_from(v).map_or(EVEValue::BigInt(v), EVEValue::Integer))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        match i128::try_from(v) {
+            Ok(v) => self.serialize_i128(v),
+            Err(_) => Err(ConvertError::Message(format!("{} does not fit a VarInteger", v)))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Unicode(Cow::Owned(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Unicode(Cow::Owned(v.to_owned())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::String(Cow::Owned(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::None)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Unicode(Cow::Borrowed(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        match name {
+            GLOBAL => match to_value(value)? {
+                EVEValue::Unicode(name) => Ok(EVEValue::Global(name)),
+                _ => Err(ConvertError::Message("global name is not a string".to_string()))
+            },
+            KEYVAL => Ok(EVEValue::Object {
+                class: Cow::Borrowed(KEYVAL_CLASS),
+                args: Box::new(value.serialize(ValueSerializer { structs_as_dicts: true })?)
+            }),
+            _ => value.serialize(self)
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(tagged(variant, to_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SerializeVec::new(len.unwrap_or(0), Form::List))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(SerializeVec::new(len, Form::Tuple))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        match name {
+            OBJECT => Ok(SerializeVec::new(len, Form::Object)),
+            _ => Ok(SerializeVec::new(len, Form::Tuple))
+        }
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeVec::new(len, Form::Variant(variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SerializeMap { map: BTreeMap::new(), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(SerializeStruct::new(len, self.structs_as_dicts, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeStruct::new(len, false, Some(variant)))
+    }
+}
+
+enum Form {
+    Tuple,
+    List,
+    // Class name followed by the args
+    Object,
+    Variant(&'static str)
+}
+
+struct SerializeVec {
+    vals: Vec<EVEValue<'static>>,
+    form: Form
+}
+
+impl SerializeVec {
+    fn new(len: usize, form: Form) -> Self {
+        Self { vals: Vec::with_capacity(len), form }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.vals.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<EVEValue<'static>, ConvertError> {
+        match self.form {
+            Form::Tuple => Ok(EVEValue::Tuple(self.vals)),
+            Form::List => Ok(EVEValue::List(self.vals)),
+            Form::Variant(variant) => Ok(tagged(variant, EVEValue::Tuple(self.vals))),
+            Form::Object => {
+                let mut vals = self.vals.into_iter();
+                match (vals.next(), vals.next()) {
+                    (Some(EVEValue::Unicode(class)), Some(args)) => Ok(EVEValue::Object { class, args: Box::new(args) }),
+                    _ => Err(ConvertError::Message("object class is not a string".to_string()))
+                }
+            }
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+struct SerializeMap {
+    map: BTreeMap<HashableEVEValue<'static>, EVEValue<'static>>,
+    key: Option<HashableEVEValue<'static>>
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(hashable(to_value(key)?)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.map.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(EVEValue::Dict(self.map))
+    }
+}
+
+struct SerializeStruct {
+    fields: Vec<(&'static str, EVEValue<'static>)>,
+    as_dict: bool,
+    variant: Option<&'static str>
+}
+
+impl SerializeStruct {
+    fn new(len: usize, as_dict: bool, variant: Option<&'static str>) -> Self {
+        Self { fields: Vec::with_capacity(len), as_dict, variant }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), ConvertError> {
+        self.fields.push((key, to_value(value)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<EVEValue<'static>, ConvertError> {
+        let value = if self.as_dict {
+            EVEValue::Dict(self.fields.into_iter().map(|(key, value)| (field_key(key), value)).collect())
+        } else {
+            EVEValue::Tuple(self.fields.into_iter().map(|(_, value)| value).collect())
+        };
+
+        match self.variant {
+            Some(variant) => Ok(tagged(variant, value)),
+            None => Ok(value)
+        }
+    }
+}
+
+impl ser::SerializeStruct for SerializeStruct {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeStruct {
+    type Ok = EVEValue<'static>;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Serialize;
+
+    use crate::types::{Global, KeyVal, Object};
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Call<'a> {
+        method: &'a str,
+        args: (i32, Option<u64>),
+        kwargs: HashMap<String, bool>
+    }
+
+    #[derive(Serialize)]
+    enum Reply {
+        Ok,
+        Error(String)
+    }
+
+    #[test_log::test]
+    fn test_to_value() {
+        let call = Call {
+            method: "GetTime",
+            args: (-3, None),
+            kwargs: HashMap::from([("machoVersion".to_string(), true)])
+        };
+
+        let mut kwargs = BTreeMap::new();
+        kwargs.insert("machoVersion".into(), EVEValue::Bool(true));
+        assert_eq!(to_value(&call).unwrap(), EVEValue::Tuple(vec![
+            "GetTime".into(),
+            EVEValue::Tuple(vec![EVEValue::Integer(-3), EVEValue::None]),
+            EVEValue::Dict(kwargs)
+        ]));
+
+        assert_eq!(to_value(&vec![1u64 << 63]).unwrap(), EVEValue::List(vec![EVEValue::BigInt(1 << 63)]));
+        assert_eq!(to_value(&Reply::Ok).unwrap(), "Ok".into());
+        assert_eq!(
+            to_value(&Reply::Error("nope".to_string())).unwrap(),
+            EVEValue::Dict(BTreeMap::from([("Error".into(), "nope".into())]))
+        );
+    }
+
+    #[test_log::test]
+    fn test_hooks() {
+        #[derive(Serialize)]
+        struct Location {
+            x: f64,
+            solar_system: i32
+        }
+
+        let keyval = to_value(&KeyVal(Location { x: 1.5, solar_system: 30000142 })).unwrap();
+        let mut attrs = BTreeMap::new();
+        attrs.insert(b"x"[..].into(), EVEValue::Float(1.5));
+        attrs.insert(b"solar_system"[..].into(), EVEValue::Integer(30000142));
+        assert_eq!(keyval, EVEValue::Object {
+            class: "util.KeyVal".into(),
+            args: Box::new(EVEValue::Dict(attrs))
+        });
+
+        let object = to_value(&Object { class: "macho.MachoAddress".to_string(), args: (Global("eve.Node".to_string()), 7) }).unwrap();
+        assert_eq!(object, EVEValue::Object {
+            class: "macho.MachoAddress".into(),
+            args: Box::new(EVEValue::Tuple(vec![EVEValue::Global("eve.Node".into()), EVEValue::Integer(7)]))
+        });
+    }
+
+    #[test_log::test]
+    fn test_unhashable_key() {
+        let map = HashMap::from([(vec![1], 2)]);
+        assert_eq!(to_value(&map).unwrap_err(), ConvertError::UnhashableKey);
+    }
+}
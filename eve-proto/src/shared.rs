@@ -0,0 +1,117 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use yoke::{Yoke, Yokeable};
+
+use crate::decode::{decode_payload_with_limits, DecodeLimits};
+use crate::error::Error;
+use crate::value::EVEValue;
+
+/// Values decoded from a reference counted payload, such as a frame from
+/// `FrameCodec`.
+///
+/// Strings, buffers and names borrow straight from the payload like they do
+/// with `decode_payload`, but the payload is kept alive alongside them, so
+/// the whole packet can be owned, cloned and sent between tasks without
+/// copying any of it.
+#[derive(Clone)]
+pub struct SharedPayload {
+    values: Yoke<Values<'static>, Arc<Bytes>>
+}
+
+#[derive(Clone, Yokeable)]
+struct Values<'a>(Vec<EVEValue<'a>>);
+
+/// Decodes a payload within the default `DecodeLimits`, sharing it with the
+/// values instead of copying from it
+pub fn decode_shared(payload: Bytes) -> Result<SharedPayload, Error> {
+    decode_shared_with_limits(payload, DecodeLimits::default())
+}
+
+pub fn decode_shared_with_limits(payload: Bytes, limits: DecodeLimits) -> Result<SharedPayload, Error> {
+    let values = Yoke::try_attach_to_cart(Arc::new(payload), |payload: &Bytes| {
+        decode_payload_with_limits(payload, limits).map(Values)
+    })?;
+    Ok(SharedPayload { values })
+}
+
+impl SharedPayload {
+    pub fn values(&self) -> &[EVEValue<'_>] {
+        &self.values.get().0
+    }
+
+    /// The payload the values were decoded from, length prefix included
+    pub fn payload(&self) -> &Bytes {
+        self.values.backing_cart()
+    }
+
+    /// A reference counted handle on a string or buffer from `values`,
+    /// which outlives the `SharedPayload`. Anything not sliced from the
+    /// payload, like the contents of a compressed stream, is copied.
+    pub fn bytes_of(&self, data: &[u8]) -> Bytes {
+        let payload = self.payload();
+        let range = payload.as_ptr_range();
+        if range.start <= data.as_ptr() && data.as_ptr_range().end <= range.end {
+            payload.slice_ref(data)
+        } else {
+            Bytes::copy_from_slice(data)
+        }
+    }
+
+    /// Copies the values out, leaving the payload behind
+    pub fn into_owned(self) -> Vec<EVEValue<'static>> {
+        self.values().iter().cloned().map(EVEValue::into_owned).collect()
+    }
+}
+
+impl fmt::Debug for SharedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedPayload").field("values", &self.values()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_payload;
+    use crate::tests::test_data;
+    use super::*;
+
+    fn string_of<'v>(value: &'v EVEValue) -> &'v [u8] {
+        match value {
+            EVEValue::String(s) => s,
+            value => panic!("expected a str, got {}", value)
+        }
+    }
+
+    #[test_log::test]
+    fn test_decode_shared() {
+        let shared = decode_shared(Bytes::from_static(test_data::PACKET1)).unwrap();
+        assert_eq!(shared.values(), decode_payload(test_data::PACKET1).unwrap());
+
+        // The node name is a slice of the payload, not a copy of it
+        let EVEValue::Tuple(items) = &shared.values()[0] else { panic!("expected a tuple") };
+        let name = string_of(&items[5]);
+        assert_eq!(name, b"EVE-EVE-TRANQUILITY@ccp");
+        assert!(shared.payload().as_ptr_range().contains(&name.as_ptr()));
+
+        let name = shared.bytes_of(name);
+        assert_eq!(name.as_ptr(), string_of(&items[5]).as_ptr());
+        assert_eq!(shared.bytes_of(b"copied"), &b"copied"[..]);
+
+        drop(shared);
+        assert_eq!(name, &b"EVE-EVE-TRANQUILITY@ccp"[..]);
+    }
+
+    #[test_log::test]
+    fn test_send() {
+        let payload = Bytes::copy_from_slice(test_data::MACHONET_GETTIME);
+        let shared = decode_shared(payload).unwrap();
+        let copy = shared.clone();
+        drop(shared);
+
+        let values = std::thread::spawn(move || copy.into_owned()).join().unwrap();
+        assert_eq!(values, decode_payload(test_data::MACHONET_GETTIME).unwrap());
+        assert!(decode_shared(Bytes::from_static(&test_data::PACKET2[..20])).is_err());
+    }
+}
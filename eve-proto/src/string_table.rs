@@ -1,4 +1,4 @@
-pub static DEFAULT_STRINGS: [&'static str; 195] = [
+pub static DEFAULT_STRINGS: [&str; 195] = [
     "*corpid",
     "*locationid",
     "age",
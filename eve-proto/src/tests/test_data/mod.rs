@@ -1,3 +1,28 @@
-pub static PACKET1: &'static [u8] = include_bytes!("packet1.bin");
-pub static PACKET2: &'static [u8] = include_bytes!("packet2.bin");
-pub static MACHONET_GETTIME: &'static [u8] = include_bytes!("machoNet.GetTime.bin");
\ No newline at end of file
+pub static PACKET1: &[u8] = include_bytes!("packet1.bin");
+pub static PACKET2: &[u8] = include_bytes!("packet2.bin");
+pub static MACHONET_GETTIME: &[u8] = include_bytes!("machoNet.GetTime.bin");
+#[cfg(feature = "json")]
+pub static MACHONET_GETTIME_JSON: &str = include_str!("machoNet.GetTime.json");
+
+/// Puts the length prefix in front of a stream
+pub fn with_length(stream: &[u8]) -> Vec<u8> {
+    let mut payload = (stream.len() as u32).to_le_bytes().to_vec();
+    payload.extend_from_slice(stream);
+    payload
+}
+
+/// A payload of one stream holding the values in `body` and no saved objects
+pub fn with_header(body: &[u8]) -> Vec<u8> {
+    with_save_map(body, &[])
+}
+
+/// A payload of one stream holding the values in `body`, then `save_map`
+pub fn with_save_map(body: &[u8], save_map: &[u32]) -> Vec<u8> {
+    let mut stream = vec![0x7e];
+    stream.extend_from_slice(&(save_map.len() as u32).to_le_bytes());
+    stream.extend_from_slice(body);
+    for slot in save_map {
+        stream.extend_from_slice(&slot.to_le_bytes());
+    }
+    with_length(&stream)
+}
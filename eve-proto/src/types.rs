@@ -0,0 +1,112 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeTupleStruct, Serializer};
+
+// Names the serializer and deserializer in `ser` and `de` look out for
+pub(crate) const GLOBAL: &str = "$eve_proto::Global";
+pub(crate) const OBJECT: &str = "$eve_proto::Object";
+pub(crate) const KEYVAL: &str = "$eve_proto::KeyVal";
+
+/// Class of the objects `KeyVal` is sent as
+pub const KEYVAL_CLASS: &str = "util.KeyVal";
+
+/// Reference to a global by name, such as a class or function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global(pub String);
+
+/// An instance of `class` built from `args`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object<T> {
+    pub class: String,
+    pub args: T
+}
+
+/// A `util.KeyVal`, sent as an object whose attributes are the fields of
+/// `T` rather than as a tuple of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyVal<T>(pub T);
+
+impl Serialize for Global {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(GLOBAL, &self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Global {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct GlobalVisitor;
+
+        impl<'de> Visitor<'de> for GlobalVisitor {
+            type Value = Global;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a global")
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Global, D::Error> {
+                String::deserialize(deserializer).map(Global)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(GLOBAL, GlobalVisitor)
+    }
+}
+
+impl<T: Serialize> Serialize for Object<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut object = serializer.serialize_tuple_struct(OBJECT, 2)?;
+        object.serialize_field(&self.class)?;
+        object.serialize_field(&self.args)?;
+        object.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Object<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for ObjectVisitor<T> {
+            type Value = Object<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an object")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Object<T>, A::Error> {
+                let class = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let args = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(Object { class, args })
+            }
+        }
+
+        deserializer.deserialize_tuple_struct(OBJECT, 2, ObjectVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize> Serialize for KeyVal<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(KEYVAL, &self.0)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for KeyVal<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyValVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for KeyValVisitor<T> {
+            type Value = KeyVal<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a {}", KEYVAL_CLASS)
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<KeyVal<T>, D::Error> {
+                T::deserialize(deserializer).map(KeyVal)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(KEYVAL, KeyValVisitor(PhantomData))
+    }
+}
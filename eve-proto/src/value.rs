@@ -1,45 +1,172 @@
-use std::{ffi::OsStr, cmp::Ordering};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
-#[derive(Debug)]
+use crate::packed_row::PackedRow;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EVEValue<'a> {
     Tuple(Vec<EVEValue<'a>>),
+    List(Vec<EVEValue<'a>>),
     Dict(BTreeMap<HashableEVEValue<'a>, EVEValue<'a>>),
-    Object(Vec<EVEValue<'a>>),
+    /// An instance of `class` built from `args`
+    Object {
+        class: Cow<'a, str>,
+        args: Box<EVEValue<'a>>
+    },
     SubStream(Vec<EVEValue<'a>>),
+    /// A value, usually a sub stream, with the Adler-32 checksum it was
+    /// received with. The encoder always computes a fresh checksum.
+    ChecksummedStream {
+        checksum: u32,
+        value: Box<EVEValue<'a>>
+    },
+    Bool(bool),
     Byte(u8),
     Short(i16),
     Integer(i64),
     BigInt(i128),
     Float(f64),
-    String(&'a OsStr),
-    OwnedString(String),
+    /// Python `str`, a string of bytes with no particular encoding
+    String(Cow<'a, [u8]>),
+    /// Python `unicode`
+    Unicode(Cow<'a, str>),
+    /// Python `buffer`
+    Buffer(Cow<'a, [u8]>),
+    /// An object pickled through `__reduce__`, the header holds the callable
+    /// and its arguments, followed by items to append and keys to set on it
+    ObjectEx {
+        kind: ObjectExKind,
+        header: Box<EVEValue<'a>>,
+        list: Vec<EVEValue<'a>>,
+        dict: Vec<(EVEValue<'a>, EVEValue<'a>)>
+    },
+    /// Reference to a global by name, such as a class or function
+    Global(Cow<'a, str>),
+    /// A value sent as a cPickle blob, see `pickle`
+    Pickled(Box<EVEValue<'a>>),
+    /// A database row, see `packed_row`
+    PackedRow(PackedRow<'a>),
     None
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectExKind {
+    /// Header is the result of `__reduce__`
+    Ex1,
+    /// Header is the result of `__reduce_ex__(2)`, its callable is the class itself
+    Ex2
+}
+
+#[derive(Debug, Clone)]
 pub enum HashableEVEValue<'a> {
+    Tuple(Vec<HashableEVEValue<'a>>),
+    Bool(bool),
     Byte(u8),
     Short(i16),
     Integer(i64),
+    BigInt(i128),
     Float(f64),
-    String(&'a OsStr),
-    OwnedString(String),
+    String(Cow<'a, [u8]>),
+    Unicode(Cow<'a, str>),
+    Buffer(Cow<'a, [u8]>),
     None
 }
 
+impl EVEValue<'_> {
+    /// Name of the Python type the value stands for
+    pub fn type_name(&self) -> &'static str {
+        use self::EVEValue::*;
+        match self {
+            Tuple(_) => "tuple",
+            List(_) => "list",
+            Dict(_) => "dict",
+            Object { .. } | ObjectEx { .. } => "object",
+            SubStream(_) => "substream",
+            ChecksummedStream { .. } => "checksummed stream",
+            Bool(_) => "bool",
+            Byte(_) | Short(_) | Integer(_) => "int",
+            BigInt(_) => "long",
+            Float(_) => "float",
+            String(_) => "str",
+            Unicode(_) => "unicode",
+            Buffer(_) => "buffer",
+            Global(_) => "global",
+            Pickled(_) => "pickle",
+            PackedRow(_) => "PackedRow",
+            None => "NoneType"
+        }
+    }
+
+    /// Copies any data borrowed from the input, so the value can outlive
+    /// the buffer it was decoded from or be sent to another task
+    pub fn into_owned(self) -> EVEValue<'static> {
+        use self::EVEValue::*;
+        match self {
+            Tuple(vals) => Tuple(vals.into_iter().map(EVEValue::into_owned).collect()),
+            List(vals) => List(vals.into_iter().map(EVEValue::into_owned).collect()),
+            Dict(map) => Dict(map.into_iter().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()),
+            Object { class, args } => Object { class: Cow::Owned(class.into_owned()), args: Box::new(args.into_owned()) },
+            SubStream(vals) => SubStream(vals.into_iter().map(EVEValue::into_owned).collect()),
+            ChecksummedStream { checksum, value } => ChecksummedStream { checksum, value: Box::new(value.into_owned()) },
+            Bool(b) => Bool(b),
+            Byte(i) => Byte(i),
+            Short(i) => Short(i),
+            Integer(i) => Integer(i),
+            BigInt(i) => BigInt(i),
+            Float(f) => Float(f),
+            String(s) => String(Cow::Owned(s.into_owned())),
+            Unicode(s) => Unicode(Cow::Owned(s.into_owned())),
+            Buffer(b) => Buffer(Cow::Owned(b.into_owned())),
+            ObjectEx { kind, header, list, dict } => ObjectEx {
+                kind,
+                header: Box::new(header.into_owned()),
+                list: list.into_iter().map(EVEValue::into_owned).collect(),
+                dict: dict.into_iter().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+            },
+            Global(name) => Global(Cow::Owned(name.into_owned())),
+            Pickled(value) => Pickled(Box::new(value.into_owned())),
+            PackedRow(row) => PackedRow(row.into_owned()),
+            None => None
+        }
+    }
+}
+
+impl HashableEVEValue<'_> {
+    pub fn into_owned(self) -> HashableEVEValue<'static> {
+        use self::HashableEVEValue::*;
+        match self {
+            Tuple(vals) => Tuple(vals.into_iter().map(HashableEVEValue::into_owned).collect()),
+            Bool(b) => Bool(b),
+            Byte(i) => Byte(i),
+            Short(i) => Short(i),
+            Integer(i) => Integer(i),
+            BigInt(i) => BigInt(i),
+            Float(f) => Float(f),
+            String(s) => String(Cow::Owned(s.into_owned())),
+            Unicode(s) => Unicode(Cow::Owned(s.into_owned())),
+            Buffer(b) => Buffer(Cow::Owned(b.into_owned())),
+            None => None
+        }
+    }
+}
+
 impl <'a> TryInto<HashableEVEValue<'a>> for EVEValue<'a> {
     type Error = ();
     fn try_into(self) -> Result<HashableEVEValue<'a>, Self::Error> {
         use self::EVEValue::*;
         match self {
             None => Ok(HashableEVEValue::None),
+            Bool(b) => Ok(b.into()),
             Byte(i) => Ok(i.into()),
             Short(i) => Ok(i.into()),
             Integer(i) => Ok(i.into()),
+            BigInt(i) => Ok(HashableEVEValue::BigInt(i)),
             Float(i) => Ok(i.into()),
-            String(s) => Ok(s.into()),
-            OwnedString(s) => Ok(s.into()),
+            String(s) => Ok(HashableEVEValue::String(s)),
+            Unicode(s) => Ok(HashableEVEValue::Unicode(s)),
+            Buffer(b) => Ok(HashableEVEValue::Buffer(b)),
+            Tuple(vals) => Ok(HashableEVEValue::Tuple(vals.into_iter().map(|v| v.try_into()).collect::<Result<_, _>>()?)),
             _ => Err(())
         }
     }
@@ -59,6 +186,60 @@ impl PartialOrd for HashableEVEValue<'_> {
     }
 }
 
+impl HashableEVEValue<'_> {
+    /// Where the type sorts against values it can't be compared to. Python 2
+    /// puts None first and numbers before other types, which then go by type
+    /// name. Numbers compare by value and str and unicode compare as text,
+    /// so they share a place.
+    fn type_rank(&self) -> u8 {
+        use self::HashableEVEValue::*;
+        match self {
+            None => 0,
+            Bool(_) | Byte(_) | Short(_) | Integer(_) | BigInt(_) | Float(_) => 1,
+            Buffer(_) => 2,
+            String(_) | Unicode(_) => 3,
+            Tuple(_) => 4
+        }
+    }
+
+    fn as_int(&self) -> Option<i128> {
+        use self::HashableEVEValue::*;
+        match *self {
+            Bool(i) => Some(i as i128),
+            Byte(i) => Some(i as i128),
+            Short(i) => Some(i as i128),
+            Integer(i) => Some(i as i128),
+            BigInt(i) => Some(i),
+            _ => Option::None
+        }
+    }
+}
+
+/// Floats in a total order, with NaN equal to itself and after every other
+/// number
+fn cmp_floats(i: f64, j: f64) -> Ordering {
+    match (i.is_nan(), j.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => i.partial_cmp(&j).expect("neither is NaN")
+    }
+}
+
+/// Compares exactly, like Python 2, rather than rounding the int to a float
+fn cmp_int_float(i: i128, f: f64) -> Ordering {
+    // 2^127, the first float past i128::MAX
+    const LIMIT: f64 = 170141183460469231731687303715884105728.0;
+    if f.is_nan() || f >= LIMIT {
+        return Ordering::Less;
+    }
+    if f < -LIMIT {
+        return Ordering::Greater;
+    }
+    let whole = f.trunc();
+    i.cmp(&(whole as i128)).then_with(|| 0.0.partial_cmp(&(f - whole)).expect("neither is NaN"))
+}
+
 // This implementaiton more or less comes from serde-pickle here:
 // https://github.com/birkenfeld/serde-pickle/blob/5932524/src/value.rs#L219
 // It's purpose is to replicate Python's ordering for values
@@ -66,57 +247,34 @@ impl PartialOrd for HashableEVEValue<'_> {
 impl Ord for HashableEVEValue<'_> {
     fn cmp(&self, other: &Self) -> Ordering {
         use self::HashableEVEValue::*;
-        match *self {
-            None => match *other {
-                None => Ordering::Equal,
-                _ => Ordering::Less
-            },
-            Short(i) => match *other {
-                None => Ordering::Greater,
-                Byte(j) => i.cmp(&(j as i16)),
-                Short(j) => i.cmp(&j),
-                Integer(j) => (i as i64).cmp(&j),
-                _ => Ordering::Less
-            },
-            Byte(i) => match *other {
-                None => Ordering::Greater,
-                Byte(j) => i.cmp(&j),
-                Short(j) => (i as i16).cmp(&j),
-                Integer(j) => (i as i64).cmp(&j),
-                _ => Ordering::Less
-            },
-            Integer(i) => match *other {
-                None => Ordering::Greater,
-                Byte(j) => i.cmp(&(j as i64)),
-                Short(j) => i.cmp(&(j as i64)),
-                Integer(j) => i.cmp(&j),
-                _ => Ordering::Less
-            },
-            Float(i) => match *other {
-                String(_) => Ordering::Less,
-                OwnedString(_) => Ordering::Less,
-                Float(j) => {
-                    match i.partial_cmp(&j) {
-                        Some(o) => o,
-                        _ => Ordering::Less
-                    }
-                },
-                _ => Ordering::Greater
-            }
-            String(s) => match *other {
-                String(s2) => s.cmp(s2),
-                OwnedString(ref s2) => s.cmp(s2.as_ref()),
-                _ => Ordering::Greater
-            },
-            OwnedString(ref s) => match *other {
-                String(s2) => AsRef::<OsStr>::as_ref(s).cmp(s2),
-                OwnedString(ref s2) => s.cmp(s2),
-                _ => Ordering::Greater
-            }
+        match (self.as_int(), other.as_int(), self, other) {
+            (Some(i), Some(j), _, _) => return i.cmp(&j),
+            (Some(i), _, _, Float(f)) => return cmp_int_float(i, *f),
+            (_, Some(j), Float(f), _) => return cmp_int_float(j, *f).reverse(),
+            _ => {}
+        }
+
+        match (self, other) {
+            (Float(i), Float(j)) => cmp_floats(*i, *j),
+            // Python 2 compares str and unicode as text, which is the same
+            // as comparing bytes for the ASCII strings used as keys
+            (String(s), String(s2)) => s.cmp(s2),
+            (String(s), Unicode(s2)) => s.as_ref().cmp(s2.as_bytes()),
+            (Unicode(s), String(s2)) => s.as_bytes().cmp(s2.as_ref()),
+            (Unicode(s), Unicode(s2)) => s.cmp(s2),
+            (Buffer(b), Buffer(b2)) => b.cmp(b2),
+            (Tuple(t), Tuple(t2)) => t.cmp(t2),
+            _ => self.type_rank().cmp(&other.type_rank())
         }
     }
 }
 
+impl From<bool> for EVEValue<'_> {
+    fn from(other: bool) -> Self {
+        Self::Bool(other)
+    }
+}
+
 impl From<u8> for EVEValue<'_> {
     fn from(other: u8) -> Self {
         Self::Byte(other)
@@ -153,15 +311,52 @@ impl From<f64> for EVEValue<'_> {
     }
 }
 
-impl <'a> From<&'a OsStr> for EVEValue<'a> {
-    fn from(other: &'a OsStr) -> Self {
-        Self::String(other)
+impl <'a> From<&'a [u8]> for EVEValue<'a> {
+    fn from(other: &'a [u8]) -> Self {
+        Self::String(Cow::Borrowed(other))
+    }
+}
+
+impl From<Vec<u8>> for EVEValue<'_> {
+    fn from(other: Vec<u8>) -> Self {
+        Self::String(Cow::Owned(other))
+    }
+}
+
+impl <'a> From<&'a str> for EVEValue<'a> {
+    fn from(other: &'a str) -> Self {
+        Self::Unicode(Cow::Borrowed(other))
     }
 }
 
 impl From<String> for EVEValue<'_> {
     fn from(other: String) -> Self {
-        Self::OwnedString(other)
+        Self::Unicode(Cow::Owned(other))
+    }
+}
+
+impl <'a> From<HashableEVEValue<'a>> for EVEValue<'a> {
+    fn from(other: HashableEVEValue<'a>) -> Self {
+        use self::HashableEVEValue::*;
+        match other {
+            Tuple(vals) => Self::Tuple(vals.into_iter().map(EVEValue::from).collect()),
+            Bool(b) => Self::Bool(b),
+            Byte(i) => Self::Byte(i),
+            Short(i) => Self::Short(i),
+            Integer(i) => Self::Integer(i),
+            BigInt(i) => Self::BigInt(i),
+            Float(f) => Self::Float(f),
+            String(s) => Self::String(s),
+            Unicode(s) => Self::Unicode(s),
+            Buffer(b) => Self::Buffer(b),
+            None => Self::None
+        }
+    }
+}
+
+impl From<bool> for HashableEVEValue<'_> {
+    fn from(other: bool) -> Self {
+        Self::Bool(other)
     }
 }
 
@@ -201,14 +396,114 @@ impl From<f64> for HashableEVEValue<'_> {
     }
 }
 
-impl <'a> From<&'a OsStr> for HashableEVEValue<'a> {
-    fn from(other: &'a OsStr) -> Self {
-        Self::String(other)
+impl <'a> From<&'a [u8]> for HashableEVEValue<'a> {
+    fn from(other: &'a [u8]) -> Self {
+        Self::String(Cow::Borrowed(other))
+    }
+}
+
+impl From<Vec<u8>> for HashableEVEValue<'_> {
+    fn from(other: Vec<u8>) -> Self {
+        Self::String(Cow::Owned(other))
+    }
+}
+
+impl <'a> From<&'a str> for HashableEVEValue<'a> {
+    fn from(other: &'a str) -> Self {
+        Self::Unicode(Cow::Borrowed(other))
     }
 }
 
 impl From<String> for HashableEVEValue<'_> {
     fn from(other: String) -> Self {
-        Self::OwnedString(other)
+        Self::Unicode(Cow::Owned(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+
+    #[test]
+    fn test_bool_ordering() {
+        let f: HashableEVEValue = false.into();
+        let t: HashableEVEValue = true.into();
+        assert!(HashableEVEValue::None < f);
+        assert!(f < t);
+        assert_eq!(f, HashableEVEValue::Integer(0));
+        assert_eq!(t, HashableEVEValue::Byte(1));
+        assert!(t < HashableEVEValue::Short(2));
+        assert!(HashableEVEValue::Integer(-1) < f);
+        assert!(t > HashableEVEValue::Float(0.5));
+        assert_eq!(HashableEVEValue::Integer(1), HashableEVEValue::Float(1.0));
+
+        // Like Python, True, 1 and 1.0 are the same key
+        let mut map = BTreeMap::new();
+        map.insert(t, EVEValue::None);
+        map.insert(HashableEVEValue::Integer(1), EVEValue::Bool(false));
+        map.insert(HashableEVEValue::Float(1.0), EVEValue::Bool(true));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let data = b"key".to_vec();
+        let key: HashableEVEValue = data.as_slice().into();
+        let value = EVEValue::Tuple(vec![
+            EVEValue::Dict([(key.clone(), EVEValue::Unicode(Cow::Borrowed("value")))].into_iter().collect()),
+            data.as_slice().into()
+        ]);
+
+        let owned = value.clone().into_owned();
+        let owned_key = key.into_owned();
+        drop(data);
+        assert!(matches!(&owned_key, HashableEVEValue::String(Cow::Owned(_))));
+        match owned {
+            EVEValue::Tuple(vals) => assert!(matches!(&vals[1], EVEValue::String(Cow::Owned(s)) if s == b"key")),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_cross_type_ordering() {
+        let big = HashableEVEValue::BigInt(1 << 70);
+        let buffer = HashableEVEValue::Buffer(Cow::Borrowed(b"zz"));
+        let tuple = |vals: Vec<HashableEVEValue<'static>>| HashableEVEValue::Tuple(vals);
+
+        assert_eq!(HashableEVEValue::BigInt(5), HashableEVEValue::Integer(5));
+        assert!(HashableEVEValue::Integer(i64::MAX) < big);
+        assert!(HashableEVEValue::BigInt(-(1 << 70)) < HashableEVEValue::Bool(false));
+
+        // None, numbers by value, then buffer < str < tuple by type name
+        let ordered = [
+            HashableEVEValue::None,
+            HashableEVEValue::Float(f64::NEG_INFINITY),
+            HashableEVEValue::BigInt(-(1 << 70)),
+            HashableEVEValue::Float(-1.5),
+            HashableEVEValue::Integer(-1),
+            HashableEVEValue::Float(-0.5),
+            true.into(),
+            HashableEVEValue::Float(1.5),
+            big,
+            HashableEVEValue::Float(1e300),
+            HashableEVEValue::Float(f64::NAN),
+            buffer,
+            "a".into(),
+            b"b"[..].into(),
+            tuple(vec![]),
+            tuple(vec![1.into(), "a".into()]),
+            tuple(vec![1.into(), "b".into()]),
+            tuple(vec![2.into()])
+        ];
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} < {:?}", pair[0], pair[1]);
+        }
+
+        assert_eq!(tuple(vec![true.into(), 1.into()]), tuple(vec![1.into(), HashableEVEValue::Byte(1)]));
+        assert_eq!(HashableEVEValue::BigInt(1 << 70), HashableEVEValue::Float((1u128 << 70) as f64));
+        assert!(HashableEVEValue::BigInt((1 << 70) + 1) > HashableEVEValue::Float((1u128 << 70) as f64));
+        assert_eq!(HashableEVEValue::Float(f64::NAN), HashableEVEValue::Float(f64::NAN));
+        assert!(HashableEVEValue::Float(f64::NAN) > HashableEVEValue::BigInt(i128::MAX));
     }
 }
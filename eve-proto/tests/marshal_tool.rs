@@ -0,0 +1,93 @@
+//! Runs the `marshal-tool` binary against the test data payloads
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use eve_proto::decode::decode_payload;
+
+fn test_data(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "src", "tests", "test_data", name].iter().collect()
+}
+
+fn marshal_tool(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_marshal-tool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test_log::test]
+fn test_dump() {
+    let path = test_data("machoNet.GetTime.bin");
+    let output = marshal_tool(&["dump", path.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("macho.CallReq(\n    6,\n"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("SubStream((1, 'GetTime', (), {'machoVersion': 1}))"));
+
+    let output = marshal_tool(&["dump", "--format", "json", path.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), std::fs::read_to_string(test_data("machoNet.GetTime.json")).unwrap());
+}
+
+#[test_log::test]
+fn test_disasm() {
+    let payload = std::fs::read(test_data("machoNet.GetTime.bin")).unwrap();
+    let output = marshal_tool(&["disasm"], &payload);
+    assert!(output.status.success());
+    let lines: Vec<_> = stdout(&output).lines().take(3).collect();
+    assert_eq!(lines, [
+        "00000000  4d 00 00 00                 length 77",
+        "00000004  7e 00 00 00 00              stream, 0 saved",
+        "00000009  17                            Object"
+    ]);
+}
+
+#[test_log::test]
+fn test_encode() {
+    let payload = std::fs::read(test_data("machoNet.GetTime.bin")).unwrap();
+    for format in ["text", "json"] {
+        let dumped = marshal_tool(&["dump", "--format", format, "-"], &payload);
+        assert!(dumped.status.success());
+
+        let encoded = marshal_tool(&["encode", "--format", format], &dumped.stdout);
+        assert!(encoded.status.success(), "{}", String::from_utf8_lossy(&encoded.stderr));
+        // Text doesn't keep integer widths, so compare what it prints as
+        assert!(decode_payload(&encoded.stdout).is_ok());
+        let redumped = marshal_tool(&["dump", "--format", format], &encoded.stdout);
+        assert_eq!(stdout(&redumped), stdout(&dumped));
+    }
+}
+
+#[test_log::test]
+fn test_diff() {
+    let (packet1, packet2) = (test_data("packet1.bin"), test_data("packet2.bin"));
+    let output = marshal_tool(&["diff", packet1.to_str().unwrap(), packet1.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let output = marshal_tool(&["diff", packet1.to_str().unwrap(), packet2.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("~ [0][0]: 170472 != "), "{}", stdout(&output));
+}
+
+#[test_log::test]
+fn test_bad_input() {
+    let output = marshal_tool(&["dump"], b"not a payload");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("marshal-tool: "));
+
+    let output = marshal_tool(&["encode"], b"(1, 2");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
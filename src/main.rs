@@ -6,7 +6,7 @@ use crate::net::EVEServer;
 
 mod net;
 
-const VERSION: &'static str = "v0.0.1";
+const VERSION: &str = "v0.0.1";
 
 fn setup_logger() -> Result<(), fern::InitError> {
     let colors = ColoredLevelConfig::default()
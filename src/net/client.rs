@@ -1,24 +1,35 @@
-use tokio::{sync::mpsc::{Sender, Receiver}, spawn};
+use tokio::spawn;
 use super::socket::EVEProtoSocket;
 
 pub struct EVEClient {
-    socket: EVEProtoSocket,
-    server_commands: Receiver<u8>,
-    client_commands: Sender<u8>
+    socket: EVEProtoSocket
 }
 
 impl EVEClient {
-    pub fn new(socket: EVEProtoSocket, command_channels: (Receiver<u8>, Sender<u8>)) -> Self {
-        let (server_commands, client_commands) = command_channels;
+    pub fn new(socket: EVEProtoSocket) -> Self {
         Self {
-            socket,
-            server_commands,
-            client_commands
+            socket
         }
     }
 
     pub async fn run(&mut self) {
-
+        loop {
+            match self.socket.read_packet().await {
+                Ok(Some(packet)) => {
+                    for value in packet.values() {
+                        log::trace!("Got packet value {}", value);
+                    }
+                }
+                Ok(None) => {
+                    log::trace!("Client disconnected");
+                    break;
+                }
+                Err(err) => {
+                    log::error!("Reading packet failed: {}", err);
+                    break;
+                }
+            }
+        }
     }
 
     pub fn spawn(mut self) {
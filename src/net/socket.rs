@@ -1,23 +1,37 @@
-use eve_proto::value::EVEValue;
+use eve_proto::{decode_shared, FrameCodec, FrameError, SharedPayload};
+use futures::StreamExt;
 use tokio::net::TcpStream;
-use std::io::Result;
+use tokio_util::codec::Framed;
+use std::io::{Error, ErrorKind, Result};
 
 pub struct EVEProtoSocket {
-    connection: TcpStream
+    connection: Framed<TcpStream, FrameCodec>
 }
 
 impl EVEProtoSocket {
     pub fn new(connection: TcpStream) -> Self {
         Self {
-            connection
+            connection: Framed::new(connection, FrameCodec::new())
         }
     }
 
-    pub fn read_packet(&mut self) -> Result<Option<EVEValue>> {
-        unimplemented!()
+    /// Reads the values of the next packet, `None` once the client has
+    /// disconnected
+    pub async fn read_packet(&mut self) -> Result<Option<SharedPayload>> {
+        match self.connection.next().await {
+            Some(frame) => {
+                let frame = frame.map_err(into_io_error)?;
+                let packet = decode_shared(frame).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+                Ok(Some(packet))
+            },
+            None => Ok(None)
+        }
     }
+}
 
-    pub fn write_packet(&mut self) -> Result<()> {
-        unimplemented!()
+fn into_io_error(err: FrameError) -> Error {
+    match err {
+        FrameError::Io(err) => err,
+        err => Error::new(ErrorKind::InvalidData, err)
     }
-}
\ No newline at end of file
+}